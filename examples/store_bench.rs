@@ -0,0 +1,71 @@
+//! Compares the throughput of the sharded store with a store that has a single shard, which behaves like the
+//! previous global `Mutex<Store>`.
+//!
+//! Run it with `cargo run --release --example store_bench -- [threads] [operations per thread]`.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use codecrafters_redis::clock::SystemClock;
+use codecrafters_redis::store::{Store, StoreValueBuilder};
+
+const KEYS: usize = 100_000;
+
+fn run(store: Arc<Store>, threads: usize, operations: usize) -> f64 {
+    let started_at = Instant::now();
+
+    let handles: Vec<_> = (0..threads)
+        .map(|thread_index| {
+            let store = store.clone();
+
+            thread::spawn(move || {
+                for operation in 0..operations {
+                    let key = format!("key:{}", (thread_index * operations + operation) % KEYS);
+
+                    // One write for every four reads, similar to a cache workload.
+                    if operation % 5 == 0 {
+                        let mut store_value_builder = StoreValueBuilder::new();
+
+                        store_value_builder.with_value("value");
+                        store.set(&key, store_value_builder.build()).unwrap();
+                    } else {
+                        store.get(&key);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    (threads * operations) as f64 / started_at.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let threads: usize = args
+        .next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |value| value.get()));
+    let operations: usize = args
+        .next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1_000_000);
+
+    println!("{} threads, {} operations per thread", threads, operations);
+
+    let single_shard = run(
+        Arc::new(Store::new(1, Arc::new(SystemClock::default()))),
+        threads,
+        operations,
+    );
+    println!("1 shard:    {:>12.0} ops/s", single_shard);
+
+    let sharded = run(Arc::new(Store::default()), threads, operations);
+    println!("sharded:    {:>12.0} ops/s", sharded);
+
+    println!("speedup:    {:>12.2}x", sharded / single_shard);
+}
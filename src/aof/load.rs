@@ -0,0 +1,224 @@
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+use crate::resp::data_types::{RespDataType, RespDecoder};
+
+use super::writer::AofError;
+
+/// Reads the commands of an AOF.
+pub struct AofLoader {
+    path: PathBuf,
+    buf: Vec<u8>,
+    position: usize,
+    // An incomplete command at the end of the file is dropped instead of failing.
+    load_truncated: bool,
+    truncated: bool,
+}
+
+impl AofLoader {
+    /// Reads the whole file. It returns None when the file does not exist.
+    pub async fn open(path: &Path) -> Result<Option<Self>, AofError> {
+        match tokio::fs::read(path).await {
+            Ok(buf) => Ok(Some(Self {
+                path: path.to_path_buf(),
+                buf,
+                position: 0,
+                load_truncated: false,
+                truncated: false,
+            })),
+            Err(err) => match err.kind() {
+                tokio::io::ErrorKind::NotFound => Ok(None),
+                _ => Err(AofError::ReadFile(err.to_string())),
+            },
+        }
+    }
+
+    /// Drops an incomplete command at the end of the file, which is what is left when the server stops in the
+    /// middle of an append. Otherwise, it is an error.
+    pub fn with_load_truncated(&mut self, load_truncated: bool) {
+        self.load_truncated = load_truncated;
+    }
+
+    /// Returns the arguments of the next command, or None at the end of the file. Arguments are bytes, since they can
+    /// be binary (the payload of RESTORE, for instance).
+    pub fn next_command(&mut self) -> Result<Option<Vec<Vec<u8>>>, AofError> {
+        if self.position == self.buf.len() || self.truncated {
+            return Ok(None);
+        }
+
+        let invalid = AofError::InvalidCommand(self.position);
+
+        let (value, length) = match RespDecoder::decode_bytes(&self.buf[self.position..]) {
+            Ok(Some(value)) => value,
+            Ok(None) if self.load_truncated => {
+                self.truncated = true;
+
+                return Ok(None);
+            }
+            Ok(None) => return Err(AofError::Truncated(self.position)),
+            Err(_) => return Err(invalid),
+        };
+
+        let args = match value {
+            RespDataType::Array(values) if !values.is_empty() => values
+                .into_iter()
+                .map(|value| match value {
+                    RespDataType::BulkString(arg) => Some(arg.into_bytes()),
+                    RespDataType::BinaryBulkString(arg) => Some(arg),
+                    _ => None,
+                })
+                .collect::<Option<Vec<Vec<u8>>>>()
+                .ok_or(invalid)?,
+            _ => return Err(invalid),
+        };
+
+        self.position += length;
+
+        Ok(Some(args))
+    }
+
+    /// Number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Whether an incomplete command was dropped at the end of the file.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Removes everything after the last command read from the file, so new commands can be appended to it.
+    pub fn truncate(&self) -> Result<(), AofError> {
+        OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .and_then(|file| {
+                file.set_len(self.position as u64)?;
+                file.sync_all()
+            })
+            .map_err(|err| AofError::WriteFile(err.to_string()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SET_A: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+
+    // Writes the file of a test, in a directory removed when the test starts.
+    fn aof_file(name: &str, content: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aof-load-{}-{}", std::process::id(), name));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("appendonly.aof"), content).unwrap();
+
+        dir.join("appendonly.aof")
+    }
+
+    fn command(args: &[&[u8]]) -> Vec<Vec<u8>> {
+        args.iter().map(|arg| arg.to_vec()).collect()
+    }
+
+    #[tokio::test]
+    async fn read_commands() {
+        let path = aof_file(
+            "read",
+            &[SET_A, b"*2\r\n$3\r\nDEL\r\n$2\r\n\x00\xff\r\n"].concat(),
+        );
+        let mut loader = AofLoader::open(&path).await.unwrap().unwrap();
+
+        assert_eq!(
+            loader.next_command().unwrap(),
+            Some(command(&[b"SET", b"a", b"1"]))
+        );
+        assert_eq!(loader.position(), SET_A.len());
+        assert_eq!(
+            loader.next_command().unwrap(),
+            Some(command(&[b"DEL", b"\x00\xff"]))
+        );
+        assert_eq!(loader.next_command().unwrap(), None);
+        assert_eq!(loader.position(), loader.len());
+        assert!(!loader.is_truncated());
+    }
+
+    #[tokio::test]
+    async fn open_a_missing_file() {
+        let path = aof_file("missing", b"").with_file_name("missing.aof");
+
+        assert!(AofLoader::open(&path).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn truncated_command_is_an_error() {
+        let path = aof_file(
+            "truncated",
+            &[SET_A, b"*3\r\n$3\r\nSET\r\n$1\r\nb"].concat(),
+        );
+        let mut loader = AofLoader::open(&path).await.unwrap().unwrap();
+
+        assert!(loader.next_command().unwrap().is_some());
+        assert!(matches!(
+            loader.next_command(),
+            Err(AofError::Truncated(position)) if position == SET_A.len()
+        ));
+    }
+
+    #[tokio::test]
+    async fn truncated_command_is_dropped_with_load_truncated() {
+        let path = aof_file(
+            "load-truncated",
+            &[SET_A, b"*3\r\n$3\r\nSET\r\n$1\r\nb"].concat(),
+        );
+        let mut loader = AofLoader::open(&path).await.unwrap().unwrap();
+
+        loader.with_load_truncated(true);
+
+        assert!(loader.next_command().unwrap().is_some());
+        assert_eq!(loader.next_command().unwrap(), None);
+        assert_eq!(loader.next_command().unwrap(), None);
+        assert!(loader.is_truncated());
+        assert_eq!(loader.position(), SET_A.len());
+
+        loader.truncate().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), SET_A);
+    }
+
+    #[tokio::test]
+    async fn invalid_command_is_an_error_with_load_truncated() {
+        for (index, content) in [
+            &b"+OK\r\n"[..],
+            b"*0\r\n",
+            b"*2\r\n$3\r\nGET\r\n:1\r\n",
+            b"*1\r\n$3\r\nGETX\r\n",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let path = aof_file(&format!("invalid-{}", index), &[SET_A, content].concat());
+            let mut loader = AofLoader::open(&path).await.unwrap().unwrap();
+
+            loader.with_load_truncated(true);
+
+            assert!(loader.next_command().unwrap().is_some());
+            assert!(
+                matches!(
+                    loader.next_command(),
+                    Err(AofError::InvalidCommand(position)) if position == SET_A.len()
+                ),
+                "{:?}",
+                content
+            );
+        }
+    }
+}
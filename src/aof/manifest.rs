@@ -0,0 +1,422 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::writer::AofError;
+
+const BASE_SUFFIX: &str = "base";
+const INCR_SUFFIX: &str = "incr";
+const RDB_FORMAT_SUFFIX: &str = ".rdb";
+const AOF_FORMAT_SUFFIX: &str = ".aof";
+const MANIFEST_SUFFIX: &str = ".manifest";
+const TEMP_PREFIX: &str = "temp-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AofFileType {
+    /// The dataset when the last rewrite started. There is at most one.
+    Base,
+    /// The commands appended after the base was created, in order.
+    Incr,
+    /// Files replaced by a rewrite, which are deleted as soon as the new manifest is written.
+    History,
+}
+
+impl FromStr for AofFileType {
+    type Err = AofError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "b" => Ok(AofFileType::Base),
+            "i" => Ok(AofFileType::Incr),
+            "h" => Ok(AofFileType::History),
+            value => Err(AofError::Manifest(format!("Unknown file type {}", value))),
+        }
+    }
+}
+
+impl std::fmt::Display for AofFileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AofFileType::Base => write!(f, "b"),
+            AofFileType::Incr => write!(f, "i"),
+            AofFileType::History => write!(f, "h"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofFileInfo {
+    pub name: String,
+    pub seq: u64,
+    pub file_type: AofFileType,
+}
+
+impl AofFileInfo {
+    /// Base files can be written in the RDB format, which is faster to load than the commands.
+    pub fn is_rdb(&self) -> bool {
+        self.name.ends_with(RDB_FORMAT_SUFFIX)
+    }
+}
+
+impl std::fmt::Display for AofFileInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "file {} seq {} type {}",
+            self.name, self.seq, self.file_type
+        )
+    }
+}
+
+/// The files that make up the AOF, with the same multi-part layout as Redis 7: a base file with the dataset when
+/// the last rewrite started, and the incremental files with the commands appended since then.
+///
+/// Every file is a line of the manifest (example: "file appendonly.aof.1.base.rdb seq 1 type b").
+#[derive(Debug, Default, Clone)]
+pub struct AofManifest {
+    pub base: Option<AofFileInfo>,
+    pub incrs: Vec<AofFileInfo>,
+    pub history: Vec<AofFileInfo>,
+    // Sequence numbers of the last base and incremental files created.
+    base_seq: u64,
+    incr_seq: u64,
+}
+
+impl AofManifest {
+    /// The name of the manifest of the AOF called `appendfilename` (example: appendonly.aof.manifest).
+    pub fn file_name(appendfilename: &str) -> String {
+        format!("{}{}", appendfilename, MANIFEST_SUFFIX)
+    }
+
+    /// Reads the manifest. It returns None when the file does not exist.
+    pub fn load(path: &Path) -> Result<Option<Self>, AofError> {
+        match fs::read_to_string(path) {
+            Ok(value) => AofManifest::from_str(&value).map(Some),
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::NotFound => Ok(None),
+                _ => Err(AofError::ReadFile(err.to_string())),
+            },
+        }
+    }
+
+    /// Replaces the manifest atomically: it is written to a temporary file that is renamed once it is on disk, so a
+    /// crash leaves either the old manifest or the new one.
+    pub fn save(&self, path: &Path) -> Result<(), AofError> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let file_name = path
+            .file_name()
+            .ok_or(AofError::Manifest(String::from("Invalid manifest path")))?;
+        let temp_path = dir.join(format!("{}{}", TEMP_PREFIX, file_name.to_string_lossy()));
+
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(self.to_string().as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, path))
+            // The rename is only durable once the directory is on disk too.
+            .and_then(|_| File::open(dir.join(".")).and_then(|dir| dir.sync_all()));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result.map_err(|err| AofError::WriteFile(err.to_string()))
+    }
+
+    /// The files to load, in order: the base and then the incremental files.
+    pub fn files(&self) -> impl Iterator<Item = &AofFileInfo> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    /// Adds a new incremental file at the end.
+    pub fn new_incr(&mut self, appendfilename: &str) -> AofFileInfo {
+        self.incr_seq += 1;
+
+        let incr = AofFileInfo {
+            name: format!(
+                "{}.{}.{}{}",
+                appendfilename, self.incr_seq, INCR_SUFFIX, AOF_FORMAT_SUFFIX
+            ),
+            seq: self.incr_seq,
+            file_type: AofFileType::Incr,
+        };
+
+        self.incrs.push(incr.clone());
+
+        incr
+    }
+
+    /// The base file of the next rewrite, which is written in the RDB format. It is not added until the rewrite
+    /// finishes.
+    pub fn next_base(&self, appendfilename: &str) -> AofFileInfo {
+        let seq = self.base_seq + 1;
+
+        AofFileInfo {
+            name: format!(
+                "{}.{}.{}{}",
+                appendfilename, seq, BASE_SUFFIX, RDB_FORMAT_SUFFIX
+            ),
+            seq,
+            file_type: AofFileType::Base,
+        }
+    }
+
+    /// Replaces the base. The previous base and the incremental files before `first_incr_seq` (the ones whose
+    /// commands are already in the new base) become history.
+    pub fn set_base(&mut self, base: AofFileInfo, first_incr_seq: u64) {
+        let (incrs, replaced_incrs) = self
+            .incrs
+            .drain(..)
+            .partition(|incr| incr.seq >= first_incr_seq);
+
+        self.incrs = incrs;
+
+        for mut file in self.base.take().into_iter().chain(replaced_incrs) {
+            file.file_type = AofFileType::History;
+
+            self.history.push(file);
+        }
+
+        self.base_seq = base.seq;
+        self.base = Some(base);
+    }
+}
+
+impl FromStr for AofManifest {
+    type Err = AofError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut manifest = AofManifest::default();
+
+        for line in value.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || AofError::Manifest(format!("Invalid line {}", line));
+            let values: Vec<&str> = line.split_whitespace().collect();
+            let (mut name, mut seq, mut file_type) = (None, None, None);
+
+            if !values.chunks_exact(2).remainder().is_empty() {
+                return Err(invalid());
+            }
+
+            // Unknown fields are ignored, so manifests written by newer versions can still be read.
+            for pair in values.chunks_exact(2) {
+                match pair[0] {
+                    "file" => name = Some(pair[1].to_string()),
+                    "seq" => seq = Some(pair[1].parse::<u64>().map_err(|_| invalid())?),
+                    "type" => file_type = Some(AofFileType::from_str(pair[1])?),
+                    _ => {}
+                }
+            }
+
+            let file = match (name, seq, file_type) {
+                (Some(name), Some(seq), Some(file_type)) => AofFileInfo {
+                    name,
+                    seq,
+                    file_type,
+                },
+                _ => return Err(invalid()),
+            };
+
+            match file.file_type {
+                AofFileType::Base if manifest.base.is_some() => {
+                    return Err(AofError::Manifest(String::from(
+                        "Found duplicate base file information",
+                    )))
+                }
+                AofFileType::Base => {
+                    manifest.base_seq = file.seq;
+                    manifest.base = Some(file);
+                }
+                AofFileType::Incr if file.seq <= manifest.incr_seq => {
+                    return Err(AofError::Manifest(String::from(
+                        "Found a non-monotonic sequence number",
+                    )))
+                }
+                AofFileType::Incr => {
+                    manifest.incr_seq = file.seq;
+                    manifest.incrs.push(file);
+                }
+                AofFileType::History => manifest.history.push(file),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl std::fmt::Display for AofManifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in self
+            .base
+            .iter()
+            .chain(self.history.iter())
+            .chain(self.incrs.iter())
+        {
+            writeln!(f, "{}", file)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, seq: u64, file_type: AofFileType) -> AofFileInfo {
+        AofFileInfo {
+            name: name.to_string(),
+            seq,
+            file_type,
+        }
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = AofManifest::from_str(
+            "# comment\n\
+             file appendonly.aof.2.base.rdb seq 2 type b\n\
+             \n\
+             file appendonly.aof.1.incr.aof seq 1 type h\n\
+             file appendonly.aof.3.incr.aof seq 3 type i unknown field\n\
+             file appendonly.aof.4.incr.aof seq 4 type i\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.base,
+            Some(file("appendonly.aof.2.base.rdb", 2, AofFileType::Base))
+        );
+        assert_eq!(
+            manifest.history,
+            vec![file("appendonly.aof.1.incr.aof", 1, AofFileType::History)]
+        );
+        assert_eq!(
+            manifest.incrs,
+            vec![
+                file("appendonly.aof.3.incr.aof", 3, AofFileType::Incr),
+                file("appendonly.aof.4.incr.aof", 4, AofFileType::Incr),
+            ]
+        );
+        assert!(manifest.base.as_ref().unwrap().is_rdb());
+        assert!(!manifest.incrs[0].is_rdb());
+    }
+
+    #[test]
+    fn parse_invalid_manifest() {
+        for value in [
+            "file appendonly.aof.1.incr.aof seq 1",
+            "file appendonly.aof.1.incr.aof seq one type i",
+            "file appendonly.aof.1.incr.aof seq 1 type x",
+            "file appendonly.aof.1.incr.aof seq 1 type",
+            "file a seq 1 type b\nfile b seq 2 type b",
+            "file a seq 2 type i\nfile b seq 2 type i",
+        ] {
+            assert!(
+                matches!(AofManifest::from_str(value), Err(AofError::Manifest(_))),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn format_manifest() {
+        let mut manifest = AofManifest::default();
+
+        manifest.new_incr("appendonly.aof");
+        manifest.set_base(manifest.next_base("appendonly.aof"), 2);
+        manifest.new_incr("appendonly.aof");
+
+        assert_eq!(
+            manifest.to_string(),
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type h\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+
+        let parsed = AofManifest::from_str(&manifest.to_string()).unwrap();
+
+        assert_eq!(parsed.base, manifest.base);
+        assert_eq!(parsed.incrs, manifest.incrs);
+        assert_eq!(parsed.history, manifest.history);
+    }
+
+    #[test]
+    fn sequence_numbers_continue_after_parsing() {
+        let mut manifest = AofManifest::from_str(
+            "file appendonly.aof.3.base.rdb seq 3 type b\n\
+             file appendonly.aof.7.incr.aof seq 7 type i\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            manifest.next_base("appendonly.aof"),
+            file("appendonly.aof.4.base.rdb", 4, AofFileType::Base)
+        );
+        assert_eq!(
+            manifest.new_incr("appendonly.aof"),
+            file("appendonly.aof.8.incr.aof", 8, AofFileType::Incr)
+        );
+    }
+
+    #[test]
+    fn set_base_keeps_the_incremental_files_after_the_rewrite() {
+        let mut manifest = AofManifest::default();
+
+        manifest.set_base(file("appendonly.aof", 1, AofFileType::Base), 0);
+        manifest.new_incr("appendonly.aof");
+        manifest.new_incr("appendonly.aof");
+        manifest.new_incr("appendonly.aof");
+        manifest.set_base(manifest.next_base("appendonly.aof"), 3);
+
+        assert_eq!(
+            manifest.base,
+            Some(file("appendonly.aof.2.base.rdb", 2, AofFileType::Base))
+        );
+        assert_eq!(
+            manifest.history,
+            vec![
+                file("appendonly.aof", 1, AofFileType::History),
+                file("appendonly.aof.1.incr.aof", 1, AofFileType::History),
+                file("appendonly.aof.2.incr.aof", 2, AofFileType::History),
+            ]
+        );
+        assert_eq!(
+            manifest.files().cloned().collect::<Vec<AofFileInfo>>(),
+            vec![
+                file("appendonly.aof.2.base.rdb", 2, AofFileType::Base),
+                file("appendonly.aof.3.incr.aof", 3, AofFileType::Incr),
+            ]
+        );
+    }
+
+    #[test]
+    fn save_and_load_manifest() {
+        let dir = std::env::temp_dir().join(format!("aof-manifest-{}", std::process::id()));
+        let path = dir.join(AofManifest::file_name("appendonly.aof"));
+        let mut manifest = AofManifest::default();
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        assert!(AofManifest::load(&path).unwrap().is_none());
+
+        manifest.new_incr("appendonly.aof");
+        manifest.save(&path).unwrap();
+
+        let loaded = AofManifest::load(&path).unwrap().unwrap();
+
+        assert_eq!(loaded.incrs, manifest.incrs);
+        assert!(!dir.join("temp-appendonly.aof.manifest").exists());
+        assert_eq!(
+            path.file_name().unwrap().to_string_lossy(),
+            "appendonly.aof.manifest"
+        );
+    }
+}
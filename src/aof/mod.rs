@@ -0,0 +1,3 @@
+pub mod load;
+pub mod manifest;
+pub mod writer;
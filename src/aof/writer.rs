@@ -0,0 +1,869 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+
+use crate::rdb::save::write_rdb_file;
+use crate::resp::data_types::{RespDataType, RespEncoder};
+use crate::store::{Store, StoreSnapshot};
+
+use super::manifest::{AofFileInfo, AofFileType, AofManifest};
+
+/// Seconds to wait before retrying an automatic rewrite after a failed one.
+const REWRITE_RETRY_DELAY: i64 = 5;
+
+#[derive(Debug)]
+pub enum AofError {
+    OpenFile(String),
+    WriteFile(String),
+    ReadFile(String),
+    InvalidCommand(usize),
+    Truncated(usize),
+    Manifest(String),
+    RewriteInProgress,
+    Disabled,
+}
+
+impl std::fmt::Display for AofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AofError::OpenFile(err) => {
+                write!(f, "OpenFile: {}", err)
+            }
+            AofError::WriteFile(err) => {
+                write!(f, "WriteFile: {}", err)
+            }
+            AofError::ReadFile(err) => {
+                write!(f, "ReadFile: {}", err)
+            }
+            AofError::InvalidCommand(offset) => {
+                write!(f, "InvalidCommand: bad file format at offset {}", offset)
+            }
+            AofError::Truncated(offset) => {
+                write!(f, "Truncated: unexpected end of file at offset {}", offset)
+            }
+            AofError::Manifest(err) => {
+                write!(f, "Manifest: {}", err)
+            }
+            AofError::RewriteInProgress => {
+                write!(
+                    f,
+                    "Background append only file rewriting already in progress"
+                )
+            }
+            AofError::Disabled => {
+                write!(f, "Append only file is not enabled")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AofError {}
+
+/// When the appended commands are flushed to disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// After every write command, before replying. No write is lost, but it is the slowest.
+    Always,
+    /// Once per second, so up to a second of writes can be lost.
+    #[default]
+    Everysec,
+    /// The operating system decides.
+    No,
+}
+
+impl FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::Everysec),
+            "no" => Ok(AppendFsync::No),
+            value => Err(format!("Append fsync policy {} is not valid", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for AppendFsync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppendFsync::Always => write!(f, "always"),
+            AppendFsync::Everysec => write!(f, "everysec"),
+            AppendFsync::No => write!(f, "no"),
+        }
+    }
+}
+
+/// Appends the write commands to the AOF, in RESP form, and rewrites it when it grows too much.
+///
+/// The AOF is locked while a write command is executed and appended, so the commands are appended in the order they
+/// change the store. Nothing is locked when the AOF is disabled.
+///
+/// The AOF is made of the files listed in its manifest, stored in their own directory. Commands are appended to the
+/// last incremental file, and a rewrite replaces the base and every incremental file with a snapshot of the store.
+#[derive(Debug)]
+pub struct AofWriter {
+    // None until the AOF is opened (after loading it) or when it is disabled.
+    file: Mutex<Option<File>>,
+    // Whether the file is open, so commands can skip the lock when it is not.
+    enabled: AtomicBool,
+    fsync: AppendFsync,
+    // Commands were appended since the last fsync.
+    pending_fsync: AtomicBool,
+    last_write_ok: AtomicBool,
+    last_fsync_ok: AtomicBool,
+    // An incomplete command was dropped from the end of the AOF when it was loaded.
+    load_truncated: AtomicBool,
+    dir: PathBuf,
+    appendfilename: String,
+    manifest: Mutex<AofManifest>,
+    // Bytes of every file of the AOF.
+    current_size: AtomicU64,
+    // Bytes of the AOF after the last rewrite (or when it was opened). Automatic rewrites depend on how much it grew
+    // since then.
+    base_size: AtomicU64,
+    rewrite_in_progress: AtomicBool,
+    last_rewrite_ok: AtomicBool,
+    // Unix time (in seconds) when the last rewrite started.
+    last_rewrite_try: AtomicI64,
+}
+
+impl AofWriter {
+    pub fn new(dir: PathBuf, appendfilename: &str, fsync: AppendFsync) -> Self {
+        Self {
+            file: Mutex::new(None),
+            enabled: AtomicBool::new(false),
+            fsync,
+            pending_fsync: AtomicBool::new(false),
+            last_write_ok: AtomicBool::new(true),
+            last_fsync_ok: AtomicBool::new(true),
+            load_truncated: AtomicBool::new(false),
+            dir,
+            appendfilename: appendfilename.to_string(),
+            manifest: Mutex::new(AofManifest::default()),
+            current_size: AtomicU64::new(0),
+            base_size: AtomicU64::new(0),
+            rewrite_in_progress: AtomicBool::new(false),
+            last_rewrite_ok: AtomicBool::new(true),
+            last_rewrite_try: AtomicI64::new(0),
+        }
+    }
+
+    /// Reads the manifest and returns the files to load, in order. The directory and the manifest are created when
+    /// they do not exist.
+    ///
+    /// An AOF written as a single file (`legacy_path`) becomes the base of the new manifest, the same as Redis does
+    /// when upgrading.
+    pub fn load_manifest(&self, legacy_path: &Path) -> Result<Vec<AofFileInfo>, AofError> {
+        let manifest_path = self.manifest_path();
+        let mut manifest = match AofManifest::load(&manifest_path)? {
+            Some(manifest) => manifest,
+            None => {
+                fs::create_dir_all(&self.dir).map_err(|err| AofError::OpenFile(err.to_string()))?;
+
+                let mut manifest = AofManifest::default();
+
+                if legacy_path.exists() {
+                    let base = AofFileInfo {
+                        name: self.appendfilename.clone(),
+                        seq: 1,
+                        file_type: AofFileType::Base,
+                    };
+
+                    fs::rename(legacy_path, self.file_path(&base))
+                        .map_err(|err| AofError::WriteFile(err.to_string()))?;
+
+                    manifest.set_base(base, 0);
+                }
+
+                manifest.save(&manifest_path)?;
+
+                manifest
+            }
+        };
+
+        // The files replaced by a rewrite that could not be deleted before.
+        self.delete_history(&mut manifest)?;
+
+        let files = manifest.files().cloned().collect();
+
+        *self.manifest.lock().unwrap() = manifest;
+
+        Ok(files)
+    }
+
+    /// Starts appending commands to the last incremental file, which is created if there is none.
+    pub fn open(&self) -> Result<(), AofError> {
+        let mut aof = self.lock();
+        let mut manifest = self.manifest.lock().unwrap();
+
+        if manifest.incrs.is_empty() {
+            let incr = manifest.new_incr(&self.appendfilename);
+
+            // The file is created before it is added to the manifest, so the manifest never lists a missing file.
+            open_file(&self.file_path(&incr))?;
+            manifest.save(&self.manifest_path())?;
+        }
+
+        let incr = manifest
+            .incrs
+            .last()
+            .cloned()
+            .ok_or(AofError::OpenFile(String::from(
+                "There is no incremental file",
+            )))?;
+        let size = self.files_size(&manifest)?;
+
+        *aof.file = Some(open_file(&self.file_path(&incr))?);
+        self.enabled.store(true, Ordering::Release);
+
+        self.current_size.store(size, Ordering::Relaxed);
+        self.base_size.store(size, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    pub fn lock(&self) -> AofGuard<'_> {
+        AofGuard {
+            writer: self,
+            file: self.file.lock().unwrap(),
+        }
+    }
+
+    /// Flushes the appended commands to disk, when there are any. Used by the `everysec` policy.
+    pub fn fsync(&self) -> Result<(), AofError> {
+        if !self.pending_fsync.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        // The file is cloned so the writes are not blocked while flushing.
+        let file = match self.lock().file.as_ref().map(|file| file.try_clone()) {
+            Some(file) => file.map_err(|err| AofError::WriteFile(err.to_string()))?,
+            None => return Ok(()),
+        };
+
+        let result = file.sync_data();
+
+        self.last_fsync_ok.store(result.is_ok(), Ordering::Relaxed);
+
+        result.map_err(|err| AofError::WriteFile(err.to_string()))
+    }
+
+    /// Rewrites the AOF in the background (BGREWRITEAOF). The new base is a snapshot of the store in the RDB format.
+    ///
+    /// Commands are appended to a new incremental file from the moment the snapshot is taken, so the ones executed
+    /// during the rewrite are kept. The old files are deleted once the new base is in the manifest.
+    pub fn background_rewrite(self: &Arc<Self>, store: Arc<Store>) -> Result<(), AofError> {
+        if !self.is_enabled() {
+            return Err(AofError::Disabled);
+        }
+
+        self.rewrite_in_progress
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| AofError::RewriteInProgress)?;
+
+        self.last_rewrite_try
+            .store(store.clock().now().timestamp(), Ordering::Relaxed);
+
+        let (snapshot, first_incr_seq) = match self.start_rewrite(&store) {
+            Ok(value) => value,
+            Err(err) => {
+                self.last_rewrite_ok.store(false, Ordering::Relaxed);
+                self.rewrite_in_progress.store(false, Ordering::Release);
+
+                return Err(err);
+            }
+        };
+        let writer = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            // A failed rewrite is reported by INFO persistence (aof_last_bgrewrite_status).
+            let result = writer.finish_rewrite(&store, &snapshot, first_incr_seq);
+
+            writer
+                .last_rewrite_ok
+                .store(result.is_ok(), Ordering::Relaxed);
+            writer.rewrite_in_progress.store(false, Ordering::Release);
+        });
+
+        Ok(())
+    }
+
+    /// Returns true when the AOF grew more than `percentage` since the last rewrite, and it is bigger than
+    /// `min_size`. A percentage of 0 disables the automatic rewrites. After a failed rewrite, it waits a few seconds
+    /// before trying again.
+    pub fn should_rewrite(&self, percentage: u64, min_size: u64, now: DateTime<Utc>) -> bool {
+        if percentage == 0 || !self.is_enabled() || self.is_rewrite_in_progress() {
+            return false;
+        }
+
+        let can_retry = self.last_rewrite_ok()
+            || now.timestamp() - self.last_rewrite_try.load(Ordering::Relaxed)
+                > REWRITE_RETRY_DELAY;
+        let current_size = self.current_size();
+        let base_size = self.base_size().max(1);
+        let growth = (current_size * 100 / base_size).saturating_sub(100);
+
+        can_retry && current_size > min_size && growth >= percentage
+    }
+
+    pub fn fsync_policy(&self) -> AppendFsync {
+        self.fsync
+    }
+
+    /// Whether the last append and the last background fsync succeeded.
+    pub fn last_write_ok(&self) -> bool {
+        self.last_write_ok.load(Ordering::Relaxed) && self.last_fsync_ok.load(Ordering::Relaxed)
+    }
+
+    pub fn load_truncated(&self) -> bool {
+        self.load_truncated.load(Ordering::Relaxed)
+    }
+
+    pub fn set_load_truncated(&self, load_truncated: bool) {
+        self.load_truncated.store(load_truncated, Ordering::Relaxed);
+    }
+
+    pub fn current_size(&self) -> u64 {
+        self.current_size.load(Ordering::Relaxed)
+    }
+
+    pub fn base_size(&self) -> u64 {
+        self.base_size.load(Ordering::Relaxed)
+    }
+
+    pub fn is_rewrite_in_progress(&self) -> bool {
+        self.rewrite_in_progress.load(Ordering::Acquire)
+    }
+
+    pub fn last_rewrite_ok(&self) -> bool {
+        self.last_rewrite_ok.load(Ordering::Relaxed)
+    }
+
+    pub fn file_path(&self, file: &AofFileInfo) -> PathBuf {
+        self.dir.join(&file.name)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(AofManifest::file_name(&self.appendfilename))
+    }
+
+    // Switches the appended commands to a new incremental file and takes a snapshot of the store, both while the AOF
+    // is locked. The snapshot has the commands of the previous files. Commands are executed before they are appended,
+    // so a command executed right before the snapshot can also be in the new file, and it is replayed on top of it.
+    fn start_rewrite(&self, store: &Store) -> Result<(StoreSnapshot, u64), AofError> {
+        let mut aof = self.lock();
+        let mut manifest = self.manifest.lock().unwrap();
+        let mut new_manifest = manifest.clone();
+        let incr = new_manifest.new_incr(&self.appendfilename);
+        let file = open_file(&self.file_path(&incr))?;
+
+        new_manifest.save(&self.manifest_path())?;
+        aof.replace_file(file)?;
+
+        *manifest = new_manifest;
+
+        Ok((store.snapshot(), incr.seq))
+    }
+
+    // Writes the new base and replaces the files whose commands are in it.
+    fn finish_rewrite(
+        &self,
+        store: &Store,
+        snapshot: &StoreSnapshot,
+        first_incr_seq: u64,
+    ) -> Result<(), AofError> {
+        let base = self
+            .manifest
+            .lock()
+            .unwrap()
+            .next_base(&self.appendfilename);
+
+        // The base is written atomically, so a crash in the middle of the rewrite never leaves a partial one.
+        write_rdb_file(
+            snapshot,
+            store.used_memory(),
+            store.clock().now(),
+            &self.file_path(&base),
+            true,
+        )
+        .map_err(|err| AofError::WriteFile(err.to_string()))?;
+
+        {
+            let mut manifest = self.manifest.lock().unwrap();
+            let mut new_manifest = manifest.clone();
+
+            new_manifest.set_base(base, first_incr_seq);
+            new_manifest.save(&self.manifest_path())?;
+
+            *manifest = new_manifest;
+
+            self.delete_history(&mut manifest)?;
+        }
+
+        // The size is taken while the AOF is locked, so no command is appended meanwhile.
+        let _aof = self.lock();
+        let size = self.files_size(&self.manifest.lock().unwrap())?;
+
+        self.current_size.store(size, Ordering::Relaxed);
+        self.base_size.store(size, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    // Deletes the files replaced by a rewrite and removes them from the manifest.
+    fn delete_history(&self, manifest: &mut AofManifest) -> Result<(), AofError> {
+        if manifest.history.is_empty() {
+            return Ok(());
+        }
+
+        for file in manifest.history.iter() {
+            match fs::remove_file(self.file_path(file)) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(AofError::WriteFile(err.to_string())),
+            }
+        }
+
+        manifest.history.clear();
+        manifest.save(&self.manifest_path())
+    }
+
+    fn files_size(&self, manifest: &AofManifest) -> Result<u64, AofError> {
+        manifest.files().try_fold(0, |size, file| {
+            fs::metadata(self.file_path(file))
+                .map(|metadata| size + metadata.len())
+                .map_err(|err| AofError::ReadFile(err.to_string()))
+        })
+    }
+}
+
+pub struct AofGuard<'a> {
+    writer: &'a AofWriter,
+    file: MutexGuard<'a, Option<File>>,
+}
+
+impl AofGuard<'_> {
+    /// Appends a command. It does nothing when the AOF is not open.
+    pub fn append(&mut self, args: &[impl AsRef<[u8]>]) -> Result<(), AofError> {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let command = RespEncoder::encode(RespDataType::Array(
+            args.iter()
+                .map(|arg| RespDataType::BinaryBulkString(arg.as_ref().to_vec()))
+                .collect(),
+        ));
+
+        let result = file
+            .write_all(&command)
+            .and_then(|_| match self.writer.fsync {
+                AppendFsync::Always => file.sync_data(),
+                AppendFsync::Everysec => {
+                    self.writer.pending_fsync.store(true, Ordering::Release);
+
+                    Ok(())
+                }
+                AppendFsync::No => Ok(()),
+            });
+
+        if result.is_ok() {
+            self.writer
+                .current_size
+                .fetch_add(command.len() as u64, Ordering::Relaxed);
+        }
+
+        self.writer
+            .last_write_ok
+            .store(result.is_ok(), Ordering::Relaxed);
+
+        result.map_err(|err| AofError::WriteFile(err.to_string()))
+    }
+
+    // Appends the next commands to another file. The current one is flushed first, whatever the fsync policy is,
+    // since it will not be written again.
+    fn replace_file(&mut self, file: File) -> Result<(), AofError> {
+        if let Some(current_file) = self.file.as_ref() {
+            current_file
+                .sync_data()
+                .map_err(|err| AofError::WriteFile(err.to_string()))?;
+        }
+
+        *self.file = Some(file);
+
+        Ok(())
+    }
+}
+
+// Opens a file to append commands to it, creating it if it does not exist.
+fn open_file(path: &Path) -> Result<File, AofError> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| AofError::OpenFile(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+    use tokio::io::BufReader;
+
+    use crate::rdb::decoder::RdbFileDecoder;
+    use crate::store::StoreValueBuilder;
+
+    use super::*;
+
+    // An empty directory for the files of a test, removed when the test starts.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aof-writer-{}-{}", std::process::id(), name));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn open_writer(dir: &Path, fsync: AppendFsync) -> AofWriter {
+        let writer = AofWriter::new(dir.to_path_buf(), "appendonly.aof", fsync);
+
+        writer
+            .load_manifest(&dir.join("legacy-appendonly.aof"))
+            .unwrap();
+        writer.open().unwrap();
+
+        writer
+    }
+
+    fn incr_path(writer: &AofWriter) -> PathBuf {
+        let manifest = writer.manifest.lock().unwrap();
+
+        writer.file_path(manifest.incrs.last().unwrap())
+    }
+
+    #[test]
+    fn append_does_nothing_until_the_aof_is_open() {
+        let dir = test_dir("not-open");
+        let writer = AofWriter::new(dir.clone(), "appendonly.aof", AppendFsync::Always);
+
+        writer.lock().append(&["SET", "a", "1"]).unwrap();
+
+        assert!(!writer.is_enabled());
+        assert_eq!(writer.current_size(), 0);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn open_creates_the_manifest_and_an_incremental_file() {
+        let dir = test_dir("open");
+        let writer = open_writer(&dir, AppendFsync::Always);
+        let manifest = AofManifest::load(&dir.join("appendonly.aof.manifest"))
+            .unwrap()
+            .unwrap();
+
+        assert!(writer.is_enabled());
+        assert!(manifest.base.is_none());
+        assert_eq!(
+            manifest.incrs,
+            vec![AofFileInfo {
+                name: String::from("appendonly.aof.1.incr.aof"),
+                seq: 1,
+                file_type: AofFileType::Incr,
+            }]
+        );
+        assert!(dir.join("appendonly.aof.1.incr.aof").exists());
+    }
+
+    #[test]
+    fn append_writes_the_command_in_resp() {
+        let dir = test_dir("append");
+        let writer = open_writer(&dir, AppendFsync::Always);
+
+        writer.lock().append(&["SET", "a", "1"]).unwrap();
+        writer
+            .lock()
+            .append(&[b"DEL".to_vec(), vec![0, 255]])
+            .unwrap();
+
+        let expected =
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*2\r\n$3\r\nDEL\r\n$2\r\n\x00\xff\r\n";
+
+        assert_eq!(fs::read(incr_path(&writer)).unwrap(), expected);
+        assert_eq!(writer.current_size(), expected.len() as u64);
+        assert_eq!(writer.base_size(), 0);
+        assert!(writer.last_write_ok());
+    }
+
+    #[test]
+    fn open_appends_to_the_last_incremental_file() {
+        let dir = test_dir("reopen");
+
+        open_writer(&dir, AppendFsync::No)
+            .lock()
+            .append(&["SET", "a", "1"])
+            .unwrap();
+
+        let writer = open_writer(&dir, AppendFsync::No);
+
+        writer.lock().append(&["SET", "b", "2"]).unwrap();
+
+        assert_eq!(
+            fs::read(incr_path(&writer)).unwrap(),
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*3\r\n$3\r\nSET\r\n$1\r\nb\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(writer.base_size(), 27);
+        assert_eq!(writer.current_size(), 54);
+    }
+
+    #[test]
+    fn everysec_flushes_only_when_commands_were_appended() {
+        let dir = test_dir("everysec");
+        let writer = open_writer(&dir, AppendFsync::Everysec);
+
+        assert!(!writer.pending_fsync.load(Ordering::Acquire));
+
+        writer.lock().append(&["SET", "a", "1"]).unwrap();
+
+        assert!(writer.pending_fsync.load(Ordering::Acquire));
+
+        writer.fsync().unwrap();
+
+        assert!(!writer.pending_fsync.load(Ordering::Acquire));
+        assert!(writer.last_write_ok());
+    }
+
+    #[test]
+    fn always_and_no_do_not_wait_for_the_background_fsync() {
+        for fsync in [AppendFsync::Always, AppendFsync::No] {
+            let dir = test_dir(&format!("fsync-{}", fsync));
+            let writer = open_writer(&dir, fsync);
+
+            writer.lock().append(&["SET", "a", "1"]).unwrap();
+
+            assert!(!writer.pending_fsync.load(Ordering::Acquire));
+        }
+    }
+
+    #[test]
+    fn parse_fsync_policy() {
+        assert_eq!(AppendFsync::from_str("always"), Ok(AppendFsync::Always));
+        assert_eq!(AppendFsync::from_str("EVERYSEC"), Ok(AppendFsync::Everysec));
+        assert_eq!(AppendFsync::from_str("no"), Ok(AppendFsync::No));
+        assert!(AppendFsync::from_str("sometimes").is_err());
+    }
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    fn store_with_keys(keys: &[(&str, &str)]) -> Arc<Store> {
+        let store = Arc::new(Store::default());
+
+        for (key, value) in keys {
+            let mut builder = StoreValueBuilder::new();
+
+            builder.with_value(value);
+            store.set(key, builder.build()).unwrap();
+        }
+
+        store
+    }
+
+    async fn wait_for_rewrite(writer: &AofWriter) {
+        while writer.is_rewrite_in_progress() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn rdb_keys(rdb_path: &Path) -> Vec<String> {
+        let file = tokio::fs::File::open(rdb_path).await.unwrap();
+        let data = RdbFileDecoder::new(BufReader::new(file))
+            .decode()
+            .await
+            .unwrap();
+        let mut keys: Vec<String> = data
+            .databases
+            .into_iter()
+            .flat_map(|databases| databases.databases.into_values())
+            .flat_map(|database| database.data.into_keys())
+            .collect();
+
+        keys.sort();
+
+        keys
+    }
+
+    #[test]
+    fn load_manifest_turns_a_legacy_aof_into_the_base() {
+        let dir = test_dir("legacy");
+        let legacy_path = dir.join("appendonly.aof");
+        let writer = AofWriter::new(
+            dir.join("appendonlydir"),
+            "appendonly.aof",
+            AppendFsync::Everysec,
+        );
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&legacy_path, b"*1\r\n$4\r\nPING\r\n").unwrap();
+
+        let files = writer.load_manifest(&legacy_path).unwrap();
+
+        assert_eq!(
+            files,
+            vec![AofFileInfo {
+                name: String::from("appendonly.aof"),
+                seq: 1,
+                file_type: AofFileType::Base,
+            }]
+        );
+        assert!(!legacy_path.exists());
+        assert_eq!(
+            fs::read(dir.join("appendonlydir").join("appendonly.aof")).unwrap(),
+            b"*1\r\n$4\r\nPING\r\n"
+        );
+
+        writer.open().unwrap();
+
+        assert_eq!(writer.current_size(), 14);
+        assert_eq!(writer.base_size(), 14);
+    }
+
+    #[test]
+    fn load_manifest_deletes_the_history() {
+        let dir = test_dir("history");
+        let mut manifest = AofManifest::default();
+
+        fs::create_dir_all(&dir).unwrap();
+        manifest.new_incr("appendonly.aof");
+        manifest.set_base(manifest.next_base("appendonly.aof"), 2);
+        manifest.save(&dir.join("appendonly.aof.manifest")).unwrap();
+        fs::write(dir.join("appendonly.aof.1.incr.aof"), b"").unwrap();
+
+        let writer = AofWriter::new(dir.clone(), "appendonly.aof", AppendFsync::Everysec);
+
+        writer
+            .load_manifest(&dir.join("legacy-appendonly.aof"))
+            .unwrap();
+
+        let manifest = AofManifest::load(&dir.join("appendonly.aof.manifest"))
+            .unwrap()
+            .unwrap();
+
+        assert!(manifest.history.is_empty());
+        assert!(!dir.join("appendonly.aof.1.incr.aof").exists());
+    }
+
+    #[tokio::test]
+    async fn background_rewrite_replaces_the_files_with_a_snapshot() {
+        let dir = test_dir("rewrite");
+        let store = store_with_keys(&[("a", "1"), ("b", "2")]);
+        let writer = Arc::new(open_writer(&dir, AppendFsync::Always));
+
+        writer.lock().append(&["SET", "a", "1"]).unwrap();
+        writer.lock().append(&["SET", "b", "2"]).unwrap();
+        writer.background_rewrite(store.clone()).unwrap();
+
+        // Appended to the new incremental file, so it is kept whenever the rewrite finishes.
+        writer.lock().append(&["SET", "c", "3"]).unwrap();
+        wait_for_rewrite(&writer).await;
+
+        let manifest = AofManifest::load(&dir.join("appendonly.aof.manifest"))
+            .unwrap()
+            .unwrap();
+        let base = manifest.base.clone().unwrap();
+
+        assert!(writer.last_rewrite_ok());
+        assert_eq!(base.name, "appendonly.aof.1.base.rdb");
+        assert_eq!(
+            manifest.incrs,
+            vec![AofFileInfo {
+                name: String::from("appendonly.aof.2.incr.aof"),
+                seq: 2,
+                file_type: AofFileType::Incr,
+            }]
+        );
+        assert!(manifest.history.is_empty());
+        assert!(!dir.join("appendonly.aof.1.incr.aof").exists());
+        assert_eq!(rdb_keys(&writer.file_path(&base)).await, vec!["a", "b"]);
+        assert_eq!(
+            fs::read(dir.join("appendonly.aof.2.incr.aof")).unwrap(),
+            b"*3\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\n3\r\n"
+        );
+
+        let size = fs::metadata(writer.file_path(&base)).unwrap().len() + 27;
+
+        assert_eq!(writer.current_size(), size);
+        assert_eq!(writer.base_size(), size);
+    }
+
+    #[tokio::test]
+    async fn background_rewrite_fails_when_the_aof_is_disabled() {
+        let writer = Arc::new(AofWriter::new(
+            test_dir("rewrite-disabled"),
+            "appendonly.aof",
+            AppendFsync::Everysec,
+        ));
+
+        assert!(matches!(
+            writer.background_rewrite(store_with_keys(&[])),
+            Err(AofError::Disabled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn background_rewrite_fails_when_one_is_in_progress() {
+        let writer = Arc::new(open_writer(&test_dir("rewrite-twice"), AppendFsync::No));
+
+        writer.rewrite_in_progress.store(true, Ordering::Release);
+
+        assert!(matches!(
+            writer.background_rewrite(store_with_keys(&[])),
+            Err(AofError::RewriteInProgress)
+        ));
+    }
+
+    #[test]
+    fn should_rewrite_when_the_aof_grows() {
+        let dir = test_dir("should-rewrite");
+
+        open_writer(&dir, AppendFsync::No)
+            .lock()
+            .append(&["SET", "a", "1"])
+            .unwrap();
+
+        let writer = open_writer(&dir, AppendFsync::No);
+
+        assert!(!writer.should_rewrite(100, 0, time(0)));
+
+        writer.lock().append(&["SET", "b", "2"]).unwrap();
+
+        assert!(writer.should_rewrite(100, 0, time(0)));
+        assert!(!writer.should_rewrite(101, 0, time(0)));
+        assert!(!writer.should_rewrite(100, 54, time(0)));
+        assert!(!writer.should_rewrite(0, 0, time(0)));
+    }
+
+    #[test]
+    fn should_rewrite_waits_after_a_failed_rewrite() {
+        let writer = open_writer(&test_dir("should-retry"), AppendFsync::No);
+
+        writer.lock().append(&["SET", "a", "1"]).unwrap();
+        writer.last_rewrite_ok.store(false, Ordering::Relaxed);
+        writer.last_rewrite_try.store(100, Ordering::Relaxed);
+
+        assert!(!writer.should_rewrite(100, 0, time(100 + REWRITE_RETRY_DELAY)));
+        assert!(writer.should_rewrite(100, 0, time(101 + REWRITE_RETRY_DELAY)));
+    }
+}
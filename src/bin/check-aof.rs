@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::Context;
+
+use clap::Parser;
+use tokio::fs::File;
+use tokio::io::BufReader;
+
+use codecrafters_redis::aof::load::AofLoader;
+use codecrafters_redis::aof::manifest::AofManifest;
+use codecrafters_redis::rdb::decoder::RdbFileDecoder;
+
+/// Checks that an AOF can be loaded, the same as redis-check-aof. With --fix, an AOF that is not valid is truncated to
+/// its last complete command.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The AOF, or the manifest of a multi-part AOF (example: appendonlydir/appendonly.aof.manifest)
+    file: PathBuf,
+    /// Truncate the AOF to its last complete command when it is not valid. With a manifest, only the last file is
+    /// truncated, since it is the only one that is appended to.
+    #[arg(long)]
+    fix: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+
+    let files = if args.file.to_string_lossy().ends_with(".manifest") {
+        let dir = args.file.parent().unwrap_or(Path::new(""));
+        let manifest = AofManifest::load(&args.file)?
+            .with_context(|| format!("Manifest {} does not exist", args.file.display()))?;
+
+        manifest.files().map(|file| dir.join(&file.name)).collect()
+    } else {
+        vec![args.file.clone()]
+    };
+
+    for (index, path) in files.iter().enumerate() {
+        let valid = if path.to_string_lossy().ends_with(".rdb") {
+            check_rdb(path).await?
+        } else {
+            check_aof(path, args.fix && index == files.len() - 1).await?
+        };
+
+        if !valid {
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// Reads every command of the file with the same loader as the server. It returns whether the file is valid, or it
+// was fixed.
+async fn check_aof(path: &Path, fix: bool) -> anyhow::Result<bool> {
+    let mut loader = AofLoader::open(path)
+        .await?
+        .with_context(|| format!("File {} does not exist", path.display()))?;
+    let mut commands = 0;
+
+    let error = loop {
+        match loader.next_command() {
+            Ok(Some(_)) => commands += 1,
+            Ok(None) => break None,
+            Err(err) => break Some(err),
+        }
+    };
+
+    println!(
+        "AOF analyzed: filename={}, size={}, commands={}, ok_up_to={}, diff={}",
+        path.display(),
+        loader.len(),
+        commands,
+        loader.position(),
+        loader.len() - loader.position()
+    );
+
+    let error = match error {
+        Some(error) => error,
+        None => {
+            println!("AOF {} is valid", path.display());
+
+            return Ok(true);
+        }
+    };
+
+    println!("AOF {} is not valid: {}", path.display(), error);
+
+    if !fix {
+        println!("Run with --fix to truncate it to the last complete command");
+
+        return Ok(false);
+    }
+
+    loader.truncate()?;
+
+    println!(
+        "Successfully truncated AOF {} to {} bytes",
+        path.display(),
+        loader.position()
+    );
+
+    Ok(true)
+}
+
+// The base of a multi-part AOF can be an RDB file, which is checked by decoding it.
+async fn check_rdb(path: &Path) -> anyhow::Result<bool> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("File {} could not be opened", path.display()))?;
+
+    match RdbFileDecoder::new(BufReader::new(file)).decode().await {
+        Ok(_) => {
+            println!("RDB {} is valid", path.display());
+
+            Ok(true)
+        }
+        Err(err) => {
+            println!("RDB {} is not valid: {}", path.display(), err);
+
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SET_A: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+    const TRUNCATED: &[u8] = b"*3\r\n$3\r\nSET\r\n$1\r\nb";
+
+    // Writes the file of a test, in a directory removed when the test starts.
+    fn aof_file(name: &str, content: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("check-aof-{}-{}", std::process::id(), name));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("appendonly.aof"), content).unwrap();
+
+        dir.join("appendonly.aof")
+    }
+
+    #[tokio::test]
+    async fn valid_aof() {
+        let path = aof_file("valid", SET_A);
+
+        assert!(check_aof(&path, true).await.unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), SET_A);
+    }
+
+    #[tokio::test]
+    async fn truncated_aof_is_not_changed_without_fix() {
+        let path = aof_file("no-fix", &[SET_A, TRUNCATED].concat());
+
+        assert!(!check_aof(&path, false).await.unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), [SET_A, TRUNCATED].concat());
+    }
+
+    #[tokio::test]
+    async fn fix_truncates_to_the_last_complete_command() {
+        let path = aof_file("fix", &[SET_A, b"*1\r\n+OK\r\n", SET_A].concat());
+
+        assert!(check_aof(&path, true).await.unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), SET_A);
+        assert!(check_aof(&path, false).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn missing_aof() {
+        let path = aof_file("missing", b"").with_file_name("missing.aof");
+
+        assert!(check_aof(&path, true).await.is_err());
+    }
+}
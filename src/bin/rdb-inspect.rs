@@ -0,0 +1,1107 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::Context;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Parser, Subcommand};
+use std::io::Write;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use codecrafters_redis::rdb::decoder::{RdbData, RdbDatabase, RdbFileDecoder};
+use codecrafters_redis::rdb::replay::database_commands;
+use codecrafters_redis::resp::data_types::{RespDataType, RespDecoder, RespEncoder};
+use codecrafters_redis::store::{
+    entry_memory_usage, StoreData, StoreModuleValue, StoreStream, StoreValue,
+};
+
+/// The number of largest keys shown in the summary by default.
+const DEFAULT_TOP_KEYS: usize = 10;
+/// The number of commands sent to a server before reading their replies.
+const PIPELINE_SIZE: usize = 1000;
+/// The number of failed commands shown when sending the commands to a server. The rest are only counted.
+const MAX_ERRORS_SHOWN: usize = 10;
+/// The upper limits (in seconds) of the TTL ranges of the summary, with their names.
+const EXPIRY_RANGES: [(i64, &str); 4] = [
+    (60, "< 1m"),
+    (60 * 60, "< 1h"),
+    (24 * 60 * 60, "< 1d"),
+    (7 * 24 * 60 * 60, "< 7d"),
+];
+
+/// Looks inside RDB files without a server: dumps the keys, validates the file, summarizes its content and compares
+/// it with another one.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: InspectCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum InspectCommand {
+    /// Prints every key as a JSON line, with its database, type, TTL (in milliseconds) and value
+    Dump {
+        /// The RDB file (example: /tmp/redis-data/dump.rdb)
+        file: PathBuf,
+    },
+    /// Checks the structure and the checksum of the file. It exits with an error when the file is not valid
+    Validate {
+        /// The RDB file (example: /tmp/redis-data/dump.rdb)
+        file: PathBuf,
+    },
+    /// Prints the number of keys per database and type, the TTL distribution, the largest keys and the estimated
+    /// memory
+    Summary {
+        /// The RDB file (example: /tmp/redis-data/dump.rdb)
+        file: PathBuf,
+        /// The number of largest keys shown
+        #[arg(long, default_value_t = DEFAULT_TOP_KEYS)]
+        top: usize,
+    },
+    /// Prints the commands that create the keys of the file (SET, RPUSH, HSET, ZADD, PEXPIREAT...) in RESP form, the
+    /// same as an AOF, or sends them to a server
+    Commands {
+        /// The RDB file (example: /tmp/redis-data/dump.rdb)
+        file: PathBuf,
+        /// The server that receives the commands, instead of printing them (example: 127.0.0.1:6379)
+        #[arg(long)]
+        target: Option<String>,
+    },
+    /// Compares two files: prints the keys added, removed and changed (value, type or TTL) in the new one, and the
+    /// number of keys per database and type of both. It exits with an error when they are different
+    Diff {
+        /// The old RDB file (example: /tmp/redis-data/dump.rdb.20240101-000000)
+        old: PathBuf,
+        /// The new RDB file (example: /tmp/redis-data/dump.rdb)
+        new: PathBuf,
+    },
+}
+
+// What is kept of every key of the old file while the new one is read: its type, a digest of its value and its
+// expiration. The values themselves are not kept, so big files can be compared.
+struct KeyDigest {
+    type_name: String,
+    digest: u64,
+    exp: Option<DateTime<Utc>>,
+}
+
+impl KeyDigest {
+    fn new(value: &StoreValue) -> Self {
+        let mut hasher = DefaultHasher::new();
+
+        // The JSON form is used because sets and hashes are sorted in it, so the same values have the same digest.
+        json_value(&value.value).hash(&mut hasher);
+
+        KeyDigest {
+            type_name: value.value.type_name().to_string(),
+            digest: hasher.finish(),
+            exp: value.exp,
+        }
+    }
+}
+
+#[derive(Default)]
+struct DiffCounts {
+    added: u64,
+    removed: u64,
+    changed: u64,
+    ttl_changed: u64,
+    unchanged: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
+    let args = Args::parse();
+
+    match args.command {
+        InspectCommand::Dump { file } => {
+            let rdb_data = decode(&file).await?.0;
+
+            dump(&rdb_data);
+            warn_skipped_keys(&rdb_data);
+
+            Ok(ExitCode::SUCCESS)
+        }
+        InspectCommand::Validate { file } => validate(&file).await,
+        InspectCommand::Summary { file, top } => {
+            summary(&decode(&file).await?.0, top);
+
+            Ok(ExitCode::SUCCESS)
+        }
+        InspectCommand::Commands { file, target } => {
+            let rdb_data = decode(&file).await?.0;
+            let commands = commands(&rdb_data);
+
+            warn_skipped_keys(&rdb_data);
+
+            match target {
+                Some(target) => send_commands(&target, &commands).await,
+                None => {
+                    let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+
+                    for command in commands {
+                        stdout.write_all(&encode_command(command))?;
+                    }
+
+                    stdout.flush()?;
+
+                    Ok(ExitCode::SUCCESS)
+                }
+            }
+        }
+        InspectCommand::Diff { old, new } => diff(&old, &new).await,
+    }
+}
+
+// Decodes the file passing every key to `on_key` as soon as it is decoded, so the keys are not kept in memory. The
+// databases of the data returned have no keys.
+async fn decode_keys(
+    path: &Path,
+    on_key: &mut (dyn FnMut(usize, String, StoreValue) + Send),
+) -> anyhow::Result<RdbData> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("File {} could not be opened", path.display()))?;
+
+    RdbFileDecoder::new(BufReader::new(file))
+        .decode_keys(on_key)
+        .await
+        .map_err(|err| anyhow::anyhow!("File {} is not valid: {}", path.display(), err))
+}
+
+// Decodes the whole file. It returns the data and the number of bytes found after its end.
+async fn decode(path: &Path) -> anyhow::Result<(RdbData, usize)> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("File {} could not be opened", path.display()))?;
+    let mut decoder = RdbFileDecoder::new(BufReader::new(file));
+
+    let rdb_data = decoder
+        .decode()
+        .await
+        .map_err(|err| anyhow::anyhow!("File {} is not valid: {}", path.display(), err))?;
+
+    let mut trailing = vec![];
+
+    decoder
+        .into_inner()
+        .read_to_end(&mut trailing)
+        .await
+        .with_context(|| format!("File {} could not be read", path.display()))?;
+
+    Ok((rdb_data, trailing.len()))
+}
+
+fn dump(rdb_data: &RdbData) {
+    let now = Utc::now();
+
+    for (index, key, value) in sorted_keys(rdb_data) {
+        let ttl = match value.exp {
+            Some(exp) => (exp - now).num_milliseconds().to_string(),
+            None => String::from("null"),
+        };
+
+        println!(
+            "{{\"db\":{},\"key\":{},\"type\":{},\"ttl\":{},\"value\":{}}}",
+            index,
+            json_string(key),
+            json_string(value.value.type_name()),
+            ttl,
+            json_value(&value.value)
+        );
+    }
+}
+
+async fn validate(path: &Path) -> anyhow::Result<ExitCode> {
+    let (rdb_data, trailing) = match decode(path).await {
+        Ok(value) => value,
+        Err(err) => {
+            println!("{}", err);
+
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let keys: usize = databases(&rdb_data)
+        .map(|database| database.data.len())
+        .sum();
+    let skipped = keys_skipped(&rdb_data);
+    // The checksum is 0 when it was disabled, or when the version is older than the checksum.
+    let checksum = match rdb_data.checksum {
+        0 => String::from("not verified"),
+        checksum => format!("{:016x} (ok)", checksum),
+    };
+
+    println!("File {} is valid", path.display());
+    println!("version: {}", rdb_data.header.version);
+    println!("checksum: {}", checksum);
+    println!("keys: {}", keys);
+    println!("keys_skipped: {}", skipped);
+
+    // Redis stops reading at the end of file opcode, so the rest is ignored, but it usually means that two files
+    // were concatenated or that the file was not completely overwritten.
+    if trailing > 0 {
+        println!(
+            "warning: {} bytes after the end of the file are ignored",
+            trailing
+        );
+    }
+
+    // The file can be read, but the server does not load every key of it.
+    if skipped > 0 {
+        println!(
+            "error: {} keys have a name or value that is not valid UTF-8, so they can not be loaded",
+            skipped
+        );
+
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn summary(rdb_data: &RdbData, top: usize) {
+    let now = Utc::now();
+    let mut memory = 0;
+    let mut expiry_counts: BTreeMap<usize, u64> = BTreeMap::new();
+    // The smallest of the largest keys is on top, so it is the one replaced by a larger key.
+    let mut largest_keys = BinaryHeap::new();
+
+    println!("# File");
+    println!("version: {}", rdb_data.header.version);
+
+    if let Some(metadata) = &rdb_data.metadata {
+        let mut aux: Vec<_> = metadata.aux.iter().collect();
+
+        aux.sort();
+
+        for (key, value) in aux {
+            println!("{}: {}", key, value);
+        }
+    }
+
+    println!();
+    println!("# Keyspace");
+
+    for database in databases(rdb_data) {
+        let (index, data) = (database.index, &database.data);
+        let mut type_counts: BTreeMap<&str, u64> = BTreeMap::new();
+
+        for (key, value) in data.iter() {
+            let size = entry_memory_usage(key, value);
+
+            *type_counts.entry(value.value.type_name()).or_default() += 1;
+            *expiry_counts
+                .entry(expiry_range(value.exp, now))
+                .or_default() += 1;
+            memory += size;
+
+            largest_keys.push(Reverse((
+                size,
+                index,
+                key.as_str(),
+                value.value.type_name(),
+                elements(&value.value),
+            )));
+
+            if largest_keys.len() > top {
+                largest_keys.pop();
+            }
+        }
+
+        let types: Vec<String> = type_counts
+            .iter()
+            .map(|(type_name, count)| format!("{}={}", type_name, count))
+            .collect();
+
+        println!("db{}: keys={},{}", index, data.len(), types.join(","));
+    }
+
+    println!("keys_skipped: {}", keys_skipped(rdb_data));
+
+    println!();
+    println!("# Expiry");
+
+    for (range, count) in expiry_counts {
+        println!("{}: {}", expiry_range_name(range), count);
+    }
+
+    println!();
+    println!("# Largest keys");
+
+    for Reverse((size, index, key, type_name, elements)) in largest_keys.into_sorted_vec() {
+        println!(
+            "db{} {} type={} elements={} memory={}",
+            index, key, type_name, elements, size
+        );
+    }
+
+    println!();
+    println!("# Memory");
+    println!("estimated_memory: {}", memory);
+}
+
+// Only a digest of the keys of the old file is kept, and the new file is compared with it while it is read. The keys
+// that are left at the end are the removed ones.
+async fn diff(old: &Path, new: &Path) -> anyhow::Result<ExitCode> {
+    let mut old_keys: HashMap<(usize, String), KeyDigest> = HashMap::new();
+    // The number of keys of the old and the new file per database and type.
+    let mut type_counts: BTreeMap<(usize, String), (u64, u64)> = BTreeMap::new();
+    let mut counts = DiffCounts::default();
+
+    let old_data = decode_keys(old, &mut |index, key, value| {
+        let digest = KeyDigest::new(&value);
+
+        type_counts
+            .entry((index, digest.type_name.clone()))
+            .or_default()
+            .0 += 1;
+        old_keys.insert((index, key), digest);
+    })
+    .await?;
+
+    println!("# Keys");
+
+    let new_data = decode_keys(new, &mut |index, key, value| {
+        let digest = KeyDigest::new(&value);
+
+        type_counts
+            .entry((index, digest.type_name.clone()))
+            .or_default()
+            .1 += 1;
+
+        let old_digest = match old_keys.remove(&(index, key.clone())) {
+            Some(old_digest) => old_digest,
+            None => {
+                println!("+ db{} {} type={}", index, key, digest.type_name);
+                counts.added += 1;
+
+                return;
+            }
+        };
+
+        let changed = if old_digest.type_name != digest.type_name {
+            println!(
+                "~ db{} {} type={} -> {}",
+                index, key, old_digest.type_name, digest.type_name
+            );
+
+            true
+        } else if old_digest.digest != digest.digest {
+            println!("~ db{} {} value type={}", index, key, digest.type_name);
+
+            true
+        } else {
+            false
+        };
+        let ttl_changed = old_digest.exp != digest.exp;
+
+        if ttl_changed {
+            println!(
+                "~ db{} {} expiration={} -> {}",
+                index,
+                key,
+                expiration(old_digest.exp),
+                expiration(digest.exp)
+            );
+        }
+
+        counts.changed += changed as u64;
+        counts.ttl_changed += ttl_changed as u64;
+        counts.unchanged += (!changed && !ttl_changed) as u64;
+    })
+    .await?;
+
+    let mut removed_keys: Vec<_> = old_keys.into_iter().collect();
+
+    removed_keys.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for ((index, key), digest) in removed_keys {
+        println!("- db{} {} type={}", index, key, digest.type_name);
+        counts.removed += 1;
+    }
+
+    println!();
+    println!("# Keyspace");
+
+    for ((index, type_name), (old_count, new_count)) in type_counts {
+        println!("db{} {}: {} -> {}", index, type_name, old_count, new_count);
+    }
+
+    let (old_skipped, new_skipped) = (keys_skipped(&old_data), keys_skipped(&new_data));
+
+    println!("keys_skipped: {} -> {}", old_skipped, new_skipped);
+
+    println!();
+    println!("# Changes");
+    println!("added: {}", counts.added);
+    println!("removed: {}", counts.removed);
+    println!("changed: {}", counts.changed);
+    println!("ttl_changed: {}", counts.ttl_changed);
+    println!("unchanged: {}", counts.unchanged);
+
+    // The skipped keys can not be compared, so the files may be different.
+    if counts.added
+        + counts.removed
+        + counts.changed
+        + counts.ttl_changed
+        + old_skipped
+        + new_skipped
+        > 0
+    {
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn expiration(exp: Option<DateTime<Utc>>) -> String {
+    match exp {
+        Some(exp) => exp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        None => String::from("none"),
+    }
+}
+
+fn commands(rdb_data: &RdbData) -> Vec<Vec<String>> {
+    let mut commands = vec![];
+    // Connections start in the database 0, so it does not need to be selected.
+    let mut selected_index = 0;
+
+    for database in databases(rdb_data) {
+        let (database_commands, skipped_keys) = database_commands(database);
+
+        if database.index != selected_index {
+            commands.push(vec![String::from("SELECT"), database.index.to_string()]);
+            selected_index = database.index;
+        }
+
+        for key in skipped_keys {
+            eprintln!(
+                "Key {} of db{} is skipped: module values can not be converted to commands",
+                key, database.index
+            );
+        }
+
+        commands.extend(database_commands);
+    }
+
+    commands
+}
+
+// Sends the commands in batches, and reads the replies of every batch before sending the next one, so the server does
+// not need to keep all the replies. It fails when any command fails.
+async fn send_commands(target: &str, commands: &[Vec<String>]) -> anyhow::Result<ExitCode> {
+    let mut stream = TcpStream::connect(target)
+        .await
+        .with_context(|| format!("Could not connect to {}", target))?;
+    let mut buf = vec![];
+    let mut errors = 0;
+
+    for batch in commands.chunks(PIPELINE_SIZE) {
+        let payload: Vec<u8> = batch
+            .iter()
+            .flat_map(|command| encode_command(command.clone()))
+            .collect();
+
+        stream.write_all(&payload).await?;
+
+        let mut replies = 0;
+
+        while replies < batch.len() {
+            let (reply, length) = match RespDecoder::decode_bytes(&buf)? {
+                Some(reply) => reply,
+                None => {
+                    if stream.read_buf(&mut buf).await? == 0 {
+                        anyhow::bail!("Connection closed by {}", target);
+                    }
+
+                    continue;
+                }
+            };
+
+            if let RespDataType::SimpleError(err) = reply {
+                errors += 1;
+
+                if errors <= MAX_ERRORS_SHOWN {
+                    eprintln!("{} failed: {}", batch[replies].join(" "), err);
+                }
+            }
+
+            buf.drain(..length);
+            replies += 1;
+        }
+    }
+
+    println!("Commands sent: {}, errors: {}", commands.len(), errors);
+
+    match errors {
+        0 => Ok(ExitCode::SUCCESS),
+        _ => Ok(ExitCode::FAILURE),
+    }
+}
+
+fn encode_command(command: Vec<String>) -> Vec<u8> {
+    RespEncoder::encode(RespDataType::Array(
+        command.into_iter().map(RespDataType::BulkString).collect(),
+    ))
+}
+
+// The keys that are not decoded because their name or value is not valid UTF-8.
+fn keys_skipped(rdb_data: &RdbData) -> u64 {
+    databases(rdb_data)
+        .map(|database| database.keys_skipped)
+        .sum()
+}
+
+// The skipped keys are reported on stderr, so the output can still be used.
+fn warn_skipped_keys(rdb_data: &RdbData) {
+    let skipped = keys_skipped(rdb_data);
+
+    if skipped > 0 {
+        eprintln!(
+            "warning: {} keys are skipped: their name or value is not valid UTF-8",
+            skipped
+        );
+    }
+}
+
+// The databases ordered by index.
+fn databases(rdb_data: &RdbData) -> impl Iterator<Item = &RdbDatabase> {
+    let mut databases: Vec<&RdbDatabase> = rdb_data
+        .databases
+        .iter()
+        .flat_map(|databases| databases.databases.values())
+        .collect();
+
+    databases.sort_by_key(|database| database.index);
+
+    databases.into_iter()
+}
+
+// The keys ordered by database and name, so the output of two dumps of the same data is the same.
+fn sorted_keys(rdb_data: &RdbData) -> Vec<(usize, &String, &StoreValue)> {
+    databases(rdb_data)
+        .flat_map(|database| {
+            let mut keys: Vec<_> = database
+                .data
+                .iter()
+                .map(|(key, value)| (database.index, key, value))
+                .collect();
+
+            keys.sort_by(|a, b| a.1.cmp(b.1));
+
+            keys
+        })
+        .collect()
+}
+
+// The index of the TTL range of a key. Keys without expiration are in the range after the last one, and the ones
+// that have already expired in the range before the first one.
+fn expiry_range(exp: Option<DateTime<Utc>>, now: DateTime<Utc>) -> usize {
+    let ttl = match exp {
+        Some(exp) => (exp - now).num_seconds(),
+        None => return EXPIRY_RANGES.len() + 2,
+    };
+
+    if ttl < 0 {
+        return 0;
+    }
+
+    EXPIRY_RANGES
+        .iter()
+        .position(|(limit, _)| ttl < *limit)
+        .map(|position| position + 1)
+        .unwrap_or(EXPIRY_RANGES.len() + 1)
+}
+
+fn expiry_range_name(range: usize) -> &'static str {
+    match range {
+        0 => "expired",
+        range if range <= EXPIRY_RANGES.len() => EXPIRY_RANGES[range - 1].1,
+        range if range == EXPIRY_RANGES.len() + 1 => ">= 7d",
+        _ => "no expiry",
+    }
+}
+
+// The length of a string, or the number of elements of the rest of types.
+fn elements(data: &StoreData) -> usize {
+    match data {
+        StoreData::String(value) => value.len(),
+        StoreData::List(values) => values.len(),
+        StoreData::Set(members) => members.len(),
+        StoreData::Hash(fields) => fields.len(),
+        StoreData::SortedSet(members) => members.len(),
+        StoreData::Stream(stream) => stream.entries.len(),
+        StoreData::Module(module) => module.fields.len(),
+    }
+}
+
+// Sets and hashes are sorted, so the output of two dumps of the same data is the same.
+fn json_value(data: &StoreData) -> String {
+    match data {
+        StoreData::String(value) => json_string(value),
+        StoreData::List(values) => json_array(values.iter().map(|value| json_string(value))),
+        StoreData::Set(members) => {
+            let mut members: Vec<&String> = members.iter().collect();
+
+            members.sort();
+
+            json_array(members.into_iter().map(|member| json_string(member)))
+        }
+        StoreData::Hash(fields) => {
+            let mut fields: Vec<(&String, &String)> = fields.iter().collect();
+
+            fields.sort();
+
+            json_object(
+                fields
+                    .into_iter()
+                    .map(|(field, value)| (field.as_str(), json_string(value))),
+            )
+        }
+        StoreData::SortedSet(members) => json_array(members.iter().map(|(member, score)| {
+            json_array([json_string(member), json_number(*score)].into_iter())
+        })),
+        StoreData::Stream(stream) => json_stream(stream),
+        StoreData::Module(module) => json_module(module),
+    }
+}
+
+fn json_stream(stream: &StoreStream) -> String {
+    let entries = json_array(stream.entries.iter().map(|(id, fields)| {
+        json_object(
+            [
+                ("id", json_string(&id.to_string())),
+                (
+                    "fields",
+                    json_object(
+                        fields
+                            .iter()
+                            .map(|(field, value)| (field.as_str(), json_string(value))),
+                    ),
+                ),
+            ]
+            .into_iter(),
+        )
+    }));
+    let groups = json_array(stream.groups.iter().map(|group| {
+        json_object(
+            [
+                ("name", json_string(&group.name)),
+                ("last_id", json_string(&group.last_id.to_string())),
+                ("pending", group.pending.len().to_string()),
+                ("consumers", group.consumers.len().to_string()),
+            ]
+            .into_iter(),
+        )
+    }));
+
+    json_object(
+        [
+            ("entries", entries),
+            ("last_id", json_string(&stream.last_id.to_string())),
+            ("entries_added", stream.entries_added.to_string()),
+            ("groups", groups),
+        ]
+        .into_iter(),
+    )
+}
+
+// The fields of module values can only be read by the module, so only the module is shown.
+fn json_module(module: &StoreModuleValue) -> String {
+    json_object(
+        [
+            ("module", json_string(&module.name)),
+            ("id", module.id.to_string()),
+            ("fields", module.fields.len().to_string()),
+        ]
+        .into_iter(),
+    )
+}
+
+fn json_array(values: impl Iterator<Item = String>) -> String {
+    format!("[{}]", values.collect::<Vec<String>>().join(","))
+}
+
+fn json_object<'a>(fields: impl Iterator<Item = (&'a str, String)>) -> String {
+    let fields: Vec<String> = fields
+        .map(|(name, value)| format!("{}:{}", json_string(name), value))
+        .collect();
+
+    format!("{{{}}}", fields.join(","))
+}
+
+// JSON has no infinite numbers, which are valid scores, so they are written the same as Redis replies them.
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else if value.is_nan() {
+        json_string("nan")
+    } else if value > 0.0 {
+        json_string("inf")
+    } else {
+        json_string("-inf")
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+
+    use chrono::{Duration, TimeZone};
+
+    use codecrafters_redis::rdb::encoder::RdbFileEncoder;
+
+    use super::*;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    fn value(data: StoreData, exp: Option<DateTime<Utc>>) -> StoreValue {
+        StoreValue { value: data, exp }
+    }
+
+    fn string(value: &str) -> StoreData {
+        StoreData::String(value.to_string())
+    }
+
+    // Writes an RDB file with the keys of every database, in a directory removed when the test starts.
+    fn rdb_file(name: &str, databases: &[(usize, Vec<(&str, StoreValue)>)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rdb-inspect-{}-{}", std::process::id(), name));
+        let mut encoder = RdbFileEncoder::new(vec![]);
+
+        encoder.encode_header().unwrap();
+        encoder.encode_metadata("redis-ver", "7.4.0").unwrap();
+
+        for (index, keys) in databases {
+            encoder.encode_database(*index, keys.len(), 0).unwrap();
+
+            for (key, value) in keys {
+                encoder.encode_key_value(key, value).unwrap();
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("dump.rdb"), encoder.finish().unwrap()).unwrap();
+
+        dir.join("dump.rdb")
+    }
+
+    #[tokio::test]
+    async fn validate_a_valid_file() {
+        let path = rdb_file("valid", &[(0, vec![("a", value(string("1"), None))])]);
+
+        assert_eq!(validate(&path).await.unwrap(), ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn validate_a_corrupted_file() {
+        let path = rdb_file("corrupted", &[(0, vec![("a", value(string("1"), None))])]);
+        let mut content = std::fs::read(&path).unwrap();
+        let last = content.len() - 1;
+
+        content[last] ^= 0xff;
+        std::fs::write(&path, &content).unwrap();
+
+        assert_eq!(validate(&path).await.unwrap(), ExitCode::FAILURE);
+        assert_eq!(
+            validate(&path.with_file_name("missing.rdb")).await.unwrap(),
+            ExitCode::FAILURE
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_counts_the_bytes_after_the_end_of_the_file() {
+        let path = rdb_file("trailing", &[]);
+        let mut content = std::fs::read(&path).unwrap();
+
+        content.extend(b"REDIS");
+        std::fs::write(&path, &content).unwrap();
+
+        let (rdb_data, trailing) = decode(&path).await.unwrap();
+
+        assert_eq!(trailing, 5);
+        assert_eq!(rdb_data.header.version, "0011");
+        assert_eq!(validate(&path).await.unwrap(), ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn keys_are_sorted_by_database_and_name() {
+        let path = rdb_file(
+            "sorted",
+            &[
+                (
+                    3,
+                    vec![
+                        ("b", value(string("1"), None)),
+                        ("a", value(string("2"), None)),
+                    ],
+                ),
+                (1, vec![("c", value(string("3"), None))]),
+            ],
+        );
+        let rdb_data = decode(&path).await.unwrap().0;
+        let keys: Vec<(usize, &str)> = sorted_keys(&rdb_data)
+            .into_iter()
+            .map(|(index, key, _)| (index, key.as_str()))
+            .collect();
+
+        assert_eq!(keys, vec![(1, "c"), (3, "a"), (3, "b")]);
+    }
+
+    #[test]
+    fn json_of_every_type() {
+        assert_eq!(
+            json_value(&string("a\"b\\c\n\u{1}")),
+            "\"a\\\"b\\\\c\\n\\u0001\""
+        );
+        assert_eq!(
+            json_value(&StoreData::List(VecDeque::from([
+                String::from("b"),
+                String::from("a"),
+            ]))),
+            "[\"b\",\"a\"]"
+        );
+        assert_eq!(
+            json_value(&StoreData::Set(HashSet::from([
+                String::from("b"),
+                String::from("a"),
+            ]))),
+            "[\"a\",\"b\"]"
+        );
+        assert_eq!(
+            json_value(&StoreData::Hash(HashMap::from([
+                (String::from("y"), String::from("2")),
+                (String::from("x"), String::from("1")),
+            ]))),
+            "{\"x\":\"1\",\"y\":\"2\"}"
+        );
+        assert_eq!(
+            json_value(&StoreData::SortedSet(vec![
+                (String::from("a"), 1.5),
+                (String::from("b"), f64::INFINITY),
+            ])),
+            "[[\"a\",1.5],[\"b\",\"inf\"]]"
+        );
+        assert_eq!(
+            json_value(&StoreData::Stream(StoreStream::default())),
+            "{\"entries\":[],\"last_id\":\"0-0\",\"entries_added\":0,\"groups\":[]}"
+        );
+        assert_eq!(
+            json_value(&StoreData::Module(StoreModuleValue {
+                id: 1,
+                name: String::from("mymodule1"),
+                fields: vec![],
+            })),
+            "{\"module\":\"mymodule1\",\"id\":1,\"fields\":0}"
+        );
+    }
+
+    #[test]
+    fn json_numbers() {
+        assert_eq!(json_number(2.0), "2");
+        assert_eq!(json_number(-0.25), "-0.25");
+        assert_eq!(json_number(f64::NEG_INFINITY), "\"-inf\"");
+        assert_eq!(json_number(f64::NAN), "\"nan\"");
+    }
+
+    #[test]
+    fn expiry_ranges() {
+        let now = time(1_000_000);
+        let ranges: Vec<&str> = [
+            Some(now - Duration::seconds(1)),
+            Some(now),
+            Some(now + Duration::minutes(1)),
+            Some(now + Duration::hours(1)),
+            Some(now + Duration::days(1)),
+            Some(now + Duration::days(7)),
+            None,
+        ]
+        .into_iter()
+        .map(|exp| expiry_range_name(expiry_range(exp, now)))
+        .collect();
+
+        assert_eq!(
+            ranges,
+            vec![
+                "expired",
+                "< 1m",
+                "< 1h",
+                "< 1d",
+                "< 7d",
+                ">= 7d",
+                "no expiry"
+            ]
+        );
+    }
+
+    #[test]
+    fn elements_of_every_type() {
+        assert_eq!(elements(&string("abc")), 3);
+        assert_eq!(
+            elements(&StoreData::SortedSet(vec![
+                (String::from("a"), 1.0),
+                (String::from("b"), 2.0),
+            ])),
+            2
+        );
+        assert_eq!(elements(&StoreData::Stream(StoreStream::default())), 0);
+    }
+
+    #[tokio::test]
+    async fn commands_select_every_database_but_the_first() {
+        let path = rdb_file(
+            "commands",
+            &[
+                (2, vec![("c", value(string("3"), None))]),
+                (
+                    0,
+                    vec![
+                        ("b", value(string("2"), None)),
+                        ("a", value(string("1"), None)),
+                    ],
+                ),
+            ],
+        );
+        let rdb_data = decode(&path).await.unwrap().0;
+
+        assert_eq!(
+            commands(&rdb_data),
+            vec![
+                vec!["SET", "a", "1"],
+                vec!["SET", "b", "2"],
+                vec!["SELECT", "2"],
+                vec!["SET", "c", "3"],
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_commands_in_resp() {
+        assert_eq!(
+            encode_command(vec![String::from("SET"), String::from("a"), String::new()]),
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn digest_ignores_the_order_of_sets_and_hashes() {
+        let set = |members: &[&str]| {
+            value(
+                StoreData::Set(members.iter().map(|member| member.to_string()).collect()),
+                None,
+            )
+        };
+        let digest = KeyDigest::new(&set(&["a", "b", "c"]));
+
+        assert_eq!(digest.type_name, "set");
+        assert_eq!(KeyDigest::new(&set(&["c", "a", "b"])).digest, digest.digest);
+        assert_ne!(KeyDigest::new(&set(&["a", "b"])).digest, digest.digest);
+    }
+
+    #[tokio::test]
+    async fn diff_of_the_same_keys() {
+        let keys = vec![
+            ("a", value(string("1"), Some(time(2_000_000_000)))),
+            (
+                "b",
+                value(
+                    StoreData::Hash(HashMap::from([
+                        (String::from("x"), String::from("1")),
+                        (String::from("y"), String::from("2")),
+                    ])),
+                    None,
+                ),
+            ),
+        ];
+        let old = rdb_file("diff-same-old", &[(0, keys.clone())]);
+        let new = rdb_file("diff-same-new", &[(0, keys.into_iter().rev().collect())]);
+
+        assert_eq!(diff(&old, &new).await.unwrap(), ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn diff_of_different_keys() {
+        let old = rdb_file("diff-old", &[(0, vec![("a", value(string("1"), None))])]);
+
+        for (name, keys) in [
+            (
+                "added",
+                vec![
+                    ("a", value(string("1"), None)),
+                    ("b", value(string("2"), None)),
+                ],
+            ),
+            ("removed", vec![]),
+            ("changed", vec![("a", value(string("2"), None))]),
+            (
+                "type-changed",
+                vec![(
+                    "a",
+                    value(StoreData::List(VecDeque::from([String::from("1")])), None),
+                )],
+            ),
+            (
+                "ttl-changed",
+                vec![("a", value(string("1"), Some(time(2_000_000_000))))],
+            ),
+        ] {
+            let new = rdb_file(&format!("diff-{}", name), &[(0, keys)]);
+
+            assert_eq!(
+                diff(&old, &new).await.unwrap(),
+                ExitCode::FAILURE,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn diff_compares_the_keys_of_each_database() {
+        let old = rdb_file("diff-db-old", &[(0, vec![("a", value(string("1"), None))])]);
+        let new = rdb_file("diff-db-new", &[(1, vec![("a", value(string("1"), None))])]);
+
+        assert_eq!(diff(&old, &new).await.unwrap(), ExitCode::FAILURE);
+    }
+
+    #[tokio::test]
+    async fn diff_of_a_file_that_is_not_valid() {
+        let old = rdb_file("diff-valid", &[]);
+
+        assert!(diff(&old, &old.with_file_name("missing.rdb"))
+            .await
+            .is_err());
+    }
+}
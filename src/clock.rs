@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// The source of the current time for everything that depends on it (expirations, idle times...).
+///
+/// Every clock can be moved forward (or backward), so expirations can be tested without waiting for them.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> DateTime<Utc>;
+    fn advance(&self, duration: Duration);
+}
+
+/// The clock used by default. It follows the system time, plus the time that it has been advanced.
+#[derive(Debug, Default)]
+pub struct SystemClock {
+    offset_in_milliseconds: AtomicI64,
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + Duration::milliseconds(self.offset_in_milliseconds.load(Ordering::Relaxed))
+    }
+
+    fn advance(&self, duration: Duration) {
+        self.offset_in_milliseconds
+            .fetch_add(duration.num_milliseconds(), Ordering::Relaxed);
+    }
+}
+
+/// A clock that only moves when it is told to. Useful for tests that depend on the current time.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(|err| err.into_inner()) = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|err| err.into_inner());
+
+        *now += duration;
+    }
+}
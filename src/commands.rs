@@ -1,14 +1,35 @@
+use std::future::Future;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use tokio::io::{self, AsyncWriteExt};
+use std::sync::{Arc, OnceLock};
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::runtime::{Handle, RuntimeFlavor};
 
 use chrono::{DateTime, Duration, Utc};
 
+use crate::aof::writer::AofWriter;
+use crate::connections::migrate::MigrateConnection;
+use crate::rdb::dump::{dump_value, restore_value};
+use crate::rdb::save::RdbSaver;
+use crate::rdb::sync::{RdbLoadMode, RdbLoadingProgress, RdbSync};
 use crate::resp::data_types::{RespDataType, RespEncoder};
 use crate::resp::reader::RespReader;
 use crate::server::{ServerConfig, ServerInfo};
-use crate::store::{Store, StoreValueBuilder};
+use crate::store::{MaxMemoryPolicy, Store, StoreData, StoreError, StoreValue, StoreValueBuilder};
+
+/// The reply of the commands that are used with a key holding a value of a different type.
+const WRONG_TYPE_ERROR: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+/// The reply of RESTORE when the key exists and it is not replaced.
+const BUSYKEY_ERROR: &str = "BUSYKEY Target key name already exists.";
+/// The reply of the commands that need the dataset while it is being loaded.
+const LOADING_ERROR: &str = "LOADING Redis is loading the dataset in memory";
+/// Commands that do not need the dataset, so they can be executed while it is being loaded.
+const LOADING_ALLOWED_COMMANDS: [&str; 7] = [
+    "INFO", "CONFIG", "ECHO", "LASTSAVE", "REPLCONF", "SELECT", "AUTH",
+];
+/// The timeout of MIGRATE when it is not a positive number of milliseconds, the same as Redis.
+const DEFAULT_MIGRATE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1000);
 
 #[derive(Debug)]
 pub enum CommandError {
@@ -59,36 +80,64 @@ impl std::fmt::Display for CommandError {
 
 impl std::error::Error for CommandError {}
 
-pub struct CommandWriter<'a> {
+pub struct CommandWriter<'a, S = TcpStream> {
     args: Vec<String>,
-    stream: &'a mut TcpStream,
+    // The arguments as they were received. Arguments that are not valid UTF-8 (like DUMP payloads) are only exact
+    // here, since `args` has their lossy conversion.
+    raw_args: Vec<Vec<u8>>,
+    stream: &'a mut S,
+    // Commands replayed from the AOF are executed while the dataset is being loaded, and they are not appended to
+    // the AOF again.
+    from_aof: bool,
 }
 
-impl<'a> CommandWriter<'a> {
-    pub fn new(stream: &'a mut TcpStream) -> Self {
+impl<'a, S: AsyncWrite + Unpin> CommandWriter<'a, S> {
+    pub fn new(stream: &'a mut S) -> Self {
         Self {
             args: Vec::new(),
+            raw_args: Vec::new(),
             stream,
+            from_aof: false,
+        }
+    }
+
+    /// A command read from the AOF. Its reply is written to the stream, which is usually a sink.
+    pub fn from_aof(raw_args: Vec<Vec<u8>>, stream: &'a mut S) -> Self {
+        Self {
+            args: raw_args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).to_string())
+                .collect(),
+            raw_args,
+            stream,
+            from_aof: true,
         }
     }
 
     pub fn from_resp_data_type(
         value: RespDataType,
-        stream: &'a mut TcpStream,
-    ) -> Result<CommandWriter<'a>, CommandError> {
+        stream: &'a mut S,
+    ) -> Result<CommandWriter<'a, S>, CommandError> {
         match value {
             RespDataType::Array(values) => {
-                let str_values = values
-                    .iter()
+                let raw_args = values
+                    .into_iter()
                     .filter_map(|value| match value {
-                        RespDataType::BulkString(value) => Some(value.to_string()),
+                        RespDataType::BulkString(value) => Some(value.into_bytes()),
+                        RespDataType::BinaryBulkString(value) => Some(value),
                         _ => None,
                     })
+                    .collect::<Vec<Vec<u8>>>();
+                let str_values = raw_args
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value).to_string())
                     .collect::<Vec<String>>();
 
                 Ok(CommandWriter {
                     args: str_values,
+                    raw_args,
                     stream,
+                    from_aof: false,
                 })
             }
             _ => Err(CommandError::InvalidCommand(String::from(
@@ -99,21 +148,63 @@ impl<'a> CommandWriter<'a> {
 
     pub async fn write(
         self,
-        store: Arc<Mutex<Store>>,
+        store: Arc<Store>,
         server_config: Arc<ServerConfig>,
         server_info: Arc<ServerInfo>,
+        rdb_saver: Arc<RdbSaver>,
+        rdb_sync: Arc<RdbSync>,
+        aof_writer: Arc<AofWriter>,
     ) -> Result<(), CommandError> {
         let command_name = self.get_command_name().ok_or(CommandError::EmptyCommand)?;
 
         let command: Result<Box<dyn Command>, CommandError> = match command_name.to_uppercase() {
+            name if rdb_sync.is_loading()
+                && !self.from_aof
+                && !LOADING_ALLOWED_COMMANDS
+                    .iter()
+                    .any(|allowed| name.starts_with(allowed)) =>
+            {
+                Ok(Box::new(LoadingCommand))
+            }
             name if name.starts_with("PING") => Ok(Box::new(PingCommand)),
             name if name.starts_with("ECHO") => Ok(Box::new(EchoCommand::new(self.args.clone()))),
             name if name.starts_with("SET") => {
                 Ok(Box::new(SetCommand::new(self.args.clone(), store)))
             }
+            name if name.starts_with("MSET") => {
+                Ok(Box::new(MsetCommand::new(self.args.clone(), store)))
+            }
+            name if name.starts_with("RENAME") => {
+                Ok(Box::new(RenameCommand::new(self.args.clone(), store)))
+            }
+            name if name.starts_with("DEL") => {
+                Ok(Box::new(DelCommand::new(self.args.clone(), store)))
+            }
+            name if name.starts_with("PERSIST") => {
+                Ok(Box::new(PersistCommand::new(self.args.clone(), store)))
+            }
+            name if name.starts_with("TYPE") => {
+                Ok(Box::new(TypeCommand::new(self.args.clone(), store)))
+            }
             name if name.starts_with("GET") => {
                 Ok(Box::new(GetCommand::new(self.args.clone(), store)))
             }
+            name if name.starts_with("DUMP") => {
+                Ok(Box::new(DumpCommand::new(self.args.clone(), store)))
+            }
+            name if name.starts_with("RESTORE") => {
+                Ok(Box::new(RestoreCommand::new(self.raw_args.clone(), store)))
+            }
+            name if name.starts_with("MIGRATE") => Ok(Box::new(MigrateCommand::new(
+                self.args.clone(),
+                store,
+                server_info,
+                aof_writer.clone(),
+            ))),
+            name if name.starts_with("SELECT") => {
+                Ok(Box::new(SelectCommand::new(self.args.clone())))
+            }
+            name if name.starts_with("AUTH") => Ok(Box::new(AuthCommand::new(self.args.clone()))),
             name if name.starts_with("CONFIG GET") => Ok(Box::new(ConfigGetCommand::new(
                 self.args.clone(),
                 server_config,
@@ -121,9 +212,40 @@ impl<'a> CommandWriter<'a> {
             name if name.starts_with("KEYS") => {
                 Ok(Box::new(KeysCommand::new(self.args.clone(), store)))
             }
-            name if name.starts_with("INFO") => {
-                Ok(Box::new(InfoCommand::new(self.args.clone(), server_info)))
+            name if name.starts_with("INFO") => Ok(Box::new(InfoCommand::new(
+                self.args.clone(),
+                server_info,
+                store,
+                rdb_saver,
+                rdb_sync,
+                aof_writer.clone(),
+            ))),
+            name if name.starts_with("OBJECT") => {
+                Ok(Box::new(ObjectCommand::new(self.args.clone(), store)))
+            }
+            name if name.starts_with("MEMORY") => {
+                Ok(Box::new(MemoryCommand::new(self.args.clone(), store)))
             }
+            name if name.starts_with("SAVE") => {
+                Ok(Box::new(SaveCommand::new(store, server_config, rdb_saver)))
+            }
+            name if name.starts_with("BGSAVE") => Ok(Box::new(BgsaveCommand::new(
+                store,
+                server_config,
+                rdb_saver,
+            ))),
+            name if name.starts_with("BGREWRITEAOF") => Ok(Box::new(BgrewriteaofCommand::new(
+                store,
+                aof_writer.clone(),
+            ))),
+            name if name.starts_with("LASTSAVE") => Ok(Box::new(LastsaveCommand::new(rdb_saver))),
+            name if name.starts_with("DEBUG") => Ok(Box::new(DebugCommand::new(
+                self.args.clone(),
+                store,
+                server_config,
+                rdb_saver,
+                rdb_sync,
+            ))),
             name if name.starts_with("REPLCONF") => {
                 Ok(Box::new(ReplconfCommand::new(self.args.clone())))
             }
@@ -133,25 +255,47 @@ impl<'a> CommandWriter<'a> {
             )),
         };
 
-        let buf = command?.generate_reply()?;
+        let command = command?;
+
+        let buf = if command.is_write() && !self.from_aof && aof_writer.is_enabled() {
+            // The AOF is locked while the command is executed, so commands are appended in the same order they
+            // change the store. It is not locked at all when it is disabled.
+            let mut aof = aof_writer.lock();
+            let buf = command.generate_reply()?;
+
+            // Commands that fail are not appended. A write error is reported in INFO persistence, and the command
+            // is replied anyway since the store was already changed.
+            if !buf.starts_with(b"-") {
+                let args = command
+                    .propagated_args()
+                    .unwrap_or_else(|| self.raw_args.clone());
+
+                let _ = aof.append(&args);
+            }
+
+            buf
+        } else {
+            command.generate_reply()?
+        };
 
         self.stream
-            .write_all(buf.as_bytes())
+            .write_all(&buf)
             .await
             .map_err(|err| CommandError::Reply(err.to_string()))
     }
+}
 
+impl CommandWriter<'_, TcpStream> {
     pub async fn write_request(
         &mut self,
         command: Box<dyn Command>,
     ) -> Result<Option<RespDataType>, CommandError> {
         let buf = command.generate_request()?;
 
-        let _ = self
-            .stream
-            .write_all(buf.as_bytes())
+        self.stream
+            .write_all(&buf)
             .await
-            .map_err(|err| CommandError::Reply(err.to_string()));
+            .map_err(CommandError::Request)?;
 
         let mut reader = RespReader::new(self.stream);
 
@@ -160,7 +304,9 @@ impl<'a> CommandWriter<'a> {
             .await
             .map_err(|err| CommandError::Reply(err.to_string()))
     }
+}
 
+impl<S> CommandWriter<'_, S> {
     /// The first (and sometimes also the second) bulk string in the array is the command's name.
     fn get_command_name(&self) -> Option<String> {
         match self.args.len() {
@@ -176,8 +322,16 @@ impl<'a> CommandWriter<'a> {
 }
 
 pub trait Command: Send + Sync {
-    fn generate_reply(&self) -> Result<String, CommandError>;
-    fn generate_request(&self) -> Result<String, CommandError> {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError>;
+    /// Whether the command can change the store. These commands are appended to the AOF.
+    fn is_write(&self) -> bool {
+        false
+    }
+    /// The arguments appended to the AOF once the command is executed. None means the arguments it was called with.
+    fn propagated_args(&self) -> Option<Vec<Vec<u8>>> {
+        None
+    }
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
         unimplemented!("This command does not implement a request");
     }
 }
@@ -186,19 +340,30 @@ pub trait Command: Send + Sync {
 pub struct PingCommand;
 
 impl Command for PingCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
         Ok(RespEncoder::encode(RespDataType::SimpleString(
             "PONG".to_string(),
         )))
     }
 
-    fn generate_request(&self) -> Result<String, CommandError> {
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
         Ok(RespEncoder::encode(RespDataType::Array(vec![
             RespDataType::SimpleString("PING".to_string()),
         ])))
     }
 }
 
+#[derive(Debug)]
+struct LoadingCommand;
+
+impl Command for LoadingCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(RespEncoder::encode(RespDataType::SimpleError(
+            LOADING_ERROR.to_string(),
+        )))
+    }
+}
+
 #[derive(Debug)]
 struct EchoCommand {
     args: Vec<String>,
@@ -211,7 +376,7 @@ impl EchoCommand {
 }
 
 impl Command for EchoCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
         let arg = self.args.get(1).ok_or(CommandError::InvalidFormat(
             "ECHO command is missing a value".to_string(),
         ))?;
@@ -229,7 +394,8 @@ enum SetCommandOption {
 struct SetCommandOptionParser;
 
 impl SetCommandOptionParser {
-    fn parse(args: Vec<String>) -> Result<Vec<SetCommandOption>, CommandError> {
+    // Expirations are relative to `now`, which comes from the store clock.
+    fn parse(args: Vec<String>, now: DateTime<Utc>) -> Result<Vec<SetCommandOption>, CommandError> {
         let mut options: Vec<SetCommandOption> = vec![];
         let chunks = args.chunks(2);
 
@@ -250,7 +416,25 @@ impl SetCommandOptionParser {
                         )
                     })?;
 
-                    let exp = Utc::now() + Duration::milliseconds(option_value);
+                    let exp = now + Duration::milliseconds(option_value);
+
+                    options.push(SetCommandOption::PX(exp));
+                }
+                // Absolute Unix time in milliseconds. Expirations are written to the AOF this way, so they do not
+                // depend on when the file is loaded.
+                "PXAT" => {
+                    let option_value =
+                        chunk.get(1).ok_or(CommandError::InvalidCommandOptionValue(
+                            "PXAT option must contain a number.".to_string(),
+                        ))?;
+                    let exp = option_value
+                        .parse()
+                        .ok()
+                        .and_then(DateTime::from_timestamp_millis)
+                        .ok_or(CommandError::InvalidCommandOptionValue(
+                            "PXAT option must contain a valid Unix time in milliseconds."
+                                .to_string(),
+                        ))?;
 
                     options.push(SetCommandOption::PX(exp));
                 }
@@ -269,18 +453,24 @@ impl SetCommandOptionParser {
 
 #[derive(Debug)]
 struct SetCommand {
-    store: Arc<Mutex<Store>>,
+    store: Arc<Store>,
     args: Vec<String>,
+    // The expiration set by the command, once it is executed.
+    exp: OnceLock<DateTime<Utc>>,
 }
 
 impl SetCommand {
-    fn new(args: Vec<String>, store: Arc<Mutex<Store>>) -> Self {
-        Self { args, store }
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self {
+            args,
+            store,
+            exp: OnceLock::new(),
+        }
     }
 }
 
 impl Command for SetCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
         let mut args = self.args.iter().skip(1);
         let key = args.next().ok_or(CommandError::InvalidFormat(
             "SET command must contain a key".to_string(),
@@ -295,294 +485,1861 @@ impl Command for SetCommand {
 
         store_value_builder.with_value(value);
 
-        let options = SetCommandOptionParser::parse(args)?;
+        let options = SetCommandOptionParser::parse(args, self.store.clock().now())?;
 
         for option in options {
             match option {
                 SetCommandOption::PX(exp) => {
                     store_value_builder.with_exp(exp);
+                    let _ = self.exp.set(exp);
                 }
             };
         }
 
-        let mut store = self.store.lock().map_err(|_| {
-            CommandError::Store(format!(
-                "Error when trying the set the value {} to {}",
-                value, key
-            ))
-        })?;
-
         let store_value = store_value_builder.build();
 
-        store.set(key, store_value);
+        match self.store.set(key, store_value) {
+            Ok(()) => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            ))),
+            Err(err @ StoreError::OutOfMemory) => Ok(RespEncoder::encode(
+                RespDataType::SimpleError(err.to_string()),
+            )),
+        }
+    }
 
-        Ok(RespEncoder::encode(RespDataType::SimpleString(
-            "OK".to_string(),
-        )))
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    // Relative expirations are written as absolute ones, so replaying the command later sets the same expiration.
+    fn propagated_args(&self) -> Option<Vec<Vec<u8>>> {
+        let mut args: Vec<String> = self.args.iter().take(3).cloned().collect();
+
+        if let Some(exp) = self.exp.get() {
+            args.push(String::from("PXAT"));
+            args.push(exp.timestamp_millis().to_string());
+        }
+
+        Some(args.into_iter().map(String::into_bytes).collect())
     }
 }
 
 #[derive(Debug)]
-struct GetCommand {
-    store: Arc<Mutex<Store>>,
+struct MsetCommand {
+    store: Arc<Store>,
     args: Vec<String>,
 }
 
-impl GetCommand {
-    fn new(args: Vec<String>, store: Arc<Mutex<Store>>) -> Self {
+impl MsetCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
         Self { args, store }
     }
 }
 
-impl Command for GetCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
-        let mut args = self.args.iter().skip(1);
-        let key = args.next().ok_or(CommandError::InvalidFormat(
-            "GET command must contain a key".to_string(),
-        ))?;
+impl Command for MsetCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let args: Vec<String> = self.args.iter().skip(1).cloned().collect();
+        let chunks = args.chunks_exact(2);
 
-        let store = self.store.lock().map_err(|_| {
-            CommandError::Store(format!("Error when trying the get value from {}", key))
-        })?;
-
-        match store.get(key) {
-            Some(store_value) => match store_value.exp {
-                Some(exp) => {
-                    let now = Utc::now();
-
-                    if exp < now {
-                        Ok(RespEncoder::encode(RespDataType::NullBulkString))
-                    } else {
-                        Ok(RespEncoder::encode(RespDataType::BulkString(
-                            store_value.value.clone(),
-                        )))
-                    }
-                }
-                None => Ok(RespEncoder::encode(RespDataType::BulkString(
-                    store_value.value.clone(),
-                ))),
-            },
-            None => Ok(RespEncoder::encode(RespDataType::NullBulkString)),
+        if args.is_empty() || !chunks.remainder().is_empty() {
+            return Err(CommandError::InvalidFormat(
+                "MSET command must contain pairs of keys and values".to_string(),
+            ));
+        }
+
+        let values = chunks
+            .map(|chunk| {
+                let mut store_value_builder = StoreValueBuilder::new();
+
+                store_value_builder.with_value(&chunk[1]);
+
+                (chunk[0].clone(), store_value_builder.build())
+            })
+            .collect();
+
+        match self.store.mset(values) {
+            Ok(()) => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            ))),
+            Err(err @ StoreError::OutOfMemory) => Ok(RespEncoder::encode(
+                RespDataType::SimpleError(err.to_string()),
+            )),
         }
     }
+
+    fn is_write(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
-struct ConfigGetCommand {
+struct RenameCommand {
+    store: Arc<Store>,
     args: Vec<String>,
-    server_config: Arc<ServerConfig>,
 }
 
-impl ConfigGetCommand {
-    fn new(args: Vec<String>, server_config: Arc<ServerConfig>) -> Self {
-        Self {
-            args,
-            server_config,
-        }
+impl RenameCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
     }
 }
 
-impl Command for ConfigGetCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
-        let mut args = self.args.iter().skip(2);
-        let config_key = args.next().ok_or(CommandError::InvalidFormat(
-            "CONFIG GET command must contain at least one configuration key".to_string(),
+impl Command for RenameCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let mut args = self.args.iter().skip(1);
+        let key = args.next().ok_or(CommandError::InvalidFormat(
+            "RENAME command must contain a key".to_string(),
+        ))?;
+        let new_key = args.next().ok_or(CommandError::InvalidFormat(
+            "RENAME command must contain a new key".to_string(),
         ))?;
 
-        let config_value = match config_key.as_str() {
-            "dir" => self.server_config.dir.clone(),
-            "dbfilename" => self.server_config.dbfilename.clone(),
-            _ => None,
-        };
-
-        match config_value {
-            Some(value) => Ok(RespEncoder::encode(RespDataType::Array(vec![
-                RespDataType::BulkString(config_key.to_string()),
-                RespDataType::BulkString(value.to_string_lossy().to_string()),
-            ]))),
-            None => Ok(RespEncoder::encode(RespDataType::NullBulkString)),
+        if self.store.rename(key, new_key) {
+            Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            )))
+        } else {
+            Ok(RespEncoder::encode(RespDataType::SimpleError(
+                "ERR no such key".to_string(),
+            )))
         }
     }
+
+    fn is_write(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
-struct KeysCommand {
+struct GetCommand {
+    store: Arc<Store>,
     args: Vec<String>,
-    store: Arc<Mutex<Store>>,
 }
 
-impl KeysCommand {
-    fn new(args: Vec<String>, store: Arc<Mutex<Store>>) -> Self {
+impl GetCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
         Self { args, store }
     }
 }
 
-impl Command for KeysCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
-        let pattern = self.args.get(1).ok_or(CommandError::InvalidFormat(
-            "Args command is missing a value".to_string(),
+impl Command for GetCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let mut args = self.args.iter().skip(1);
+        let key = args.next().ok_or(CommandError::InvalidFormat(
+            "GET command must contain a key".to_string(),
         ))?;
 
-        match pattern.as_str() {
-            "*" => {
-                let store = self.store.lock().map_err(|_| {
-                    CommandError::Store("Error when trying lock store for getting keys".to_string())
-                })?;
-
-                let keys = store.get_all_keys();
-
-                Ok(RespEncoder::encode(RespDataType::Array(
-                    keys.into_iter().map(RespDataType::BulkString).collect(),
-                )))
-            }
-            _ => {
-                unimplemented!("At the moment, keys only supports * as argument");
+        match self.store.get(key).map(|store_value| store_value.value) {
+            Some(StoreData::String(value)) => {
+                Ok(RespEncoder::encode(RespDataType::BulkString(value)))
             }
+            Some(_) => Ok(RespEncoder::encode(RespDataType::SimpleError(
+                WRONG_TYPE_ERROR.to_string(),
+            ))),
+            None => Ok(RespEncoder::encode(RespDataType::NullBulkString)),
         }
     }
 }
 
 #[derive(Debug)]
-enum InfoSection {
-    Replication,
+struct DelCommand {
+    store: Arc<Store>,
+    args: Vec<String>,
 }
 
-impl FromStr for InfoSection {
-    type Err = CommandError;
+impl DelCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
+    }
+}
 
-    fn from_str(arg: &str) -> Result<Self, Self::Err> {
-        match arg {
-            "replication" => Ok(InfoSection::Replication),
-            value => Err(CommandError::InvalidInfoArg(format!(
-                "Info section {} is not supported",
-                value
-            ))),
+impl Command for DelCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let keys: Vec<&String> = self.args.iter().skip(1).collect();
+
+        if keys.is_empty() {
+            return Err(CommandError::InvalidFormat(
+                "DEL command must contain at least one key".to_string(),
+            ));
         }
+
+        let removed = keys
+            .into_iter()
+            .filter(|key| self.store.remove(key).is_some())
+            .count();
+
+        Ok(RespEncoder::encode(RespDataType::Integer(removed as i64)))
+    }
+
+    fn is_write(&self) -> bool {
+        true
     }
 }
 
 #[derive(Debug)]
-struct ServerInfoFormatter {
-    info: Arc<ServerInfo>,
+struct PersistCommand {
+    store: Arc<Store>,
+    args: Vec<String>,
 }
 
-impl ServerInfoFormatter {
-    fn new(info: Arc<ServerInfo>) -> Self {
-        Self { info }
+impl PersistCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
     }
 }
 
-impl std::fmt::Display for ServerInfoFormatter {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut info_stringify = String::new();
+impl Command for PersistCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let key = self.args.get(1).ok_or(CommandError::InvalidFormat(
+            "PERSIST command must contain a key".to_string(),
+        ))?;
 
-        info_stringify.push_str(format!("{}:{}\n", "role", self.info.role).as_str());
-        info_stringify.push_str(format!("{}:{}\n", "master_replid", self.info.id).as_str());
-        info_stringify
-            .push_str(format!("{}:{}\n", "master_repl_offset", self.info.offset).as_str());
+        let persisted = self.store.persist(key);
 
-        write!(f, "{}", info_stringify)
+        Ok(RespEncoder::encode(RespDataType::Integer(i64::from(
+            persisted,
+        ))))
+    }
+
+    fn is_write(&self) -> bool {
+        true
     }
 }
 
 #[derive(Debug)]
-struct InfoCommand {
+struct TypeCommand {
+    store: Arc<Store>,
     args: Vec<String>,
-    info: Arc<ServerInfo>,
 }
 
-impl InfoCommand {
-    fn new(args: Vec<String>, info: Arc<ServerInfo>) -> Self {
-        Self { args, info }
+impl TypeCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
     }
 }
 
-impl Command for InfoCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
-        let section_name = self.args.get(1).ok_or(CommandError::InvalidFormat(
-            "Section name is missing".to_string(),
+impl Command for TypeCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let key = self.args.get(1).ok_or(CommandError::InvalidFormat(
+            "TYPE command must contain a key".to_string(),
         ))?;
-        let section = InfoSection::from_str(section_name.as_str())?;
 
-        match section {
-            InfoSection::Replication => {
-                let info = ServerInfoFormatter::new(self.info.clone());
+        let type_name = match self.store.peek(key) {
+            Some(store_value) => store_value.value.type_name().to_string(),
+            None => String::from("none"),
+        };
 
-                Ok(RespEncoder::encode(RespDataType::BulkString(
-                    info.to_string(),
-                )))
-            }
-            _ => {
-                unimplemented!("At the moment, keys only supports * as argument");
-            }
-        }
+        Ok(RespEncoder::encode(RespDataType::SimpleString(type_name)))
     }
 }
 
 #[derive(Debug)]
-pub struct ReplconfCommand {
+struct DumpCommand {
+    store: Arc<Store>,
     args: Vec<String>,
 }
 
-impl ReplconfCommand {
-    pub fn new(args: Vec<String>) -> Self {
-        Self { args }
+impl DumpCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
     }
 }
 
-impl Command for ReplconfCommand {
-    fn generate_reply(&self) -> Result<String, CommandError> {
-        Ok(RespEncoder::encode(RespDataType::SimpleString(
-            "OK".to_string(),
-        )))
-    }
-
-    fn generate_request(&self) -> Result<String, CommandError> {
+impl Command for DumpCommand {
+    // The payload is the same that Redis creates, so it can be restored by Redis too.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
         let key = self.args.get(1).ok_or(CommandError::InvalidFormat(
-            "ReplconfCommand command is missing a key".to_string(),
-        ))?;
-        let value = self.args.get(2).ok_or(CommandError::InvalidFormat(
-            "ReplconfCommand command is missing a value".to_string(),
+            "DUMP command must contain a key".to_string(),
         ))?;
 
-        Ok(RespEncoder::encode(RespDataType::Array(vec![
-            RespDataType::BulkString("REPLCONF".to_string()),
-            RespDataType::BulkString(key.to_string()),
-            RespDataType::BulkString(value.to_string()),
-        ])))
+        let store_value = match self.store.get(key) {
+            Some(store_value) => store_value,
+            None => return Ok(RespEncoder::encode(RespDataType::NullBulkString)),
+        };
+
+        match dump_value(&store_value.value) {
+            Ok(payload) => Ok(RespEncoder::encode(RespDataType::BinaryBulkString(payload))),
+            Err(err) => Ok(RespEncoder::encode(RespDataType::SimpleError(format!(
+                "ERR {}",
+                err
+            )))),
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct PsyncCommand {
-    info: Arc<ServerInfo>,
+struct RestoreCommand {
+    store: Arc<Store>,
+    args: Vec<String>,
+    // The payload is binary, so the arguments are also kept as they were received.
+    raw_args: Vec<Vec<u8>>,
+    // The arguments appended to the AOF, once the command is executed.
+    propagated_args: OnceLock<Vec<Vec<u8>>>,
 }
 
-impl PsyncCommand {
-    pub fn new(info: Arc<ServerInfo>) -> Self {
-        Self { info }
+impl RestoreCommand {
+    fn new(raw_args: Vec<Vec<u8>>, store: Arc<Store>) -> Self {
+        Self {
+            args: raw_args
+                .iter()
+                .map(|arg| String::from_utf8_lossy(arg).to_string())
+                .collect(),
+            raw_args,
+            store,
+            propagated_args: OnceLock::new(),
+        }
     }
 }
 
-impl Command for PsyncCommand {
-    // The master needs to respond with +FULLRESYNC <REPL_ID> 0\r\n ("FULLRESYNC 0" encoded as a RESP Simple String). Here's what the response means:
-    //
-    //  - FULLRESYNC means that the master cannot perform incremental replication with the replica, and will thus start a "full" resynchronization.
-    //  - <REPL_ID> is the replication ID of the master. You've already set this in the "Replication ID & Offset" stage.
-    //      - As an example, you can hardcode 8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb as the replication ID.
-    //  - 0 is the replication offset of the master.
-    //      - You've already set this in the "Replication ID & Offset" stage.
-    fn generate_reply(&self) -> Result<String, CommandError> {
-        Ok(RespEncoder::encode(RespDataType::SimpleString(format!(
-            "FULLRESYNC {} {}",
-            self.info.id, self.info.offset
-        ))))
+struct RestoreOptions {
+    ttl: i64,
+    replace: bool,
+    absttl: bool,
+    idle_time: Option<Duration>,
+    lfu_counter: Option<u8>,
+}
+
+impl RestoreOptions {
+    // Invalid options are replied as an error, with the same messages as Redis. The key and the payload are only
+    // checked to be present.
+    fn parse(args: &[String]) -> Result<Self, String> {
+        if args.len() < 4 {
+            return Err(String::from(
+                "ERR wrong number of arguments for 'restore' command",
+            ));
+        }
+
+        let mut options = RestoreOptions {
+            ttl: args[2]
+                .parse()
+                .ok()
+                .filter(|ttl| *ttl >= 0)
+                .ok_or(String::from("ERR Invalid TTL value, must be >= 0"))?,
+            replace: false,
+            absttl: false,
+            idle_time: None,
+            lfu_counter: None,
+        };
+        let mut args = args.iter().skip(4);
+
+        while let Some(option_name) = args.next() {
+            match option_name.to_uppercase().as_str() {
+                "REPLACE" => options.replace = true,
+                "ABSTTL" => options.absttl = true,
+                // The idle time is used by the LRU policies and the frequency by the LFU ones, so only one of them
+                // makes sense.
+                "IDLETIME" if options.lfu_counter.is_none() => {
+                    let seconds = args
+                        .next()
+                        .ok_or(String::from("ERR syntax error"))?
+                        .parse::<i64>()
+                        .ok()
+                        .filter(|seconds| *seconds >= 0)
+                        .ok_or(String::from("ERR Invalid IDLETIME value, must be >= 0"))?;
+
+                    options.idle_time = Some(Duration::seconds(seconds));
+                }
+                "FREQ" if options.idle_time.is_none() => {
+                    let frequency = args
+                        .next()
+                        .ok_or(String::from("ERR syntax error"))?
+                        .parse::<u8>()
+                        .map_err(|_| {
+                            String::from("ERR Invalid FREQ value, must be >= 0 and <= 255")
+                        })?;
+
+                    options.lfu_counter = Some(frequency);
+                }
+                _ => return Err(String::from("ERR syntax error")),
+            }
+        }
+
+        Ok(options)
     }
+}
 
-    // The PSYNC command is used to synchronize the state of the replica with the master. The replica will send this command to the master with two arguments:
-    // The first argument is the replication ID of the master.
-    // The second argument is the offset of the master.
-    fn generate_request(&self) -> Result<String, CommandError> {
+impl Command for RestoreCommand {
+    // RESTORE key ttl payload [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+    //
+    // The TTL is in milliseconds, and 0 means that the key does not expire. With ABSTTL, it is a Unix time in
+    // milliseconds instead.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let RestoreOptions {
+            ttl,
+            replace,
+            absttl,
+            idle_time,
+            lfu_counter,
+        } = match RestoreOptions::parse(&self.args) {
+            Ok(options) => options,
+            Err(err) => return Ok(RespEncoder::encode(RespDataType::SimpleError(err))),
+        };
+        let key = &self.args[1];
+        let payload = &self.raw_args[3];
+        let data = match restore_value(payload) {
+            Ok(data) => data,
+            Err(err) => {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(format!(
+                    "ERR {}",
+                    err
+                ))))
+            }
+        };
+
+        let now = self.store.clock().now();
+        let exp = match ttl {
+            0 => None,
+            ttl if absttl => DateTime::from_timestamp_millis(ttl),
+            ttl => now.checked_add_signed(Duration::milliseconds(ttl)),
+        };
+
+        if ttl != 0 && exp.is_none() {
+            return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                String::from("ERR Invalid TTL value, it is out of range"),
+            )));
+        }
+
+        // A key that has already expired is not restored, but the key it replaces is removed anyway.
+        if exp.is_some_and(|exp| exp <= now) {
+            if replace {
+                self.store.remove(key);
+            } else if self.store.peek(key).is_some() {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                    BUSYKEY_ERROR.to_string(),
+                )));
+            }
+
+            let _ = self
+                .propagated_args
+                .set(vec![b"DEL".to_vec(), key.as_bytes().to_vec()]);
+
+            return Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            )));
+        }
+
+        let mut store_value_builder = StoreValueBuilder::new();
+
+        store_value_builder.with_data(data);
+
+        if let Some(exp) = exp {
+            store_value_builder.with_exp(exp);
+        }
+
+        match self.store.restore(
+            key,
+            store_value_builder.build(),
+            replace,
+            idle_time,
+            lfu_counter,
+        ) {
+            Ok(true) => {}
+            Ok(false) => {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                    BUSYKEY_ERROR.to_string(),
+                )))
+            }
+            Err(err) => {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                    err.to_string(),
+                )))
+            }
+        }
+
+        // The expiration is written as an absolute one, so replaying the command later sets the same expiration.
+        let mut propagated_args = vec![
+            b"RESTORE".to_vec(),
+            self.raw_args[1].clone(),
+            exp.map(|exp| exp.timestamp_millis())
+                .unwrap_or(0)
+                .to_string()
+                .into_bytes(),
+            payload.clone(),
+            b"REPLACE".to_vec(),
+            b"ABSTTL".to_vec(),
+        ];
+
+        if let Some(idle_time) = idle_time {
+            propagated_args.push(b"IDLETIME".to_vec());
+            propagated_args.push(idle_time.num_seconds().to_string().into_bytes());
+        }
+
+        if let Some(lfu_counter) = lfu_counter {
+            propagated_args.push(b"FREQ".to_vec());
+            propagated_args.push(lfu_counter.to_string().into_bytes());
+        }
+
+        let _ = self.propagated_args.set(propagated_args);
+
+        Ok(RespEncoder::encode(RespDataType::SimpleString(
+            "OK".to_string(),
+        )))
+    }
+
+    fn is_write(&self) -> bool {
+        true
+    }
+
+    fn propagated_args(&self) -> Option<Vec<Vec<u8>>> {
+        self.propagated_args.get().cloned()
+    }
+
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(encode_request(&self.raw_args))
+    }
+}
+
+// The options of MIGRATE host port key|"" db timeout [COPY] [REPLACE] [AUTH password] [AUTH2 username password]
+// [KEYS key...]
+#[derive(Debug)]
+struct MigrateOptions {
+    address: String,
+    keys: Vec<String>,
+    db: usize,
+    timeout: std::time::Duration,
+    copy: bool,
+    replace: bool,
+    // The arguments of the AUTH command sent before the keys.
+    auth: Option<Vec<String>>,
+}
+
+impl MigrateOptions {
+    // Invalid options are replied as an error, with the same messages as Redis.
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut args = args.iter().skip(1);
+        let mut next = || {
+            args.next().cloned().ok_or(String::from(
+                "ERR wrong number of arguments for 'migrate' command",
+            ))
+        };
+        let not_an_integer = |_| String::from("ERR value is not an integer or out of range");
+        let host = next()?;
+        let port: u16 = next()?.parse().map_err(not_an_integer)?;
+        let key = next()?;
+        let db = next()?.parse().map_err(not_an_integer)?;
+        let timeout: i64 = next()?.parse().map_err(not_an_integer)?;
+        let mut options = MigrateOptions {
+            address: format!("{}:{}", host, port),
+            keys: vec![key],
+            db,
+            timeout: u64::try_from(timeout)
+                .ok()
+                .filter(|timeout| *timeout > 0)
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(DEFAULT_MIGRATE_TIMEOUT),
+            copy: false,
+            replace: false,
+            auth: None,
+        };
+
+        while let Some(option_name) = args.next() {
+            match option_name.to_uppercase().as_str() {
+                "COPY" => options.copy = true,
+                "REPLACE" => options.replace = true,
+                "AUTH" => {
+                    let password = args.next().ok_or(String::from("ERR syntax error"))?;
+
+                    options.auth = Some(vec![String::from("AUTH"), password.clone()]);
+                }
+                "AUTH2" => {
+                    let (username, password) = args
+                        .next()
+                        .zip(args.next())
+                        .ok_or(String::from("ERR syntax error"))?;
+
+                    options.auth = Some(vec![
+                        String::from("AUTH"),
+                        username.clone(),
+                        password.clone(),
+                    ]);
+                }
+                // The rest of arguments are the keys.
+                "KEYS" => {
+                    if !options.keys[0].is_empty() {
+                        return Err(String::from(
+                            "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                        ));
+                    }
+
+                    options.keys = args.by_ref().cloned().collect();
+                }
+                _ => return Err(String::from("ERR syntax error")),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+#[derive(Debug)]
+struct MigrateCommand {
+    store: Arc<Store>,
+    args: Vec<String>,
+    server_info: Arc<ServerInfo>,
+    aof_writer: Arc<AofWriter>,
+}
+
+impl MigrateCommand {
+    fn new(
+        args: Vec<String>,
+        store: Arc<Store>,
+        server_info: Arc<ServerInfo>,
+        aof_writer: Arc<AofWriter>,
+    ) -> Self {
+        Self {
+            store,
+            args,
+            server_info,
+            aof_writer,
+        }
+    }
+
+    // Sends the keys over a cached connection. A cached connection may have been closed by the other server since it
+    // was used, so it is retried once with a new connection when it fails before any key is restored.
+    //
+    // It returns the error of the command when there is one. The keys restored by the other server are added to
+    // `restored` as they are restored, so they are known even when the migration is cancelled.
+    async fn migrate(
+        &self,
+        options: &MigrateOptions,
+        values: &[(String, StoreValue)],
+        restored: &mut Vec<String>,
+    ) -> Option<String> {
+        let connections = &self.server_info.migrate_connections;
+        let mut retried = false;
+
+        loop {
+            let mut connection =
+                match tokio::time::timeout(options.timeout, connections.take(&options.address))
+                    .await
+                {
+                    Ok(Ok(connection)) => connection,
+                    _ => {
+                        return Some(format!(
+                            "IOERR error or timeout connecting to {}",
+                            options.address
+                        ))
+                    }
+                };
+
+            match self
+                .send_values(&mut connection, options, values, restored)
+                .await
+            {
+                Ok(error) => {
+                    connections.put(&options.address, connection);
+
+                    return error;
+                }
+                Err(_) if connection.cached && restored.is_empty() && !retried => retried = true,
+                Err(err) => {
+                    return Some(format!(
+                        "IOERR error or timeout reading from {}: {}",
+                        options.address, err
+                    ))
+                }
+            }
+        }
+    }
+
+    // The keys are restored one by one, so every key is only deleted once the other server has it. It fails when the
+    // connection fails, and it returns the error replied by the other server otherwise.
+    async fn send_values(
+        &self,
+        connection: &mut MigrateConnection,
+        options: &MigrateOptions,
+        values: &[(String, StoreValue)],
+        restored: &mut Vec<String>,
+    ) -> Result<Option<String>, CommandError> {
+        let mut writer = CommandWriter::new(&mut connection.stream);
+        let mut error = None;
+
+        if let Some(auth) = &options.auth {
+            let reply = send_request(&mut writer, AuthCommand::new(auth.clone()), options).await?;
+
+            if let RespDataType::SimpleError(err) = reply {
+                return Ok(Some(target_error(&err)));
+            }
+        }
+
+        if connection.db != Some(options.db) {
+            let select = SelectCommand::new(vec![String::from("SELECT"), options.db.to_string()]);
+
+            if let RespDataType::SimpleError(err) =
+                send_request(&mut writer, select, options).await?
+            {
+                return Ok(Some(target_error(&err)));
+            }
+
+            connection.db = Some(options.db);
+        }
+
+        let now = self.store.clock().now();
+
+        for (key, value) in values {
+            let payload =
+                dump_value(&value.value).map_err(|err| CommandError::Store(err.to_string()))?;
+            // The TTL is relative, since the clocks of both servers may not be the same.
+            let ttl = value
+                .exp
+                .map(|exp| (exp - now).num_milliseconds().max(1))
+                .unwrap_or(0);
+            let mut args = vec![
+                b"RESTORE".to_vec(),
+                key.as_bytes().to_vec(),
+                ttl.to_string().into_bytes(),
+                payload,
+            ];
+
+            if options.replace {
+                args.push(b"REPLACE".to_vec());
+            }
+
+            let restore = RestoreCommand::new(args, self.store.clone());
+
+            match send_request(&mut writer, restore, options).await? {
+                RespDataType::SimpleError(err) => error = Some(target_error(&err)),
+                _ => restored.push(key.clone()),
+            }
+        }
+
+        Ok(error)
+    }
+}
+
+impl Command for MigrateCommand {
+    // The keys are sent without locking the AOF, so the writes of other clients do not wait for the other server.
+    // MIGRATE appends the keys it deletes to the AOF by itself, as a DEL.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let options = match MigrateOptions::parse(&self.args) {
+            Ok(options) => options,
+            Err(err) => return Ok(RespEncoder::encode(RespDataType::SimpleError(err))),
+        };
+
+        let values: Vec<(String, StoreValue)> = options
+            .keys
+            .iter()
+            .filter_map(|key| self.store.get(key).map(|value| (key.clone(), value)))
+            .collect();
+
+        if values.is_empty() {
+            return Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "NOKEY".to_string(),
+            )));
+        }
+
+        let mut restored = vec![];
+        // The timeout of every request also bounds the whole migration, so the thread is not blocked for longer
+        // than that, however many keys are sent.
+        let migration = tokio::time::timeout(
+            options.timeout,
+            self.migrate(&options, &values, &mut restored),
+        );
+        let error = match block_on(migration) {
+            Ok(Ok(error)) => error,
+            Ok(Err(_)) => Some(format!(
+                "IOERR error or timeout reading from {}",
+                options.address
+            )),
+            Err(err) => return Ok(RespEncoder::encode(RespDataType::SimpleError(err))),
+        };
+
+        // The keys are deleted while the AOF is locked, so the DEL is appended in the same order it changes the store.
+        // It is not locked when it is disabled.
+        let aof = self.aof_writer.is_enabled().then(|| self.aof_writer.lock());
+
+        let deleted: Vec<String> = restored
+            .into_iter()
+            .filter(|key| !options.copy && self.store.remove(key).is_some())
+            .collect();
+
+        if let Some(mut aof) = aof.filter(|_| !deleted.is_empty()) {
+            let args: Vec<String> = std::iter::once(String::from("DEL"))
+                .chain(deleted)
+                .collect();
+
+            // A write error is reported in INFO persistence, and the keys were already deleted anyway.
+            let _ = aof.append(&args);
+        }
+
+        match error {
+            Some(err) => Ok(RespEncoder::encode(RespDataType::SimpleError(err))),
+            None => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SelectCommand {
+    args: Vec<String>,
+}
+
+impl SelectCommand {
+    pub fn new(args: Vec<String>) -> Self {
+        Self { args }
+    }
+}
+
+impl Command for SelectCommand {
+    // The server only has the database 0.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let index: usize = self
+            .args
+            .get(1)
+            .and_then(|index| index.parse().ok())
+            .ok_or(CommandError::InvalidCommandOptionValue(
+                "SELECT index must be a positive number.".to_string(),
+            ))?;
+
+        match index {
+            0 => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            ))),
+            _ => Ok(RespEncoder::encode(RespDataType::SimpleError(
+                "ERR DB index is out of range".to_string(),
+            ))),
+        }
+    }
+
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(encode_request(&self.args))
+    }
+}
+
+#[derive(Debug)]
+pub struct AuthCommand {
+    args: Vec<String>,
+}
+
+impl AuthCommand {
+    pub fn new(args: Vec<String>) -> Self {
+        Self { args }
+    }
+}
+
+impl Command for AuthCommand {
+    // The server has no password, the same as a Redis server without `requirepass`.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(RespEncoder::encode(RespDataType::SimpleError(
+            "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                .to_string(),
+        )))
+    }
+
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(encode_request(&self.args))
+    }
+}
+
+// Sends a request to another server for MIGRATE, failing when it does not reply within the timeout.
+async fn send_request(
+    writer: &mut CommandWriter<'_, TcpStream>,
+    command: impl Command + 'static,
+    options: &MigrateOptions,
+) -> Result<RespDataType, CommandError> {
+    match tokio::time::timeout(options.timeout, writer.write_request(Box::new(command))).await {
+        Ok(Ok(Some(reply))) => Ok(reply),
+        Ok(Ok(None)) => Err(CommandError::Reply("the connection was closed".to_string())),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(CommandError::Reply("timeout".to_string())),
+    }
+}
+
+fn target_error(err: &str) -> String {
+    format!("ERR Target instance replied with error: {}", err)
+}
+
+fn encode_request(args: &[impl AsRef<[u8]>]) -> Vec<u8> {
+    RespEncoder::encode(RespDataType::Array(
+        args.iter()
+            .map(|arg| RespDataType::BinaryBulkString(arg.as_ref().to_vec()))
+            .collect(),
+    ))
+}
+
+// Commands are not async, so the current thread waits for the future without blocking the rest of connections. The
+// thread is taken out of the runtime while it waits, which is only possible in a multi-threaded runtime, so it fails
+// with the error reply otherwise.
+fn block_on<F: Future>(future: F) -> Result<F::Output, String> {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            Ok(tokio::task::block_in_place(|| handle.block_on(future)))
+        }
+        _ => Err(String::from(
+            "ERR this command needs a multi-threaded runtime",
+        )),
+    }
+}
+
+#[derive(Debug)]
+struct ConfigGetCommand {
+    args: Vec<String>,
+    server_config: Arc<ServerConfig>,
+}
+
+impl ConfigGetCommand {
+    fn new(args: Vec<String>, server_config: Arc<ServerConfig>) -> Self {
+        Self {
+            args,
+            server_config,
+        }
+    }
+}
+
+impl Command for ConfigGetCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let mut args = self.args.iter().skip(2);
+        let config_key = args.next().ok_or(CommandError::InvalidFormat(
+            "CONFIG GET command must contain at least one configuration key".to_string(),
+        ))?;
+
+        let config_value = match config_key.as_str() {
+            "dir" => self
+                .server_config
+                .dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().to_string()),
+            "dbfilename" => self
+                .server_config
+                .dbfilename
+                .as_ref()
+                .map(|dbfilename| dbfilename.to_string_lossy().to_string()),
+            "maxmemory" => Some(self.server_config.maxmemory.to_string()),
+            "maxmemory-policy" => Some(self.server_config.maxmemory_policy.to_string()),
+            "save" => Some(
+                self.server_config
+                    .save
+                    .iter()
+                    .map(|save_point| save_point.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            ),
+            "rdb-retention" => Some(self.server_config.rdb_retention.to_string()),
+            "appendonly" => Some(String::from(if self.server_config.appendonly {
+                "yes"
+            } else {
+                "no"
+            })),
+            "appendfilename" => Some(
+                self.server_config
+                    .appendfilename
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            "appendfsync" => Some(self.server_config.appendfsync.to_string()),
+            "aof-load-truncated" => Some(String::from(if self.server_config.aof_load_truncated {
+                "yes"
+            } else {
+                "no"
+            })),
+            "appenddirname" => Some(
+                self.server_config
+                    .appenddirname
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            "auto-aof-rewrite-percentage" => {
+                Some(self.server_config.auto_aof_rewrite_percentage.to_string())
+            }
+            "auto-aof-rewrite-min-size" => {
+                Some(self.server_config.auto_aof_rewrite_min_size.to_string())
+            }
+            "debug-advance-time" => Some(String::from(if self.server_config.debug_advance_time {
+                "yes"
+            } else {
+                "no"
+            })),
+            _ => None,
+        };
+
+        match config_value {
+            Some(value) => Ok(RespEncoder::encode(RespDataType::Array(vec![
+                RespDataType::BulkString(config_key.to_string()),
+                RespDataType::BulkString(value),
+            ]))),
+            None => Ok(RespEncoder::encode(RespDataType::NullBulkString)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct KeysCommand {
+    args: Vec<String>,
+    store: Arc<Store>,
+}
+
+impl KeysCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
+    }
+}
+
+impl Command for KeysCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let pattern = self.args.get(1).ok_or(CommandError::InvalidFormat(
+            "Args command is missing a value".to_string(),
+        ))?;
+
+        match pattern.as_str() {
+            "*" => {
+                let keys = self.store.get_all_keys();
+
+                Ok(RespEncoder::encode(RespDataType::Array(
+                    keys.into_iter().map(RespDataType::BulkString).collect(),
+                )))
+            }
+            _ => {
+                unimplemented!("At the moment, keys only supports * as argument");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum InfoSection {
+    Replication,
+    Memory,
+    Stats,
+    Persistence,
+}
+
+impl FromStr for InfoSection {
+    type Err = CommandError;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        match arg {
+            "replication" => Ok(InfoSection::Replication),
+            "memory" => Ok(InfoSection::Memory),
+            "stats" => Ok(InfoSection::Stats),
+            "persistence" => Ok(InfoSection::Persistence),
+            value => Err(CommandError::InvalidInfoArg(format!(
+                "Info section {} is not supported",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ServerInfoFormatter {
+    info: Arc<ServerInfo>,
+}
+
+impl ServerInfoFormatter {
+    fn new(info: Arc<ServerInfo>) -> Self {
+        Self { info }
+    }
+}
+
+impl std::fmt::Display for ServerInfoFormatter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut info_stringify = String::new();
+
+        info_stringify.push_str(format!("{}:{}\n", "role", self.info.role).as_str());
+        info_stringify.push_str(format!("{}:{}\n", "master_replid", self.info.id).as_str());
+        info_stringify
+            .push_str(format!("{}:{}\n", "master_repl_offset", self.info.offset).as_str());
+
+        write!(f, "{}", info_stringify)
+    }
+}
+
+#[derive(Debug)]
+struct MemoryInfoFormatter {
+    used_memory: usize,
+    peak_memory: usize,
+    max_memory: usize,
+    max_memory_policy: MaxMemoryPolicy,
+}
+
+impl std::fmt::Display for MemoryInfoFormatter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut info_stringify = String::new();
+
+        info_stringify.push_str(format!("{}:{}\n", "used_memory", self.used_memory).as_str());
+        info_stringify.push_str(format!("{}:{}\n", "used_memory_peak", self.peak_memory).as_str());
+        info_stringify.push_str(format!("{}:{}\n", "maxmemory", self.max_memory).as_str());
+        info_stringify
+            .push_str(format!("{}:{}\n", "maxmemory_policy", self.max_memory_policy).as_str());
+
+        write!(f, "{}", info_stringify)
+    }
+}
+
+#[derive(Debug)]
+struct StatsInfoFormatter {
+    expired_keys: u64,
+    evicted_keys: u64,
+}
+
+impl std::fmt::Display for StatsInfoFormatter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut info_stringify = String::new();
+
+        info_stringify.push_str(format!("{}:{}\n", "expired_keys", self.expired_keys).as_str());
+        info_stringify.push_str(format!("{}:{}\n", "evicted_keys", self.evicted_keys).as_str());
+
+        write!(f, "{}", info_stringify)
+    }
+}
+
+#[derive(Debug)]
+struct PersistenceInfoFormatter {
+    changes_since_last_save: u64,
+    bgsave_in_progress: bool,
+    last_save_time: i64,
+    last_bgsave_ok: bool,
+    last_retention_ok: bool,
+    cow_size: usize,
+    last_load_keys_loaded: u64,
+    last_load_keys_expired: u64,
+    last_load_keys_skipped: u64,
+    loading_progress: Option<RdbLoadingProgress>,
+    now: DateTime<Utc>,
+    aof_enabled: bool,
+    aof_last_write_ok: bool,
+    aof_rewrite_in_progress: bool,
+    aof_last_rewrite_ok: bool,
+    aof_current_size: u64,
+    aof_base_size: u64,
+    aof_last_load_truncated: bool,
+}
+
+impl std::fmt::Display for PersistenceInfoFormatter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut info_stringify = String::new();
+
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "loading",
+                u8::from(self.loading_progress.is_some())
+            )
+            .as_str(),
+        );
+
+        // The progress is only reported while loading, the same as Redis.
+        if let Some(progress) = self.loading_progress {
+            info_stringify
+                .push_str(format!("{}:{}\n", "loading_start_time", progress.start_time).as_str());
+            info_stringify
+                .push_str(format!("{}:{}\n", "loading_total_bytes", progress.total_bytes).as_str());
+            info_stringify.push_str(
+                format!("{}:{}\n", "loading_loaded_bytes", progress.loaded_bytes).as_str(),
+            );
+            info_stringify.push_str(
+                format!("{}:{:.2}\n", "loading_loaded_perc", progress.loaded_perc()).as_str(),
+            );
+            info_stringify.push_str(
+                format!(
+                    "{}:{}\n",
+                    "loading_eta_seconds",
+                    progress.eta_seconds(self.now)
+                )
+                .as_str(),
+            );
+        }
+
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_changes_since_last_save", self.changes_since_last_save
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_bgsave_in_progress",
+                u8::from(self.bgsave_in_progress)
+            )
+            .as_str(),
+        );
+        info_stringify
+            .push_str(format!("{}:{}\n", "rdb_last_save_time", self.last_save_time).as_str());
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_last_bgsave_status",
+                if self.last_bgsave_ok { "ok" } else { "err" }
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_last_retention_status",
+                if self.last_retention_ok { "ok" } else { "err" }
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(format!("{}:{}\n", "current_cow_size", self.cow_size).as_str());
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_last_load_keys_loaded", self.last_load_keys_loaded
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_last_load_keys_expired", self.last_load_keys_expired
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "rdb_last_load_keys_skipped", self.last_load_keys_skipped
+            )
+            .as_str(),
+        );
+        info_stringify
+            .push_str(format!("{}:{}\n", "aof_enabled", u8::from(self.aof_enabled)).as_str());
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "aof_last_write_status",
+                if self.aof_last_write_ok { "ok" } else { "err" }
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "aof_rewrite_in_progress",
+                u8::from(self.aof_rewrite_in_progress)
+            )
+            .as_str(),
+        );
+        info_stringify.push_str(
+            format!(
+                "{}:{}\n",
+                "aof_last_bgrewrite_status",
+                if self.aof_last_rewrite_ok {
+                    "ok"
+                } else {
+                    "err"
+                }
+            )
+            .as_str(),
+        );
+
+        // The sizes are only reported when the AOF is enabled, the same as Redis.
+        if self.aof_enabled {
+            info_stringify
+                .push_str(format!("{}:{}\n", "aof_current_size", self.aof_current_size).as_str());
+            info_stringify
+                .push_str(format!("{}:{}\n", "aof_base_size", self.aof_base_size).as_str());
+            info_stringify.push_str(
+                format!(
+                    "{}:{}\n",
+                    "aof_last_load_truncated",
+                    u8::from(self.aof_last_load_truncated)
+                )
+                .as_str(),
+            );
+        }
+
+        write!(f, "{}", info_stringify)
+    }
+}
+
+#[derive(Debug)]
+struct InfoCommand {
+    args: Vec<String>,
+    info: Arc<ServerInfo>,
+    store: Arc<Store>,
+    rdb_saver: Arc<RdbSaver>,
+    rdb_sync: Arc<RdbSync>,
+    aof_writer: Arc<AofWriter>,
+}
+
+impl InfoCommand {
+    fn new(
+        args: Vec<String>,
+        info: Arc<ServerInfo>,
+        store: Arc<Store>,
+        rdb_saver: Arc<RdbSaver>,
+        rdb_sync: Arc<RdbSync>,
+        aof_writer: Arc<AofWriter>,
+    ) -> Self {
+        Self {
+            args,
+            info,
+            store,
+            rdb_saver,
+            rdb_sync,
+            aof_writer,
+        }
+    }
+}
+
+impl Command for InfoCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let section_name = self.args.get(1).ok_or(CommandError::InvalidFormat(
+            "Section name is missing".to_string(),
+        ))?;
+        let section = InfoSection::from_str(section_name.as_str())?;
+
+        let info = match section {
+            InfoSection::Replication => ServerInfoFormatter::new(self.info.clone()).to_string(),
+            InfoSection::Memory => MemoryInfoFormatter {
+                used_memory: self.store.used_memory(),
+                peak_memory: self.store.peak_memory(),
+                max_memory: self.store.max_memory(),
+                max_memory_policy: self.store.max_memory_policy(),
+            }
+            .to_string(),
+            InfoSection::Stats => StatsInfoFormatter {
+                expired_keys: self.store.expired_keys(),
+                evicted_keys: self.store.evicted_keys(),
+            }
+            .to_string(),
+            InfoSection::Persistence => {
+                let last_load_stats = self.rdb_sync.last_load_stats();
+
+                PersistenceInfoFormatter {
+                    changes_since_last_save: self.store.dirty(),
+                    bgsave_in_progress: self.rdb_saver.is_in_progress(),
+                    last_save_time: self.rdb_saver.last_save(),
+                    last_bgsave_ok: self.rdb_saver.last_bgsave_ok(),
+                    last_retention_ok: self.rdb_saver.last_retention_ok(),
+                    cow_size: self.store.snapshot_memory(),
+                    last_load_keys_loaded: last_load_stats.keys_loaded,
+                    last_load_keys_expired: last_load_stats.keys_expired,
+                    last_load_keys_skipped: last_load_stats.keys_skipped,
+                    loading_progress: self.rdb_sync.loading_progress(),
+                    now: self.store.clock().now(),
+                    aof_enabled: self.aof_writer.is_enabled(),
+                    aof_last_write_ok: self.aof_writer.last_write_ok(),
+                    aof_rewrite_in_progress: self.aof_writer.is_rewrite_in_progress(),
+                    aof_last_rewrite_ok: self.aof_writer.last_rewrite_ok(),
+                    aof_current_size: self.aof_writer.current_size(),
+                    aof_base_size: self.aof_writer.base_size(),
+                    aof_last_load_truncated: self.aof_writer.load_truncated(),
+                }
+                .to_string()
+            }
+        };
+
+        Ok(RespEncoder::encode(RespDataType::BulkString(info)))
+    }
+}
+
+#[derive(Debug)]
+struct ObjectCommand {
+    args: Vec<String>,
+    store: Arc<Store>,
+}
+
+impl ObjectCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
+    }
+
+    // Invalid arguments are replied as an error, with the same messages as Redis.
+    fn execute(&self) -> Result<Vec<u8>, String> {
+        let subcommand = self.args.get(1).ok_or(String::from(
+            "ERR wrong number of arguments for 'object' command",
+        ))?;
+
+        if !["ENCODING", "IDLETIME", "FREQ", "REFCOUNT"]
+            .contains(&subcommand.to_uppercase().as_str())
+        {
+            return Err(format!(
+                "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+                subcommand
+            ));
+        }
+
+        let key = match &self.args[2..] {
+            [key] => key,
+            _ => {
+                return Err(format!(
+                    "ERR wrong number of arguments for 'object|{}' command",
+                    subcommand.to_lowercase()
+                ))
+            }
+        };
+
+        let (value, metadata) = match (self.store.peek(key), self.store.get_metadata(key)) {
+            (Some(value), Some(metadata)) => (value, metadata),
+            _ => return Ok(RespEncoder::encode(RespDataType::NullBulkString)),
+        };
+
+        let reply = match subcommand.to_uppercase().as_str() {
+            "ENCODING" => RespDataType::BulkString(value.encoding().to_string()),
+            "IDLETIME" => RespDataType::Integer(metadata.idle_time.num_seconds()),
+            "FREQ" => RespDataType::Integer(i64::from(metadata.lfu_counter)),
+            // Values are never shared between keys.
+            _ => RespDataType::Integer(1),
+        };
+
+        Ok(RespEncoder::encode(reply))
+    }
+}
+
+impl Command for ObjectCommand {
+    // OBJECT subcommands inspect a key without updating its access information, so they can be used for finding
+    // hot or idle keys without changing the result.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(self
+            .execute()
+            .unwrap_or_else(|err| RespEncoder::encode(RespDataType::SimpleError(err))))
+    }
+}
+
+#[derive(Debug)]
+struct MemoryCommand {
+    args: Vec<String>,
+    store: Arc<Store>,
+}
+
+impl MemoryCommand {
+    fn new(args: Vec<String>, store: Arc<Store>) -> Self {
+        Self { args, store }
+    }
+
+    fn generate_usage_reply(&self) -> Result<Vec<u8>, String> {
+        let mut args = self.args.iter().skip(2);
+        let key = args.next().ok_or(String::from(
+            "ERR wrong number of arguments for 'memory|usage' command",
+        ))?;
+
+        // Redis estimates the size of collections from SAMPLES of their elements. The store computes the size of
+        // every value from all its elements when it is written, so the result is exact and the number of samples
+        // does not change it. It is still validated in order to reject the same commands as Redis.
+        while let Some(option_name) = args.next() {
+            let samples = match args.next() {
+                Some(samples) if option_name.to_uppercase() == "SAMPLES" => samples,
+                _ => return Err(String::from("ERR syntax error")),
+            };
+
+            match samples.parse::<i64>() {
+                Ok(samples) if samples >= 0 => {}
+                Ok(_) => return Err(String::from("ERR syntax error")),
+                Err(_) => return Err(String::from("ERR value is not an integer or out of range")),
+            }
+        }
+
+        match self.store.get_metadata(key) {
+            Some(metadata) => Ok(RespEncoder::encode(RespDataType::Integer(
+                metadata.memory_usage as i64,
+            ))),
+            None => Ok(RespEncoder::encode(RespDataType::NullBulkString)),
+        }
+    }
+
+    fn generate_stats_reply(&self) -> Result<Vec<u8>, String> {
+        let used_memory = self.store.used_memory();
+        let overhead_memory = self.store.overhead_memory();
+        let dataset_memory = used_memory.saturating_sub(overhead_memory);
+        let keys_count = self.store.len();
+        let bytes_per_key = used_memory.checked_div(keys_count).unwrap_or(0);
+        let dataset_percentage = if used_memory == 0 {
+            0.0
+        } else {
+            dataset_memory as f64 * 100.0 / used_memory as f64
+        };
+
+        Ok(RespEncoder::encode(RespDataType::Array(vec![
+            RespDataType::BulkString("peak.allocated".to_string()),
+            RespDataType::Integer(self.store.peak_memory() as i64),
+            RespDataType::BulkString("total.allocated".to_string()),
+            RespDataType::Integer(used_memory as i64),
+            RespDataType::BulkString("overhead.total".to_string()),
+            RespDataType::Integer(overhead_memory as i64),
+            RespDataType::BulkString("keys.count".to_string()),
+            RespDataType::Integer(keys_count as i64),
+            RespDataType::BulkString("keys.bytes-per-key".to_string()),
+            RespDataType::Integer(bytes_per_key as i64),
+            RespDataType::BulkString("dataset.bytes".to_string()),
+            RespDataType::Integer(dataset_memory as i64),
+            RespDataType::BulkString("dataset.percentage".to_string()),
+            RespDataType::BulkString(format!("{:.2}", dataset_percentage)),
+        ])))
+    }
+}
+
+impl Command for MemoryCommand {
+    // Invalid arguments are replied as an error, with the same messages as Redis.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let subcommand = match self.args.get(1) {
+            Some(subcommand) => subcommand,
+            None => {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                    String::from("ERR wrong number of arguments for 'memory' command"),
+                )))
+            }
+        };
+
+        let reply = match subcommand.to_uppercase().as_str() {
+            "USAGE" => self.generate_usage_reply(),
+            "STATS" => self.generate_stats_reply(),
+            _ => Err(format!(
+                "ERR unknown subcommand '{}'. Try MEMORY HELP.",
+                subcommand
+            )),
+        };
+
+        Ok(reply.unwrap_or_else(|err| RespEncoder::encode(RespDataType::SimpleError(err))))
+    }
+}
+
+#[derive(Debug)]
+struct SaveCommand {
+    store: Arc<Store>,
+    server_config: Arc<ServerConfig>,
+    rdb_saver: Arc<RdbSaver>,
+}
+
+impl SaveCommand {
+    fn new(store: Arc<Store>, server_config: Arc<ServerConfig>, rdb_saver: Arc<RdbSaver>) -> Self {
+        Self {
+            store,
+            server_config,
+            rdb_saver,
+        }
+    }
+}
+
+impl Command for SaveCommand {
+    // SAVE blocks the connection until the whole RDB file is written.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let rdb_path = match self.server_config.get_rdb_path() {
+            Some(rdb_path) => rdb_path,
+            None => {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                    "ERR dir and dbfilename must be configured for saving".to_string(),
+                )))
+            }
+        };
+
+        match self.rdb_saver.save(&self.store, &rdb_path) {
+            Ok(()) => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "OK".to_string(),
+            ))),
+            Err(err) => Ok(RespEncoder::encode(RespDataType::SimpleError(format!(
+                "ERR {}",
+                err
+            )))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BgsaveCommand {
+    store: Arc<Store>,
+    server_config: Arc<ServerConfig>,
+    rdb_saver: Arc<RdbSaver>,
+}
+
+impl BgsaveCommand {
+    fn new(store: Arc<Store>, server_config: Arc<ServerConfig>, rdb_saver: Arc<RdbSaver>) -> Self {
+        Self {
+            store,
+            server_config,
+            rdb_saver,
+        }
+    }
+}
+
+impl Command for BgsaveCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        let rdb_path = match self.server_config.get_rdb_path() {
+            Some(rdb_path) => rdb_path,
+            None => {
+                return Ok(RespEncoder::encode(RespDataType::SimpleError(
+                    "ERR dir and dbfilename must be configured for saving".to_string(),
+                )))
+            }
+        };
+
+        match self.rdb_saver.background_save(self.store.clone(), rdb_path) {
+            Ok(()) => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "Background saving started".to_string(),
+            ))),
+            Err(err) => Ok(RespEncoder::encode(RespDataType::SimpleError(format!(
+                "ERR {}",
+                err
+            )))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BgrewriteaofCommand {
+    store: Arc<Store>,
+    aof_writer: Arc<AofWriter>,
+}
+
+impl BgrewriteaofCommand {
+    fn new(store: Arc<Store>, aof_writer: Arc<AofWriter>) -> Self {
+        Self { store, aof_writer }
+    }
+}
+
+impl Command for BgrewriteaofCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        match self.aof_writer.background_rewrite(self.store.clone()) {
+            Ok(()) => Ok(RespEncoder::encode(RespDataType::SimpleString(
+                "Background append only file rewriting started".to_string(),
+            ))),
+            Err(err) => Ok(RespEncoder::encode(RespDataType::SimpleError(format!(
+                "ERR {}",
+                err
+            )))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LastsaveCommand {
+    rdb_saver: Arc<RdbSaver>,
+}
+
+impl LastsaveCommand {
+    fn new(rdb_saver: Arc<RdbSaver>) -> Self {
+        Self { rdb_saver }
+    }
+}
+
+impl Command for LastsaveCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(RespEncoder::encode(RespDataType::Integer(
+            self.rdb_saver.last_save(),
+        )))
+    }
+}
+
+#[derive(Debug)]
+struct DebugCommand {
+    args: Vec<String>,
+    store: Arc<Store>,
+    server_config: Arc<ServerConfig>,
+    rdb_saver: Arc<RdbSaver>,
+    rdb_sync: Arc<RdbSync>,
+}
+
+impl DebugCommand {
+    fn new(
+        args: Vec<String>,
+        store: Arc<Store>,
+        server_config: Arc<ServerConfig>,
+        rdb_saver: Arc<RdbSaver>,
+        rdb_sync: Arc<RdbSync>,
+    ) -> Self {
+        Self {
+            args,
+            store,
+            server_config,
+            rdb_saver,
+            rdb_sync,
+        }
+    }
+
+    // The options after the arguments of RELOAD and LOADRDB. MERGE adds the keys of the file to the current ones
+    // instead of replacing them, and NOSAVE (only for RELOAD) loads the file without saving the dataset first.
+    fn parse_load_options(
+        &self,
+        options: &[String],
+        nosave_allowed: bool,
+    ) -> Result<(RdbLoadMode, bool), String> {
+        let mut mode = RdbLoadMode::Replace;
+        let mut save = true;
+
+        for option in options {
+            match option.to_uppercase().as_str() {
+                "MERGE" => mode = RdbLoadMode::Merge,
+                "NOSAVE" if nosave_allowed => save = false,
+                _ => return Err(String::from("ERR syntax error")),
+            }
+        }
+
+        Ok((mode, save))
+    }
+
+    // Loads an RDB file into the store, the same as `Server::load_rdb`. Clients keep using the previous keys while
+    // the file is decoded, and the AOF is rewritten afterwards when it is enabled.
+    fn load_rdb(
+        &self,
+        rdb_path: PathBuf,
+        mode: RdbLoadMode,
+        save: bool,
+    ) -> Result<Vec<u8>, String> {
+        if save {
+            if let Err(err) = self.rdb_saver.save(&self.store, &rdb_path) {
+                return Err(format!("ERR Error trying to save the DB: {}", err));
+            }
+        }
+
+        let dirty = self.store.dirty();
+
+        if let Err(err) = block_on(self.rdb_sync.load(rdb_path, mode))? {
+            return Err(format!("ERR Error trying to load the RDB dump: {}", err));
+        }
+
+        // The dataset that was just saved is the same that was loaded, so there is nothing new to save.
+        if save {
+            self.store
+                .clear_dirty(self.store.dirty().saturating_sub(dirty));
+        }
+
+        Ok(RespEncoder::encode(RespDataType::SimpleString(
+            "OK".to_string(),
+        )))
+    }
+
+    // Invalid arguments are replied as an error, with the same messages as Redis.
+    fn execute(&self) -> Result<Vec<u8>, String> {
+        let wrong_number_of_arguments =
+            || String::from("ERR wrong number of arguments for 'debug' command");
+        let subcommand = self.args.get(1).ok_or_else(wrong_number_of_arguments)?;
+        let value = || self.args.get(2).ok_or_else(wrong_number_of_arguments);
+
+        match subcommand.to_uppercase().as_str() {
+            "SET-ACTIVE-EXPIRE" => {
+                let enabled = match value()?.as_str() {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(String::from("ERR SET-ACTIVE-EXPIRE value must be 0 or 1")),
+                };
+
+                self.store.set_active_expire(enabled);
+            }
+            "ADVANCE-TIME" => {
+                if !self.server_config.debug_advance_time {
+                    return Err(String::from(
+                        "ERR DEBUG ADVANCE-TIME is disabled, start the server with --debug-advance-time yes to use it",
+                    ));
+                }
+
+                // The clock only moves forward, so expired keys do not come back.
+                let milliseconds =
+                    match value()?.parse::<i64>() {
+                        Ok(milliseconds) if milliseconds >= 0 => milliseconds,
+                        _ => return Err(String::from(
+                            "ERR ADVANCE-TIME value must be a non-negative number of milliseconds",
+                        )),
+                    };
+
+                self.store
+                    .clock()
+                    .advance(Duration::milliseconds(milliseconds));
+            }
+            "RELOAD" => {
+                let (mode, save) = self.parse_load_options(&self.args[2..], true)?;
+                let rdb_path = match self.server_config.get_rdb_path() {
+                    Some(rdb_path) => rdb_path,
+                    None => {
+                        return Err(String::from(
+                            "ERR dir and dbfilename must be configured for reloading",
+                        ))
+                    }
+                };
+
+                return self.load_rdb(rdb_path, mode, save);
+            }
+            "LOADRDB" => {
+                let rdb_path = PathBuf::from(value()?);
+                let (mode, _) = self.parse_load_options(&self.args[3..], false)?;
+
+                return self.load_rdb(rdb_path, mode, false);
+            }
+            _ => return Err(format!("ERR unknown subcommand '{}'", subcommand)),
+        }
+
+        Ok(RespEncoder::encode(RespDataType::SimpleString(
+            "OK".to_string(),
+        )))
+    }
+}
+
+impl Command for DebugCommand {
+    // Debugging helpers for tests:
+    //
+    //  - DEBUG SET-ACTIVE-EXPIRE <0|1> disables or enables the removal of expired keys in the background.
+    //  - DEBUG ADVANCE-TIME <milliseconds> moves the store clock forward, so keys expire without waiting for them.
+    //    It changes the time of every client, so it is disabled unless the server is started with
+    //    --debug-advance-time yes.
+    //  - DEBUG RELOAD [NOSAVE] [MERGE] saves the dataset to the RDB file and loads it back.
+    //  - DEBUG LOADRDB <path> [MERGE] replaces the dataset with the keys of another RDB file, for restoring fixtures.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(self
+            .execute()
+            .unwrap_or_else(|err| RespEncoder::encode(RespDataType::SimpleError(err))))
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplconfCommand {
+    args: Vec<String>,
+}
+
+impl ReplconfCommand {
+    pub fn new(args: Vec<String>) -> Self {
+        Self { args }
+    }
+}
+
+impl Command for ReplconfCommand {
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(RespEncoder::encode(RespDataType::SimpleString(
+            "OK".to_string(),
+        )))
+    }
+
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
+        let key = self.args.get(1).ok_or(CommandError::InvalidFormat(
+            "ReplconfCommand command is missing a key".to_string(),
+        ))?;
+        let value = self.args.get(2).ok_or(CommandError::InvalidFormat(
+            "ReplconfCommand command is missing a value".to_string(),
+        ))?;
+
+        Ok(RespEncoder::encode(RespDataType::Array(vec![
+            RespDataType::BulkString("REPLCONF".to_string()),
+            RespDataType::BulkString(key.to_string()),
+            RespDataType::BulkString(value.to_string()),
+        ])))
+    }
+}
+
+#[derive(Debug)]
+pub struct PsyncCommand {
+    info: Arc<ServerInfo>,
+}
+
+impl PsyncCommand {
+    pub fn new(info: Arc<ServerInfo>) -> Self {
+        Self { info }
+    }
+}
+
+impl Command for PsyncCommand {
+    // The master needs to respond with +FULLRESYNC <REPL_ID> 0\r\n ("FULLRESYNC 0" encoded as a RESP Simple String). Here's what the response means:
+    //
+    //  - FULLRESYNC means that the master cannot perform incremental replication with the replica, and will thus start a "full" resynchronization.
+    //  - <REPL_ID> is the replication ID of the master. You've already set this in the "Replication ID & Offset" stage.
+    //      - As an example, you can hardcode 8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb as the replication ID.
+    //  - 0 is the replication offset of the master.
+    //      - You've already set this in the "Replication ID & Offset" stage.
+    fn generate_reply(&self) -> Result<Vec<u8>, CommandError> {
+        Ok(RespEncoder::encode(RespDataType::SimpleString(format!(
+            "FULLRESYNC {} {}",
+            self.info.id, self.info.offset
+        ))))
+    }
+
+    // The PSYNC command is used to synchronize the state of the replica with the master. The replica will send this command to the master with two arguments:
+    // The first argument is the replication ID of the master.
+    // The second argument is the offset of the master.
+    fn generate_request(&self) -> Result<Vec<u8>, CommandError> {
         Ok(RespEncoder::encode(RespDataType::Array(vec![
             RespDataType::BulkString("PSYNC".to_string()),
             RespDataType::BulkString("?".to_string()),
@@ -590,3 +2347,311 @@ impl Command for PsyncCommand {
         ])))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::aof::writer::AppendFsync;
+    use crate::clock::{Clock, ManualClock};
+    use crate::connections::migrate::MigrateConnections;
+    use crate::server::ServerRole;
+
+    use super::*;
+
+    fn new_store() -> (Arc<Store>, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        ));
+
+        (Arc::new(Store::with_clock(clock.clone())), clock)
+    }
+
+    fn args(command: &str) -> Vec<String> {
+        command.split(' ').map(String::from).collect()
+    }
+
+    fn reply(command: impl Command) -> String {
+        String::from_utf8(command.generate_reply().unwrap()).unwrap()
+    }
+
+    fn server_config(debug_advance_time: bool) -> Arc<ServerConfig> {
+        Arc::new(ServerConfig {
+            dir: None,
+            dbfilename: None,
+            maxmemory: 0,
+            maxmemory_policy: MaxMemoryPolicy::default(),
+            save: vec![],
+            rdb_retention: 0,
+            appendonly: false,
+            appendfilename: PathBuf::from("appendonly.aof"),
+            appenddirname: PathBuf::from("appendonlydir"),
+            appendfsync: AppendFsync::default(),
+            aof_load_truncated: true,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            debug_advance_time,
+        })
+    }
+
+    // Runs a command through the same path as the commands of the clients (or of the AOF), returning its reply.
+    async fn write(
+        command: &str,
+        store: &Arc<Store>,
+        rdb_sync: &Arc<RdbSync>,
+        from_aof: bool,
+    ) -> String {
+        let mut stream = Vec::new();
+        let writer = if from_aof {
+            CommandWriter::from_aof(
+                args(command).into_iter().map(String::into_bytes).collect(),
+                &mut stream,
+            )
+        } else {
+            CommandWriter::from_resp_data_type(
+                RespDataType::Array(
+                    args(command)
+                        .into_iter()
+                        .map(RespDataType::BulkString)
+                        .collect(),
+                ),
+                &mut stream,
+            )
+            .unwrap()
+        };
+
+        writer
+            .write(
+                store.clone(),
+                server_config(false),
+                Arc::new(ServerInfo {
+                    address: "127.0.0.1:6379".parse().unwrap(),
+                    role: ServerRole::Master,
+                    id: String::from("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"),
+                    offset: 0,
+                    migrate_connections: MigrateConnections::new(),
+                }),
+                Arc::new(RdbSaver::new(store.clock().now())),
+                rdb_sync.clone(),
+                Arc::new(AofWriter::new(
+                    std::env::temp_dir(),
+                    "appendonly.aof",
+                    AppendFsync::default(),
+                )),
+            )
+            .await
+            .unwrap();
+
+        String::from_utf8(stream).unwrap()
+    }
+
+    fn set(store: &Arc<Store>, key: &str, value: &str) {
+        let mut builder = StoreValueBuilder::new();
+
+        builder.with_value(value);
+        store.set(key, builder.build()).unwrap();
+    }
+
+    #[test]
+    fn object_inspects_keys_without_touching_them() {
+        let (store, clock) = new_store();
+        let object = |command| reply(ObjectCommand::new(args(command), store.clone()));
+
+        set(&store, "number", "12345");
+        set(&store, "string", "value");
+        clock.advance(Duration::seconds(30));
+
+        assert_eq!(object("OBJECT ENCODING number"), "$3\r\nint\r\n");
+        assert_eq!(object("OBJECT ENCODING string"), "$6\r\nembstr\r\n");
+        assert_eq!(object("OBJECT IDLETIME string"), ":30\r\n");
+        assert_eq!(object("object idletime string"), ":30\r\n");
+        assert_eq!(object("OBJECT REFCOUNT string"), ":1\r\n");
+        assert_eq!(object("OBJECT ENCODING missing"), "$-1\r\n");
+
+        store.get("string");
+
+        assert_eq!(object("OBJECT IDLETIME string"), ":0\r\n");
+    }
+
+    #[test]
+    fn object_replies_errors_for_invalid_arguments() {
+        let (store, _) = new_store();
+        let object = |command| reply(ObjectCommand::new(args(command), store.clone()));
+
+        assert_eq!(
+            object("OBJECT"),
+            "-ERR wrong number of arguments for 'object' command\r\n"
+        );
+        assert_eq!(
+            object("OBJECT LENGTH key"),
+            "-ERR unknown subcommand 'LENGTH'. Try OBJECT HELP.\r\n"
+        );
+        assert_eq!(
+            object("OBJECT ENCODING"),
+            "-ERR wrong number of arguments for 'object|encoding' command\r\n"
+        );
+        assert_eq!(
+            object("OBJECT FREQ a b"),
+            "-ERR wrong number of arguments for 'object|freq' command\r\n"
+        );
+    }
+
+    #[test]
+    fn memory_usage_of_keys() {
+        let (store, _) = new_store();
+        let memory = |command| reply(MemoryCommand::new(args(command), store.clone()));
+
+        set(&store, "key", "value");
+
+        let usage = store.get_metadata("key").unwrap().memory_usage;
+
+        assert_eq!(memory("MEMORY USAGE key"), format!(":{}\r\n", usage));
+        assert_eq!(
+            memory("MEMORY USAGE key SAMPLES 0"),
+            format!(":{}\r\n", usage)
+        );
+        assert_eq!(memory("MEMORY USAGE missing"), "$-1\r\n");
+    }
+
+    #[test]
+    fn memory_replies_errors_for_invalid_arguments() {
+        let (store, _) = new_store();
+        let memory = |command| reply(MemoryCommand::new(args(command), store.clone()));
+
+        assert_eq!(
+            memory("MEMORY"),
+            "-ERR wrong number of arguments for 'memory' command\r\n"
+        );
+        assert_eq!(
+            memory("MEMORY DOCTOR"),
+            "-ERR unknown subcommand 'DOCTOR'. Try MEMORY HELP.\r\n"
+        );
+        assert_eq!(
+            memory("MEMORY USAGE"),
+            "-ERR wrong number of arguments for 'memory|usage' command\r\n"
+        );
+        assert_eq!(memory("MEMORY USAGE key COUNT 5"), "-ERR syntax error\r\n");
+        assert_eq!(memory("MEMORY USAGE key SAMPLES"), "-ERR syntax error\r\n");
+        assert_eq!(
+            memory("MEMORY USAGE key SAMPLES -1"),
+            "-ERR syntax error\r\n"
+        );
+        assert_eq!(
+            memory("MEMORY USAGE key SAMPLES five"),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn memory_stats_counts_the_keys() {
+        let (store, _) = new_store();
+
+        set(&store, "a", "1");
+        set(&store, "b", "2");
+
+        let stats = reply(MemoryCommand::new(args("MEMORY STATS"), store.clone()));
+
+        assert!(stats.contains("$10\r\nkeys.count\r\n:2\r\n"));
+        assert!(stats.contains(&format!(
+            "$15\r\ntotal.allocated\r\n:{}\r\n",
+            store.used_memory()
+        )));
+    }
+
+    #[tokio::test]
+    async fn block_on_fails_in_a_current_thread_runtime() {
+        assert_eq!(
+            block_on(async { 1 }),
+            Err(String::from(
+                "ERR this command needs a multi-threaded runtime"
+            ))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn block_on_waits_for_the_future() {
+        assert_eq!(block_on(async { 1 }), Ok(1));
+    }
+
+    #[test]
+    fn set_expirations_follow_the_store_clock() {
+        let (store, clock) = new_store();
+        let get = || reply(GetCommand::new(args("GET key"), store.clone()));
+        let set_command = SetCommand::new(args("SET key value PX 1000"), store.clone());
+
+        assert_eq!(set_command.generate_reply().unwrap(), b"+OK\r\n");
+        assert_eq!(
+            set_command.propagated_args().unwrap().last().unwrap(),
+            &(clock.now() + Duration::seconds(1))
+                .timestamp_millis()
+                .to_string()
+                .into_bytes()
+        );
+
+        clock.advance(Duration::milliseconds(1000));
+        assert_eq!(get(), "$5\r\nvalue\r\n");
+
+        clock.advance(Duration::milliseconds(1));
+        assert_eq!(get(), "$-1\r\n");
+        assert_eq!(store.expired_keys(), 1);
+    }
+
+    #[test]
+    fn debug_advance_time_moves_the_store_clock() {
+        let (store, clock) = new_store();
+        let debug = |command, debug_advance_time| {
+            reply(DebugCommand::new(
+                args(command),
+                store.clone(),
+                server_config(debug_advance_time),
+                Arc::new(RdbSaver::new(clock.now())),
+                Arc::new(RdbSync::new(store.clone())),
+            ))
+        };
+        let start = clock.now();
+
+        assert!(debug("DEBUG ADVANCE-TIME 1000", false)
+            .starts_with("-ERR DEBUG ADVANCE-TIME is disabled"));
+        assert_eq!(clock.now(), start);
+
+        assert_eq!(debug("DEBUG ADVANCE-TIME 1500", true), "+OK\r\n");
+        assert_eq!(clock.now(), start + Duration::milliseconds(1500));
+
+        assert_eq!(
+            debug("DEBUG ADVANCE-TIME -1", true),
+            "-ERR ADVANCE-TIME value must be a non-negative number of milliseconds\r\n"
+        );
+        assert_eq!(clock.now(), start + Duration::milliseconds(1500));
+    }
+
+    #[tokio::test]
+    async fn commands_that_need_the_dataset_wait_for_the_loading() {
+        let (store, _) = new_store();
+        let rdb_sync = Arc::new(RdbSync::new(store.clone()));
+
+        rdb_sync.start_loading();
+
+        assert_eq!(
+            write("GET key", &store, &rdb_sync, false).await,
+            format!("-{}\r\n", LOADING_ERROR)
+        );
+        assert!(write("INFO persistence", &store, &rdb_sync, false)
+            .await
+            .contains("loading:1\n"));
+        // The AOF is replayed while the dataset is being loaded.
+        assert_eq!(
+            write("SET key value", &store, &rdb_sync, true).await,
+            "+OK\r\n"
+        );
+
+        rdb_sync.finish_loading();
+
+        assert_eq!(
+            write("GET key", &store, &rdb_sync, false).await,
+            "$5\r\nvalue\r\n"
+        );
+        assert!(write("INFO persistence", &store, &rdb_sync, false)
+            .await
+            .contains("loading:0\n"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// Cached connections are closed after this time without being used, the same as Redis does.
+const MIGRATE_CONNECTION_TTL: Duration = Duration::from_secs(10);
+/// Maximum number of cached connections. When it is reached, new connections are closed after being used.
+const MAX_MIGRATE_CONNECTIONS: usize = 64;
+
+/// A connection to another server opened by MIGRATE.
+#[derive(Debug)]
+pub struct MigrateConnection {
+    pub stream: TcpStream,
+    /// The database selected in the other server. None until SELECT is sent.
+    pub db: Option<usize>,
+    /// Whether it was taken from the cache, so the other server may have closed it in the meantime.
+    pub cached: bool,
+    last_used: Instant,
+}
+
+/// The connections opened by MIGRATE, by the address of the other server. They are reused by the next MIGRATE
+/// commands to the same server, so moving keys one by one does not open a connection per key.
+///
+/// A connection is taken out of the cache while it is used, so two commands never use it at the same time.
+#[derive(Debug, Default)]
+pub struct MigrateConnections {
+    connections: Mutex<HashMap<String, MigrateConnection>>,
+}
+
+impl MigrateConnections {
+    pub fn new() -> Self {
+        MigrateConnections::default()
+    }
+
+    /// Takes the cached connection to `address`, or opens a new one when there is none.
+    pub async fn take(&self, address: &str) -> std::io::Result<MigrateConnection> {
+        let cached = self
+            .connections
+            .lock()
+            .unwrap()
+            .remove(address)
+            .filter(|connection| connection.last_used.elapsed() < MIGRATE_CONNECTION_TTL);
+
+        if let Some(mut connection) = cached {
+            connection.cached = true;
+
+            return Ok(connection);
+        }
+
+        let stream = TcpStream::connect(address).await?;
+
+        Ok(MigrateConnection {
+            stream,
+            db: None,
+            cached: false,
+            last_used: Instant::now(),
+        })
+    }
+
+    /// Caches a connection once it is used. Connections that failed must be dropped instead, since it is unknown
+    /// what the other server received.
+    pub fn put(&self, address: &str, mut connection: MigrateConnection) {
+        let mut connections = self.connections.lock().unwrap();
+
+        connections.retain(|_, connection| connection.last_used.elapsed() < MIGRATE_CONNECTION_TTL);
+
+        if connections.len() >= MAX_MIGRATE_CONNECTIONS {
+            return;
+        }
+
+        connection.last_used = Instant::now();
+        connections.insert(address.to_string(), connection);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod aof;
+pub mod clock;
 pub mod commands;
 pub mod connections;
 pub mod rdb;
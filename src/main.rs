@@ -21,6 +21,42 @@ struct Args {
     /// When the --replicaof flag is passed, the server assumes the "slave" role instead.
     #[arg(long)]
     replicaof: Option<String>,
+    /// The maximum amount of memory used by the store (example: 100mb). By default, there is no limit.
+    #[arg(long)]
+    maxmemory: Option<String>,
+    /// The eviction policy used when maxmemory is reached (example: allkeys-lru)
+    #[arg(long)]
+    maxmemory_policy: Option<String>,
+    /// Automatic save rules as pairs of seconds and changes (example: "3600 1 300 100"). There are none by default.
+    #[arg(long)]
+    save: Option<String>,
+    /// Number of previous RDB files kept next to the current one, named after the time they were saved (0 keeps none)
+    #[arg(long)]
+    rdb_retention: Option<String>,
+    /// Whether write commands are appended to the AOF, which is loaded at startup instead of the RDB file (yes/no)
+    #[arg(long)]
+    appendonly: Option<String>,
+    /// The name of the AOF, stored in the --dir directory (example: appendonly.aof)
+    #[arg(long)]
+    appendfilename: Option<String>,
+    /// The directory, inside --dir, where the files of the AOF are stored (example: appendonlydir)
+    #[arg(long)]
+    appenddirname: Option<String>,
+    /// When the AOF is flushed to disk: always, everysec or no
+    #[arg(long)]
+    appendfsync: Option<String>,
+    /// Whether an incomplete command at the end of the AOF is dropped when loading it, instead of failing (yes/no)
+    #[arg(long)]
+    aof_load_truncated: Option<String>,
+    /// How much the AOF has to grow since the last rewrite to be rewritten automatically, as a percentage (0 disables it)
+    #[arg(long)]
+    auto_aof_rewrite_percentage: Option<String>,
+    /// The minimum size of the AOF to be rewritten automatically (example: 64mb)
+    #[arg(long)]
+    auto_aof_rewrite_min_size: Option<String>,
+    /// Whether DEBUG ADVANCE-TIME can move the clock of the server forward, only meant for tests (yes/no)
+    #[arg(long)]
+    debug_advance_time: Option<String>,
 }
 
 #[tokio::main]
@@ -61,6 +97,78 @@ async fn main() -> anyhow::Result<()> {
             .with_context(|| format!("Failed to read {} db file", dbfilename))?;
     }
 
+    if let Some(maxmemory) = cli_args.maxmemory {
+        server
+            .with_maxmemory(&maxmemory)
+            .with_context(|| format!("Invalid maxmemory {}", maxmemory))?;
+    }
+
+    if let Some(maxmemory_policy) = cli_args.maxmemory_policy {
+        server
+            .with_maxmemory_policy(&maxmemory_policy)
+            .with_context(|| format!("Invalid maxmemory policy {}", maxmemory_policy))?;
+    }
+
+    if let Some(save) = cli_args.save {
+        server
+            .with_save(&save)
+            .with_context(|| format!("Invalid save rules {}", save))?;
+    }
+
+    if let Some(rdb_retention) = cli_args.rdb_retention {
+        server
+            .with_rdb_retention(&rdb_retention)
+            .with_context(|| format!("Invalid rdb-retention value {}", rdb_retention))?;
+    }
+
+    if let Some(appendonly) = cli_args.appendonly {
+        server
+            .with_appendonly(&appendonly)
+            .with_context(|| format!("Invalid appendonly value {}", appendonly))?;
+    }
+
+    if let Some(appendfilename) = cli_args.appendfilename {
+        server
+            .with_appendfilename(&appendfilename)
+            .with_context(|| format!("Invalid AOF file name {}", appendfilename))?;
+    }
+
+    if let Some(appenddirname) = cli_args.appenddirname {
+        server
+            .with_appenddirname(&appenddirname)
+            .with_context(|| format!("Invalid AOF directory name {}", appenddirname))?;
+    }
+
+    if let Some(appendfsync) = cli_args.appendfsync {
+        server
+            .with_appendfsync(&appendfsync)
+            .with_context(|| format!("Invalid appendfsync policy {}", appendfsync))?;
+    }
+
+    if let Some(aof_load_truncated) = cli_args.aof_load_truncated {
+        server
+            .with_aof_load_truncated(&aof_load_truncated)
+            .with_context(|| format!("Invalid aof-load-truncated value {}", aof_load_truncated))?;
+    }
+
+    if let Some(percentage) = cli_args.auto_aof_rewrite_percentage {
+        server
+            .with_auto_aof_rewrite_percentage(&percentage)
+            .with_context(|| format!("Invalid auto-aof-rewrite-percentage value {}", percentage))?;
+    }
+
+    if let Some(min_size) = cli_args.auto_aof_rewrite_min_size {
+        server
+            .with_auto_aof_rewrite_min_size(&min_size)
+            .with_context(|| format!("Invalid auto-aof-rewrite-min-size value {}", min_size))?;
+    }
+
+    if let Some(debug_advance_time) = cli_args.debug_advance_time {
+        server
+            .with_debug_advance_time(&debug_advance_time)
+            .with_context(|| format!("Invalid debug-advance-time value {}", debug_advance_time))?;
+    }
+
     server
         .listen()
         .await
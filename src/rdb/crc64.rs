@@ -0,0 +1,83 @@
+/// The CRC64 variant used by Redis (Jones polynomial, reflected, no final xor) for RDB checksums and DUMP payloads.
+const POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const TABLE: [u64; 256] = build_table();
+
+const fn build_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+/// Incremental CRC64 computation, so a checksum can be calculated while a file is read or written.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Crc64 {
+    crc: u64,
+}
+
+impl Crc64 {
+    pub fn new() -> Self {
+        Self { crc: 0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.crc = TABLE[((self.crc ^ u64::from(*byte)) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.crc
+    }
+}
+
+pub fn crc64(bytes: &[u8]) -> u64 {
+    let mut crc = Crc64::new();
+
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc64_matches_the_redis_check_value() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn crc64_of_nothing_is_zero() {
+        assert_eq!(crc64(b""), 0);
+    }
+
+    #[test]
+    fn incremental_crc64_matches_the_whole_input() {
+        let mut crc = Crc64::new();
+
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+
+        assert_eq!(crc.finish(), crc64(b"123456789"));
+    }
+}
@@ -1,22 +1,104 @@
 use bytes::{BufMut, Bytes, BytesMut};
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::string::FromUtf8Error;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-
-use crate::store::{StoreValue, StoreValueBuilder};
-
-#[derive(Debug)]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::store::{
+    StoreData, StoreModuleField, StoreModuleValue, StoreStream, StoreStreamConsumer,
+    StoreStreamGroup, StoreStreamPendingEntry, StoreValue, StoreValueBuilder, StreamId,
+};
+
+use super::crc64::Crc64;
+use super::{intset, listpack, lzf, ziplist, zipmap};
+
+/// The first RDB version that ends with a checksum.
+const MIN_CHECKSUM_VERSION: u32 = 5;
+/// The newest RDB version that can be loaded (Redis 7.4).
+pub const MAX_VERSION: u32 = 12;
+
+/// The most elements allocated up front for a collection. Lengths come from untrusted input (RESTORE payloads, for
+/// instance), so bigger collections grow as their elements are decoded, and a wrong length fails at the end of the
+/// input instead of allocating it.
+pub(super) const MAX_PREALLOCATION: usize = 1024;
+
+/// Quicklist nodes that contain a single big element, saved as a plain string.
+const QUICKLIST_NODE_CONTAINER_PLAIN: usize = 1;
+/// Quicklist nodes that contain a listpack.
+const QUICKLIST_NODE_CONTAINER_PACKED: usize = 2;
+
+const STREAM_ITEM_FLAG_DELETED: u8 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: u8 = 2;
+
+const MODULE_OPCODE_EOF: usize = 0;
+const MODULE_OPCODE_SINT: usize = 1;
+const MODULE_OPCODE_UINT: usize = 2;
+const MODULE_OPCODE_FLOAT: usize = 3;
+const MODULE_OPCODE_DOUBLE: usize = 4;
+const MODULE_OPCODE_STRING: usize = 5;
+
+/// The characters that can be used in a module type name.
+const MODULE_TYPE_NAME_CHARSET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Debug, Clone, Copy)]
 enum RdbValueType {
-    StringEncoding,
+    String,
+    List,
+    Set,
+    SortedSet,
+    Hash,
+    SortedSet2,
+    ModulePreGa,
+    Module2,
+    HashZipmap,
+    ListZiplist,
+    SetIntset,
+    SortedSetZiplist,
+    HashZiplist,
+    ListQuicklist,
+    StreamListpacks,
+    HashListpack,
+    SortedSetListpack,
+    ListQuicklist2,
+    StreamListpacks2,
+    SetListpack,
+    StreamListpacks3,
+    HashMetadataPreGa,
+    HashListpackExPreGa,
+    HashMetadata,
+    HashListpackEx,
 }
 
 impl RdbValueType {
     fn from_byte(byte: u8) -> Option<RdbValueType> {
-        match format!("{:X}", byte).as_str() {
-            "0" => Some(RdbValueType::StringEncoding),
+        match byte {
+            0 => Some(RdbValueType::String),
+            1 => Some(RdbValueType::List),
+            2 => Some(RdbValueType::Set),
+            3 => Some(RdbValueType::SortedSet),
+            4 => Some(RdbValueType::Hash),
+            5 => Some(RdbValueType::SortedSet2),
+            6 => Some(RdbValueType::ModulePreGa),
+            7 => Some(RdbValueType::Module2),
+            9 => Some(RdbValueType::HashZipmap),
+            10 => Some(RdbValueType::ListZiplist),
+            11 => Some(RdbValueType::SetIntset),
+            12 => Some(RdbValueType::SortedSetZiplist),
+            13 => Some(RdbValueType::HashZiplist),
+            14 => Some(RdbValueType::ListQuicklist),
+            15 => Some(RdbValueType::StreamListpacks),
+            16 => Some(RdbValueType::HashListpack),
+            17 => Some(RdbValueType::SortedSetListpack),
+            18 => Some(RdbValueType::ListQuicklist2),
+            19 => Some(RdbValueType::StreamListpacks2),
+            20 => Some(RdbValueType::SetListpack),
+            21 => Some(RdbValueType::StreamListpacks3),
+            22 => Some(RdbValueType::HashMetadataPreGa),
+            23 => Some(RdbValueType::HashListpackExPreGa),
+            24 => Some(RdbValueType::HashMetadata),
+            25 => Some(RdbValueType::HashListpackEx),
             _ => None,
         }
     }
@@ -27,12 +109,18 @@ enum RdbSection {
     Metadata,
     Database,
     Header,
+    Function,
+    FunctionPreGa,
+    ModuleAux,
     EndOfFile,
 }
 
 impl RdbSection {
     fn from_byte(byte: u8) -> Option<RdbSection> {
         match format!("{:X}", byte).as_str() {
+            "F5" => Some(RdbSection::Function),
+            "F6" => Some(RdbSection::FunctionPreGa),
+            "F7" => Some(RdbSection::ModuleAux),
             "FA" => Some(RdbSection::Metadata),
             "FE" => Some(RdbSection::Database),
             "FF" => Some(RdbSection::EndOfFile),
@@ -46,6 +134,10 @@ pub struct RdbData {
     pub header: RdbDataHeader,
     pub metadata: Option<RdbDataMetadata>,
     pub databases: Option<RdbDataDatabases>,
+    /// The code of the function libraries, one per library.
+    pub functions: Vec<String>,
+    /// Data saved by modules that is not attached to any key.
+    pub module_aux: Vec<RdbModuleAux>,
     pub checksum: u64,
 }
 
@@ -55,49 +147,51 @@ pub struct RdbDataHeader {
     pub version: String,
 }
 
-#[derive(Debug)]
+/// The auxiliary fields of the file. Besides the ones written by every Redis version (`redis-ver`, `redis-bits`,
+/// `ctime` and `used-mem`), replication and AOF information (`repl-id`, `repl-offset`, `aof-base`...) is kept too,
+/// including fields that are unknown to the server.
+#[derive(Debug, Default)]
 pub struct RdbDataMetadata {
-    redis_ver: Option<String>,
-    redis_bits: Option<String>,
-    ctime: Option<String>,
-    used_mem: Option<String>,
+    pub aux: HashMap<String, String>,
 }
 
 impl RdbDataMetadata {
     fn new() -> Self {
-        RdbDataMetadata {
-            redis_ver: None,
-            redis_bits: None,
-            ctime: None,
-            used_mem: None,
-        }
+        RdbDataMetadata::default()
     }
 
-    fn set_key(&mut self, key: &str, value: &str) -> Result<(), RdbFileDecoderError> {
-        match key {
-            "redis-ver" => {
-                self.redis_ver = Some(value.to_string());
-                Ok(())
-            }
-            "redis-bits" => {
-                self.redis_bits = Some(value.to_string());
-                Ok(())
-            }
-            "ctime" => {
-                self.ctime = Some(value.to_string());
-                Ok(())
-            }
-            "used-mem" => {
-                self.used_mem = Some(value.to_string());
-                Ok(())
-            }
-            _ => Err(RdbFileDecoderError::InvalidMetadata(String::from(
-                "Invalid metadata key",
-            ))),
-        }
+    fn set_key(&mut self, key: &str, value: &str) {
+        self.aux.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.aux.get(key).map(|value| value.as_str())
+    }
+
+    pub fn redis_ver(&self) -> Option<&str> {
+        self.get("redis-ver")
+    }
+
+    pub fn redis_bits(&self) -> Option<&str> {
+        self.get("redis-bits")
+    }
+
+    pub fn ctime(&self) -> Option<&str> {
+        self.get("ctime")
+    }
+
+    pub fn used_mem(&self) -> Option<&str> {
+        self.get("used-mem")
     }
 }
 
+/// Module data that is not attached to a key, with the moment of the load it belongs to (before or after the keys).
+#[derive(Debug)]
+pub struct RdbModuleAux {
+    pub module: StoreModuleValue,
+    pub when: u64,
+}
+
 #[derive(Debug, Default)]
 pub struct RdbDataDatabases {
     pub databases: HashMap<usize, RdbDatabase>,
@@ -113,6 +207,12 @@ impl RdbDataDatabases {
 pub struct RdbDatabase {
     pub index: usize,
     pub data: HashMap<String, StoreValue>,
+    /// Keys that were not loaded because their name or value is not valid UTF-8.
+    pub keys_skipped: u64,
+    /// Hashes that were not loaded because all their fields had already expired.
+    pub keys_expired: u64,
+    /// The eviction information of the keys that were saved with it.
+    pub metadata: HashMap<String, RdbKeyMetadata>,
 }
 
 impl RdbDatabase {
@@ -120,19 +220,38 @@ impl RdbDatabase {
         Self {
             index,
             data: HashMap::new(),
+            keys_skipped: 0,
+            keys_expired: 0,
+            metadata: HashMap::new(),
         }
     }
 
-    fn set(&mut self, key: &str, value: StoreValue) {
+    fn set(&mut self, key: &str, value: StoreValue, metadata: RdbKeyMetadata) {
+        if metadata.idle.is_some() || metadata.freq.is_some() {
+            self.metadata.insert(key.to_string(), metadata);
+        }
+
         self.data.insert(key.to_string(), value);
     }
 }
 
+/// Eviction information of a key. Redis saves the idle time when the eviction policy is LRU, and the frequency
+/// counter when it is LFU.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RdbKeyMetadata {
+    /// Seconds since the last access.
+    pub idle: Option<u64>,
+    /// The logarithmic access frequency counter.
+    pub freq: Option<u8>,
+}
+
 #[derive(Debug)]
 struct RdbDataBuilder {
     header: Option<RdbDataHeader>,
     metadata: Option<RdbDataMetadata>,
     databases: Option<RdbDataDatabases>,
+    functions: Vec<String>,
+    module_aux: Vec<RdbModuleAux>,
     checksum: Option<u64>,
 }
 
@@ -142,6 +261,8 @@ impl RdbDataBuilder {
             header: None,
             metadata: None,
             databases: None,
+            functions: Vec::new(),
+            module_aux: Vec::new(),
             checksum: None,
         }
     }
@@ -150,12 +271,32 @@ impl RdbDataBuilder {
         self.header = Some(header);
     }
 
+    // Other sections can be saved between auxiliary fields or databases, so every part is merged with the previous
+    // ones.
     pub fn with_metadata(&mut self, metadata: RdbDataMetadata) {
-        self.metadata = Some(metadata);
+        match &mut self.metadata {
+            Some(current) => current.aux.extend(metadata.aux),
+            None => self.metadata = Some(metadata),
+        }
     }
 
     pub fn with_databases(&mut self, databases: RdbDataDatabases) {
-        self.databases = Some(databases);
+        match &mut self.databases {
+            Some(current) => current.databases.extend(databases.databases),
+            None => self.databases = Some(databases),
+        }
+    }
+
+    pub fn with_function(&mut self, function: String) {
+        self.functions.push(function);
+    }
+
+    pub fn with_module_aux(&mut self, module_aux: RdbModuleAux) {
+        self.module_aux.push(module_aux);
+    }
+
+    pub fn with_checksum(&mut self, checksum: u64) {
+        self.checksum = Some(checksum);
     }
 
     pub fn build(self) -> RdbData {
@@ -163,6 +304,8 @@ impl RdbDataBuilder {
             header: self.header.unwrap(),
             metadata: self.metadata,
             databases: self.databases,
+            functions: self.functions,
+            module_aux: self.module_aux,
             checksum: self.checksum.unwrap_or(0),
         }
     }
@@ -178,9 +321,14 @@ pub enum RdbFileDecoderError {
     InvalidNumberConversion(String),
     InvalidSize,
     InvalidString,
+    InvalidCompressedString,
+    InvalidValueType(u8),
+    InvalidValue(String),
+    UnsupportedVersion(u32),
     InvalidExpirationConversion(std::num::TryFromIntError),
     MissingDbIndex,
     EmptyBuffer,
+    ChecksumMismatch { expected: u64, actual: u64 },
 }
 
 impl std::fmt::Display for RdbFileDecoderError {
@@ -207,6 +355,26 @@ impl std::fmt::Display for RdbFileDecoderError {
             RdbFileDecoderError::InvalidString => {
                 write!(f, "InvalidString Error")
             }
+            RdbFileDecoderError::InvalidCompressedString => {
+                write!(f, "InvalidCompressedString Error")
+            }
+            RdbFileDecoderError::InvalidValueType(value_type) => {
+                write!(
+                    f,
+                    "InvalidValueType Error: unknown value type {}",
+                    value_type
+                )
+            }
+            RdbFileDecoderError::InvalidValue(err) => {
+                write!(f, "InvalidValue Error: {}", err)
+            }
+            RdbFileDecoderError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "UnsupportedVersion Error: RDB version {} is not supported (from 1 to {})",
+                    version, MAX_VERSION
+                )
+            }
             RdbFileDecoderError::InvalidExpirationConversion(err) => {
                 write!(f, "InvalidExpirationConversion Error = {}", err)
             }
@@ -219,94 +387,164 @@ impl std::fmt::Display for RdbFileDecoderError {
             RdbFileDecoderError::InvalidArrayConversion(err) => {
                 write!(f, "InvalidArrayConversion Error = {}", err)
             }
+            RdbFileDecoderError::ChecksumMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "ChecksumMismatch Error: the file checksum is {:016x} but its content checksum is {:016x}",
+                    expected, actual
+                )
+            }
         }
     }
 }
 
-// When buffer contains information, then read methods will first get information from the buffer before reading
-// the file.
+// Reads the exact number of bytes requested from the underlying reader, failing when it ends before.
+//
+// Bytes can be pushed back after reading them, which is useful when a byte has to be read in order to know what
+// comes next (for instance, to check if it's a section). Read methods first take the pushed back bytes, in the
+// reverse order they were pushed back, and then read the rest from the reader.
 //
-// If the buffer is smaller than the amount of bytes that we need to read, the result will be a combination between
-// the buffer and the bytes read from the file.
+// The reader is not buffered here, so that it can be used again after the RDB payload (a replication socket, for
+// instance). Files should be wrapped in a `BufReader` in order to save system calls.
 //
-// We keep the buffer in order to save system calls.
-struct RdbFileReader {
-    file: File,
-    buffer: BytesMut,
+// The checksum is updated with every byte read from the reader. Bytes pushed back were already included, so they
+// are not added again.
+struct RdbFileReader<R> {
+    reader: R,
+    pushed_back: Vec<u8>,
+    crc: Crc64,
 }
 
-impl RdbFileReader {
-    fn new(file: File) -> Self {
+impl<R: AsyncRead + Unpin> RdbFileReader<R> {
+    fn new(reader: R) -> Self {
         Self {
-            file,
-            buffer: BytesMut::new(),
+            reader,
+            pushed_back: Vec::new(),
+            crc: Crc64::new(),
         }
     }
 
+    // The buffer grows as the bytes are read, so a length bigger than the rest of the input fails when the input
+    // ends, without allocating the whole length first.
     async fn read_exact(&mut self, number_of_bytes: usize) -> Result<Vec<u8>, RdbFileDecoderError> {
-        let mut buf = BytesMut::with_capacity(number_of_bytes - self.buffer.len());
+        let mut buf = Vec::with_capacity(number_of_bytes.min(MAX_PREALLOCATION));
 
-        if !self.buffer.is_empty() {
-            buf.put(self.buffer.clone());
+        while buf.len() < number_of_bytes {
+            match self.pushed_back.pop() {
+                Some(byte) => buf.push(byte),
+                None => break,
+            }
         }
 
-        self.file
-            .read_buf(&mut buf)
+        let pushed_back = buf.len();
+
+        (&mut self.reader)
+            .take((number_of_bytes - pushed_back) as u64)
+            .read_to_end(&mut buf)
             .await
             .map_err(|err| RdbFileDecoderError::ReadFile(err.to_string()))?;
 
-        self.clear_buf();
+        if buf.len() < number_of_bytes {
+            return Err(RdbFileDecoderError::ReadFile(String::from("early eof")));
+        }
+
+        self.crc.update(&buf[pushed_back..]);
 
-        Ok(buf.to_vec())
+        Ok(buf)
     }
 
     async fn read_u8(&mut self) -> Result<u8, RdbFileDecoderError> {
-        if self.buffer.is_empty() {
-            let byte = self
-                .file
-                .read_u8()
-                .await
-                .map_err(|err| RdbFileDecoderError::ReadFile(err.to_string()))?;
-
+        if let Some(byte) = self.pushed_back.pop() {
             return Ok(byte);
         }
-        let cloned_buffer = self.buffer.clone();
-        let last_byte = cloned_buffer.last().unwrap();
 
-        self.buffer.clear();
+        let byte = self
+            .reader
+            .read_u8()
+            .await
+            .map_err(|err| RdbFileDecoderError::ReadFile(err.to_string()))?;
+
+        self.crc.update(&[byte]);
+
+        Ok(byte)
+    }
+
+    // Returns the next byte without consuming it.
+    async fn peek_u8(&mut self) -> Result<u8, RdbFileDecoderError> {
+        let byte = self.read_u8().await?;
+
+        self.push_back(byte);
+
+        Ok(byte)
+    }
 
-        Ok(last_byte.to_owned())
+    // The byte will be returned by the next read.
+    fn push_back(&mut self, byte: u8) {
+        self.pushed_back.push(byte);
     }
 
-    // It writes a byte to the buffer
-    //
-    // This is useful when needing to read a byte in order to check if it's a section. In case it's not a section, we want to keep
-    // that byte as it will be necessary for the next read.
-    fn write_byte_to_buf(&mut self, byte: u8) {
-        self.buffer.put_u8(byte);
+    // The checksum of everything read so far.
+    fn checksum(&self) -> u64 {
+        self.crc.finish()
     }
 
-    fn clear_buf(&mut self) {
-        self.buffer.clear();
+    fn into_inner(self) -> R {
+        self.reader
     }
 }
 
-pub struct RdbFileDecoder {
-    reader: RdbFileReader,
+/// Receives the keys decoded by `RdbFileDecoder::decode_keys`, with the index of their database.
+pub type RdbKeyHandler<'a> = dyn FnMut(usize, String, StoreValue) + Send + 'a;
+
+/// Decodes an RDB payload from any reader: a file, a replication socket, memory or stdin.
+pub struct RdbFileDecoder<R> {
+    reader: RdbFileReader<R>,
     current_section: RdbSection,
+    // The first string of the current key that is not valid UTF-8, which is only known once part of its value is read.
+    invalid_string: Option<FromUtf8Error>,
+    // When it is set, hash fields that expired before it are not loaded.
+    now: Option<DateTime<Utc>>,
+    // All the fields of the current hash had already expired, so the key is not loaded.
+    expired_value: bool,
 }
 
-impl RdbFileDecoder {
-    pub fn new(file: File) -> Self {
+impl<R: AsyncRead + Unpin> RdbFileDecoder<R> {
+    pub fn new(reader: R) -> Self {
         Self {
-            reader: RdbFileReader::new(file),
+            reader: RdbFileReader::new(reader),
             // We assume that the first section of the .rdb file is going to be the header
             current_section: RdbSection::Header,
+            invalid_string: None,
+            now: None,
+            expired_value: false,
         }
     }
 
+    /// Drops the hash fields that expired before `now`, and the hashes that are left without fields. Fields are kept
+    /// otherwise, the same as on replicas.
+    pub fn with_now(&mut self, now: DateTime<Utc>) {
+        self.now = Some(now);
+    }
+
     pub async fn decode(&mut self) -> Result<RdbData, RdbFileDecoderError> {
+        self.decode_sections(None).await
+    }
+
+    /// Decodes the payload passing every key to `on_key` as soon as it is decoded, instead of keeping it. The
+    /// databases of the result have no keys, so files bigger than the memory can be read.
+    pub async fn decode_keys(
+        &mut self,
+        on_key: &mut RdbKeyHandler<'_>,
+    ) -> Result<RdbData, RdbFileDecoderError> {
+        self.decode_sections(Some(on_key)).await
+    }
+
+    async fn decode_sections(
+        &mut self,
+        mut on_key: Option<&mut RdbKeyHandler<'_>>,
+    ) -> Result<RdbData, RdbFileDecoderError> {
         let mut builder = RdbDataBuilder::new();
+        let mut version = 0;
 
         loop {
             match self.current_section {
@@ -314,6 +552,12 @@ impl RdbFileDecoder {
                     let header_decoder = HeaderDecoder::new(self);
                     let header = header_decoder.decode().await?;
 
+                    version = header.version.parse().unwrap_or(0);
+
+                    if !(1..=MAX_VERSION).contains(&version) {
+                        return Err(RdbFileDecoderError::UnsupportedVersion(version));
+                    }
+
                     builder.with_header(header);
                     self.current_section = RdbSection::Metadata;
                 }
@@ -324,12 +568,38 @@ impl RdbFileDecoder {
                     builder.with_metadata(metadata);
                 }
                 RdbSection::Database => {
-                    let databases_decoder = DatabasesDecoder::new(self);
+                    let databases_decoder = DatabasesDecoder::new(self, on_key.as_deref_mut());
                     let databases = databases_decoder.decode().await?;
 
                     builder.with_databases(databases);
                 }
+                RdbSection::Function => {
+                    let function = StringDecoder::new(self).decode().await?;
+
+                    builder.with_function(function);
+                    self.decode_next_section().await?;
+                }
+                // Functions saved by Redis 7.0 release candidates have a different format, which Redis itself does
+                // not load either.
+                RdbSection::FunctionPreGa => {
+                    return Err(RdbFileDecoderError::InvalidSection(String::from(
+                        "Functions saved before Redis 7.0 GA cannot be loaded",
+                    )))
+                }
+                RdbSection::ModuleAux => {
+                    let module_aux = self.decode_module_aux().await?;
+
+                    builder.with_module_aux(module_aux);
+                    self.decode_next_section().await?;
+                }
                 RdbSection::EndOfFile => {
+                    // Files older than version 5 do not end with a checksum.
+                    if version >= MIN_CHECKSUM_VERSION {
+                        let checksum = self.decode_checksum().await?;
+
+                        builder.with_checksum(checksum);
+                    }
+
                     break;
                 }
             };
@@ -339,14 +609,101 @@ impl RdbFileDecoder {
 
         Ok(data)
     }
+
+    /// Decodes a value without its key and expiration: its type followed by its data, the same as the beginning of a
+    /// DUMP payload.
+    pub async fn decode_value(&mut self) -> Result<StoreData, RdbFileDecoderError> {
+        let byte = self.reader.read_u8().await?;
+        let value_type =
+            RdbValueType::from_byte(byte).ok_or(RdbFileDecoderError::InvalidValueType(byte))?;
+
+        let value = ValueDecoder::new(self).decode(value_type).await?;
+
+        match self.invalid_string.take() {
+            Some(err) => Err(RdbFileDecoderError::InvalidStringConversion(err)),
+            None => Ok(value),
+        }
+    }
+
+    // The store only keeps UTF-8 strings, but the strings of a key can not be skipped until the whole value is read.
+    // The invalid bytes are replaced meanwhile, and the error is kept so that the key is skipped once it is decoded.
+    fn string(&mut self, buf: Vec<u8>) -> String {
+        String::from_utf8(buf).unwrap_or_else(|err| {
+            let string = String::from_utf8_lossy(err.as_bytes()).into_owned();
+
+            self.invalid_string.get_or_insert(err);
+
+            string
+        })
+    }
+
+    /// Returns the reader, positioned right after the payload once it is decoded.
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    // Module auxiliary data is the module type ID, when it has to be loaded (with its opcode, always an unsigned
+    // integer) and the module fields.
+    async fn decode_module_aux(&mut self) -> Result<RdbModuleAux, RdbFileDecoderError> {
+        let mut value_decoder = ValueDecoder::new(self);
+        let id = value_decoder.decode_length().await? as u64;
+
+        if value_decoder.decode_length().await? != MODULE_OPCODE_UINT {
+            return Err(RdbFileDecoderError::InvalidValue(String::from(
+                "Invalid module aux when opcode",
+            )));
+        }
+
+        let when = value_decoder.decode_length().await? as u64;
+        let fields = value_decoder.decode_module_fields().await?;
+
+        Ok(RdbModuleAux {
+            module: StoreModuleValue {
+                id,
+                name: module_type_name(id),
+                fields,
+            },
+            when,
+        })
+    }
+
+    // Reads the opcode that follows a section which does not tell where it ends by itself.
+    async fn decode_next_section(&mut self) -> Result<(), RdbFileDecoderError> {
+        let byte = self.reader.read_u8().await?;
+
+        self.current_section = RdbSection::from_byte(byte).ok_or(
+            RdbFileDecoderError::InvalidSection(format!("Invalid section opcode {:X}", byte)),
+        )?;
+
+        Ok(())
+    }
+
+    // The checksum is the CRC64 of the whole file up to the end of file opcode (included), in little-endian. A zero
+    // checksum means that it was disabled when the file was written, so it is not verified.
+    async fn decode_checksum(&mut self) -> Result<u64, RdbFileDecoderError> {
+        let actual = self.reader.checksum();
+        let buf = self.reader.read_exact(8).await?;
+        let buf: [u8; 8] = buf.try_into().map_err(|_| {
+            RdbFileDecoderError::InvalidArrayConversion(String::from(
+                "Array expected to have a length of 8",
+            ))
+        })?;
+        let expected = u64::from_le_bytes(buf);
+
+        if expected != 0 && expected != actual {
+            return Err(RdbFileDecoderError::ChecksumMismatch { expected, actual });
+        }
+
+        Ok(expected)
+    }
 }
 
-struct HeaderDecoder<'a> {
-    rdb_decoder: &'a mut RdbFileDecoder,
+struct HeaderDecoder<'a, R> {
+    rdb_decoder: &'a mut RdbFileDecoder<R>,
 }
 
-impl<'a> HeaderDecoder<'a> {
-    pub fn new(rdb_decoder: &'a mut RdbFileDecoder) -> HeaderDecoder<'a> {
+impl<'a, R: AsyncRead + Unpin> HeaderDecoder<'a, R> {
+    pub fn new(rdb_decoder: &'a mut RdbFileDecoder<R>) -> HeaderDecoder<'a, R> {
         rdb_decoder.current_section = RdbSection::Header;
 
         HeaderDecoder { rdb_decoder }
@@ -376,12 +733,12 @@ impl<'a> HeaderDecoder<'a> {
     }
 }
 
-struct MetadataDecoder<'a> {
-    rdb_decoder: &'a mut RdbFileDecoder,
+struct MetadataDecoder<'a, R> {
+    rdb_decoder: &'a mut RdbFileDecoder<R>,
 }
 
-impl<'a> MetadataDecoder<'a> {
-    pub fn new(rdb_decoder: &'a mut RdbFileDecoder) -> MetadataDecoder<'a> {
+impl<'a, R: AsyncRead + Unpin> MetadataDecoder<'a, R> {
+    pub fn new(rdb_decoder: &'a mut RdbFileDecoder<R>) -> MetadataDecoder<'a, R> {
         rdb_decoder.current_section = RdbSection::Metadata;
 
         MetadataDecoder { rdb_decoder }
@@ -407,31 +764,37 @@ impl<'a> MetadataDecoder<'a> {
                 }
             }
 
-            // We write to buffer because while checking the section we lost one byte that it's important for following operations
-            self.rdb_decoder.reader.write_byte_to_buf(byte);
+            // We push the byte back because while checking the section we lost one byte that it's important for following operations
+            self.rdb_decoder.reader.push_back(byte);
 
             let key = StringDecoder::new(self.rdb_decoder).decode().await?;
 
             let value = StringDecoder::new(self.rdb_decoder).decode().await?;
 
-            if metadata.set_key(&key, &value).is_err() {
-                continue;
-            }
+            metadata.set_key(&key, &value);
         }
 
         Ok(metadata)
     }
 }
 
-struct DatabasesDecoder<'a> {
-    rdb_decoder: &'a mut RdbFileDecoder,
+struct DatabasesDecoder<'a, 'b, 'c, R> {
+    rdb_decoder: &'a mut RdbFileDecoder<R>,
+    // When it is set, the keys are passed to it instead of being added to their database.
+    on_key: Option<&'b mut RdbKeyHandler<'c>>,
 }
 
-impl<'a> DatabasesDecoder<'a> {
-    pub fn new(rdb_decoder: &'a mut RdbFileDecoder) -> DatabasesDecoder<'a> {
+impl<'a, 'b, 'c, R: AsyncRead + Unpin> DatabasesDecoder<'a, 'b, 'c, R> {
+    pub fn new(
+        rdb_decoder: &'a mut RdbFileDecoder<R>,
+        on_key: Option<&'b mut RdbKeyHandler<'c>>,
+    ) -> DatabasesDecoder<'a, 'b, 'c, R> {
         rdb_decoder.current_section = RdbSection::Database;
 
-        DatabasesDecoder { rdb_decoder }
+        DatabasesDecoder {
+            rdb_decoder,
+            on_key,
+        }
     }
 
     pub async fn decode(mut self) -> Result<RdbDataDatabases, RdbFileDecoderError> {
@@ -454,8 +817,8 @@ impl<'a> DatabasesDecoder<'a> {
                 }
             }
 
-            // We write to buffer because while checking the section we lost one byte that it's important for following operations
-            self.rdb_decoder.reader.write_byte_to_buf(byte);
+            // We push the byte back because while checking the section we lost one byte that it's important for following operations
+            self.rdb_decoder.reader.push_back(byte);
 
             let database = self.decode_database().await?;
 
@@ -476,19 +839,34 @@ impl<'a> DatabasesDecoder<'a> {
 
                 // Get database values
                 loop {
-                    let byte = self.rdb_decoder.reader.read_u8().await?;
-
-                    // We write to buffer because while checking the section we lost one byte that it's important for following operations
-                    self.rdb_decoder.reader.write_byte_to_buf(byte);
+                    let byte = self.rdb_decoder.reader.peek_u8().await?;
 
                     // We stop to check for more values when there is a new section
                     if RdbSection::from_byte(byte).is_some() {
                         return Ok(database);
                     }
 
-                    let (key, value) = self.decode_db_store_value().await?;
+                    let (key, value, metadata) = self.decode_db_store_value().await?;
+
+                    let invalid_string = self.rdb_decoder.invalid_string.take();
+                    let expired_value = std::mem::take(&mut self.rdb_decoder.expired_value);
+
+                    if invalid_string.is_some() {
+                        database.keys_skipped += 1;
+
+                        continue;
+                    }
+
+                    if expired_value {
+                        database.keys_expired += 1;
 
-                    database.set(&key, value);
+                        continue;
+                    }
+
+                    match self.on_key.as_mut() {
+                        Some(on_key) => on_key(index, key, value),
+                        None => database.set(&key, value, metadata),
+                    }
                 }
             }
             _ => Err(RdbFileDecoderError::MissingDbIndex),
@@ -501,21 +879,24 @@ impl<'a> DatabasesDecoder<'a> {
         Ok(db_index)
     }
 
-    async fn decode_size_information(&mut self) -> Result<(Size, Size), RdbFileDecoderError> {
+    // The hash table size information was added in version 7, so older files go straight to the keys.
+    async fn decode_size_information(
+        &mut self,
+    ) -> Result<Option<(Size, Size)>, RdbFileDecoderError> {
         let byte = self.rdb_decoder.reader.read_u8().await?;
 
         // Indicates that hash table size information follows.
         if format!("{:X}", byte).as_str() != "FB" {
-            return Err(RdbFileDecoderError::InvalidSection(String::from(
-                "Invalid database section: Size information is missing",
-            )));
+            self.rdb_decoder.reader.push_back(byte);
+
+            return Ok(None);
         }
 
         // Get size information
         let number_of_keys = SizeDecoder::new(self.rdb_decoder).decode().await?;
         let number_of_expired_keys = SizeDecoder::new(self.rdb_decoder).decode().await?;
 
-        Ok((number_of_keys, number_of_expired_keys))
+        Ok(Some((number_of_keys, number_of_expired_keys)))
     }
 
     async fn decode_expire_timestamp_in_seconds(&mut self) -> Result<u32, RdbFileDecoderError> {
@@ -538,26 +919,26 @@ impl<'a> DatabasesDecoder<'a> {
 
     async fn decode_db_key_value_pairs(
         &mut self,
-    ) -> Result<(String, Option<String>), RdbFileDecoderError> {
+    ) -> Result<(String, StoreData), RdbFileDecoderError> {
         let byte = self.rdb_decoder.reader.read_u8().await?;
-        let value_type = RdbValueType::from_byte(byte);
-
-        let key = StringDecoder::new(self.rdb_decoder).decode().await?;
+        // An unknown type can not be skipped, since its length is unknown too.
+        let value_type =
+            RdbValueType::from_byte(byte).ok_or(RdbFileDecoderError::InvalidValueType(byte))?;
 
-        let value = match value_type {
-            Some(RdbValueType::StringEncoding) => {
-                let str_value = StringDecoder::new(self.rdb_decoder).decode().await?;
-
-                Some(str_value)
-            }
-            _ => None,
-        };
+        let key = StringDecoder::new(self.rdb_decoder).decode_bytes().await?;
+        let key = self.rdb_decoder.string(key);
+        let value = ValueDecoder::new(self.rdb_decoder)
+            .decode(value_type)
+            .await?;
 
         Ok((key, value))
     }
 
-    async fn decode_db_store_value(&mut self) -> Result<(String, StoreValue), RdbFileDecoderError> {
+    async fn decode_db_store_value(
+        &mut self,
+    ) -> Result<(String, StoreValue, RdbKeyMetadata), RdbFileDecoderError> {
         let mut store_value_builder = StoreValueBuilder::new();
+        let mut metadata = RdbKeyMetadata::default();
         let key: String;
 
         loop {
@@ -566,8 +947,6 @@ impl<'a> DatabasesDecoder<'a> {
             match format!("{:X}", byte).as_str() {
                 // Expire timestamp information in miliseconds
                 "FC" => {
-                    self.rdb_decoder.reader.clear_buf();
-
                     let expire_timestamp_in_milis =
                         self.decode_expire_timestamp_in_miliseconds().await?;
 
@@ -582,8 +961,6 @@ impl<'a> DatabasesDecoder<'a> {
                 }
                 // Expire timestamp information in seconds
                 "FD" => {
-                    self.rdb_decoder.reader.clear_buf();
-
                     let expire_timestamp_in_seconds =
                         self.decode_expire_timestamp_in_seconds().await?;
                     let expire_timestamp_in_seconds = i32::try_from(expire_timestamp_in_seconds)
@@ -595,16 +972,31 @@ impl<'a> DatabasesDecoder<'a> {
                         store_value_builder.with_exp(exp);
                     }
                 }
+                // Idle time in seconds, saved when the eviction policy is LRU
+                "F8" => {
+                    let idle = SizeDecoder::new(self.rdb_decoder).decode_length().await?;
+
+                    metadata.idle = Some(idle as u64);
+                }
+                // Frequency counter, saved when the eviction policy is LFU
+                "F9" => {
+                    metadata.freq = Some(self.rdb_decoder.reader.read_u8().await?);
+                }
+                // The slot, its number of keys and its number of keys with an expiration, saved in cluster mode
+                // before the keys of every slot. The server does not run in cluster mode, so it is ignored.
+                "F4" => {
+                    for _ in 0..3 {
+                        SizeDecoder::new(self.rdb_decoder).decode_length().await?;
+                    }
+                }
                 _ => {
-                    self.rdb_decoder.reader.write_byte_to_buf(byte);
+                    self.rdb_decoder.reader.push_back(byte);
 
-                    let db_value = self.decode_db_key_value_pairs().await?;
+                    let (db_key, db_value) = self.decode_db_key_value_pairs().await?;
 
-                    if let Some(value) = db_value.clone().1 {
-                        store_value_builder.with_value(&value);
-                    }
+                    store_value_builder.with_data(db_value);
 
-                    key = db_value.0;
+                    key = db_key;
 
                     break;
                 }
@@ -613,7 +1005,7 @@ impl<'a> DatabasesDecoder<'a> {
 
         let store_value = store_value_builder.build();
 
-        Ok((key, store_value))
+        Ok((key, store_value, metadata))
     }
 }
 
@@ -625,12 +1017,12 @@ enum Size {
 
 /// Length encoding is used to store the length of the next object in the stream.
 /// Length encoding is a variable byte encoding designed to use as few bytes as possible.
-struct SizeDecoder<'a> {
-    rdb_decoder: &'a mut RdbFileDecoder,
+struct SizeDecoder<'a, R> {
+    rdb_decoder: &'a mut RdbFileDecoder<R>,
 }
 
-impl<'a> SizeDecoder<'a> {
-    fn new(rdb_decoder: &'a mut RdbFileDecoder) -> Self {
+impl<'a, R: AsyncRead + Unpin> SizeDecoder<'a, R> {
+    fn new(rdb_decoder: &'a mut RdbFileDecoder<R>) -> Self {
         Self { rdb_decoder }
     }
 
@@ -660,9 +1052,9 @@ impl<'a> SizeDecoder<'a> {
 
                 Ok(Size::Length(usize::from(size)))
             }
-            // If the first two bits are 0b10:
-            // Ignore the remaining 6 bits of the first byte. The size is the next 4 bytes, in big-endian (read left-to-right).
-            0b10 => {
+            // If the first byte is 0x80:
+            // The size is the next 4 bytes, in big-endian (read left-to-right).
+            0b10 if byte == 0x80 => {
                 let buf = self.rdb_decoder.reader.read_exact(4).await?;
                 let buf: [u8; 4] = buf.try_into().map_err(|_| {
                     RdbFileDecoderError::InvalidArrayConversion(String::from(
@@ -679,6 +1071,25 @@ impl<'a> SizeDecoder<'a> {
 
                 Ok(Size::Length(size))
             }
+            // If the first byte is 0x81:
+            // The size is the next 8 bytes, in big-endian. It is used for 64-bit numbers, like stream IDs.
+            0b10 if byte == 0x81 => {
+                let buf = self.rdb_decoder.reader.read_exact(8).await?;
+                let buf: [u8; 8] = buf.try_into().map_err(|_| {
+                    RdbFileDecoderError::InvalidArrayConversion(String::from(
+                        "Array expected to have a length of 8",
+                    ))
+                })?;
+
+                let size = u64::from_be_bytes(buf);
+                let size: usize = size.try_into().map_err(|_| {
+                    RdbFileDecoderError::InvalidNumberConversion(String::from(
+                        "Could not convert the value properly",
+                    ))
+                })?;
+
+                Ok(Size::Length(size))
+            }
             // If the first two bits are 0b11:
             // The remaining 6 bits specify a type of string encoding.
             0b11 => {
@@ -689,61 +1100,1108 @@ impl<'a> SizeDecoder<'a> {
             _ => Err(RdbFileDecoderError::InvalidSize),
         }
     }
+
+    // Decodes a size that must be a length (not a string encoding).
+    async fn decode_length(&mut self) -> Result<usize, RdbFileDecoderError> {
+        match self.decode().await? {
+            Size::Length(length) => Ok(length),
+            Size::StringType(_) => Err(RdbFileDecoderError::InvalidSize),
+        }
+    }
 }
 
-struct StringDecoder<'a> {
-    rdb_decoder: &'a mut RdbFileDecoder,
+struct StringDecoder<'a, R> {
+    rdb_decoder: &'a mut RdbFileDecoder<R>,
 }
 
-impl<'a> StringDecoder<'a> {
-    fn new(rdb_decoder: &'a mut RdbFileDecoder) -> Self {
+impl<'a, R: AsyncRead + Unpin> StringDecoder<'a, R> {
+    fn new(rdb_decoder: &'a mut RdbFileDecoder<R>) -> Self {
         Self { rdb_decoder }
     }
 
     async fn decode(&mut self) -> Result<String, RdbFileDecoderError> {
+        let buf = self.decode_bytes().await?;
+
+        String::from_utf8(buf).map_err(RdbFileDecoderError::InvalidStringConversion)
+    }
+
+    // Strings are binary safe, which is needed for the values that contain other encodings (ziplists, listpacks...).
+    async fn decode_bytes(&mut self) -> Result<Vec<u8>, RdbFileDecoderError> {
         let mut size_decoder = SizeDecoder::new(self.rdb_decoder);
         let size = size_decoder.decode().await?;
 
         match size {
-            Size::Length(length) => {
-                let buf = self.rdb_decoder.reader.read_exact(length).await?;
-
-                let value =
-                    String::from_utf8(buf).map_err(RdbFileDecoderError::InvalidStringConversion)?;
-
-                Ok(value)
-            }
+            Size::Length(length) => self.rdb_decoder.reader.read_exact(length).await,
             Size::StringType(byte) => match format!("{:X}", byte).as_str() {
-                // The 0xC0 size indicates the string is an 8-bit integer.
+                // The 0xC0 size indicates the string is an 8-bit signed integer.
                 "C0" => {
                     let byte = self.rdb_decoder.reader.read_u8().await?;
 
-                    let number = u8::from_le_bytes([byte]);
+                    let number = i8::from_le_bytes([byte]);
 
-                    Ok(number.to_string())
+                    Ok(number.to_string().into_bytes())
                 }
-                // The 0xC1 size indicates the string is a 16-bit integer.
+                // The 0xC1 size indicates the string is a 16-bit signed integer.
                 "C1" => {
                     let buf = self.rdb_decoder.reader.read_exact(2).await?;
 
-                    let number = u16::from_le_bytes([buf[0], buf[1]]);
+                    let number = i16::from_le_bytes([buf[0], buf[1]]);
 
-                    Ok(number.to_string())
+                    Ok(number.to_string().into_bytes())
                 }
-                // The 0xC2 size indicates the string is a 32-bit integer.
+                // The 0xC2 size indicates the string is a 32-bit signed integer.
                 "C2" => {
                     let buf = self.rdb_decoder.reader.read_exact(4).await?;
 
-                    let number = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                    let number = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
 
-                    Ok(number.to_string())
+                    Ok(number.to_string().into_bytes())
                 }
-                //  The 0xC3 size indicates that the string is compressed with the LZF algorithm.
+                // The 0xC3 size indicates that the string is compressed with the LZF algorithm. It is followed by the
+                // compressed length, the uncompressed length and the compressed bytes.
                 "C3" => {
-                    todo!("It is not necessary to implement a LZF algorithm for this challenge");
+                    let compressed_length = self.decode_length().await?;
+                    let length = self.decode_length().await?;
+                    let buf = self
+                        .rdb_decoder
+                        .reader
+                        .read_exact(compressed_length)
+                        .await?;
+
+                    lzf::decompress(&buf, length)
+                        .ok_or(RdbFileDecoderError::InvalidCompressedString)
                 }
                 _ => Err(RdbFileDecoderError::InvalidString),
             },
         }
     }
+
+    async fn decode_length(&mut self) -> Result<usize, RdbFileDecoderError> {
+        SizeDecoder::new(self.rdb_decoder).decode_length().await
+    }
+}
+
+/// Decodes the value of a key, which is encoded differently depending on its type.
+///
+/// Small collections are usually saved as a single string that contains a compact encoding (ziplist, listpack or
+/// intset), which is decoded once the whole string is read.
+struct ValueDecoder<'a, R> {
+    rdb_decoder: &'a mut RdbFileDecoder<R>,
+}
+
+impl<'a, R: AsyncRead + Unpin> ValueDecoder<'a, R> {
+    fn new(rdb_decoder: &'a mut RdbFileDecoder<R>) -> Self {
+        Self { rdb_decoder }
+    }
+
+    async fn decode(&mut self, value_type: RdbValueType) -> Result<StoreData, RdbFileDecoderError> {
+        match value_type {
+            RdbValueType::String => Ok(StoreData::String(self.decode_string().await?)),
+            RdbValueType::List => {
+                let length = self.decode_length().await?;
+                let mut values = VecDeque::with_capacity(length.min(MAX_PREALLOCATION));
+
+                for _ in 0..length {
+                    values.push_back(self.decode_string().await?);
+                }
+
+                Ok(StoreData::List(values))
+            }
+            RdbValueType::Set => {
+                let length = self.decode_length().await?;
+                let mut members = HashSet::with_capacity(length.min(MAX_PREALLOCATION));
+
+                for _ in 0..length {
+                    members.insert(self.decode_string().await?);
+                }
+
+                Ok(StoreData::Set(members))
+            }
+            RdbValueType::SortedSet | RdbValueType::SortedSet2 => {
+                let length = self.decode_length().await?;
+                let mut members = Vec::with_capacity(length.min(MAX_PREALLOCATION));
+
+                for _ in 0..length {
+                    let member = self.decode_string().await?;
+                    let score = match value_type {
+                        RdbValueType::SortedSet2 => self.decode_binary_double().await?,
+                        _ => self.decode_string_double().await?,
+                    };
+
+                    members.push((member, score));
+                }
+
+                Ok(sorted_set(members))
+            }
+            RdbValueType::Hash => {
+                let length = self.decode_length().await?;
+                let mut fields = HashMap::with_capacity(length.min(MAX_PREALLOCATION));
+
+                for _ in 0..length {
+                    let field = self.decode_string().await?;
+                    let value = self.decode_string().await?;
+
+                    fields.insert(field, value);
+                }
+
+                Ok(StoreData::Hash(fields))
+            }
+            RdbValueType::ModulePreGa => Err(RdbFileDecoderError::InvalidValue(String::from(
+                "Module values saved before Redis 4.0 GA cannot be loaded",
+            ))),
+            RdbValueType::Module2 => self.decode_module().await,
+            RdbValueType::HashZipmap => {
+                let elements = self.decode_encoded(zipmap::decode, "zipmap").await?;
+
+                Ok(StoreData::Hash(self.pairs(elements)?.into_iter().collect()))
+            }
+            RdbValueType::ListZiplist => {
+                let elements = self.decode_encoded(ziplist::decode, "ziplist").await?;
+
+                Ok(StoreData::List(
+                    self.strings(elements).into_iter().collect(),
+                ))
+            }
+            RdbValueType::SetIntset => {
+                let elements = self.decode_encoded(intset::decode, "intset").await?;
+
+                Ok(StoreData::Set(self.strings(elements).into_iter().collect()))
+            }
+            RdbValueType::SetListpack => {
+                let elements = self.decode_encoded(listpack::decode, "listpack").await?;
+
+                Ok(StoreData::Set(self.strings(elements).into_iter().collect()))
+            }
+            RdbValueType::SortedSetZiplist | RdbValueType::SortedSetListpack => {
+                let elements = match value_type {
+                    RdbValueType::SortedSetZiplist => {
+                        self.decode_encoded(ziplist::decode, "ziplist").await?
+                    }
+                    _ => self.decode_encoded(listpack::decode, "listpack").await?,
+                };
+
+                let members = self
+                    .pairs(elements)?
+                    .into_iter()
+                    .map(|(member, score)| Ok((member, parse_double(score.as_bytes())?)))
+                    .collect::<Result<Vec<(String, f64)>, RdbFileDecoderError>>()?;
+
+                Ok(sorted_set(members))
+            }
+            RdbValueType::HashZiplist | RdbValueType::HashListpack => {
+                let elements = match value_type {
+                    RdbValueType::HashZiplist => {
+                        self.decode_encoded(ziplist::decode, "ziplist").await?
+                    }
+                    _ => self.decode_encoded(listpack::decode, "listpack").await?,
+                };
+
+                Ok(StoreData::Hash(self.pairs(elements)?.into_iter().collect()))
+            }
+            // Hashes with field expirations (version 12). The store does not support field expirations, so the fields
+            // that have already expired are dropped and the rest are loaded without them.
+            RdbValueType::HashMetadataPreGa | RdbValueType::HashMetadata => {
+                // The minimum expiration time of the fields, which the release candidates did not save. Every field
+                // starts with its expiration time (relative to the minimum one, plus one) or 0 when it does not
+                // expire. The release candidates saved the absolute expiration time instead.
+                let min_expire = match value_type {
+                    RdbValueType::HashMetadata => Some(self.decode_milliseconds().await?),
+                    _ => None,
+                };
+
+                let length = self.decode_length().await?;
+                let mut fields = HashMap::with_capacity(length.min(MAX_PREALLOCATION));
+
+                for _ in 0..length {
+                    let expire_at = match (self.decode_length().await? as i64, min_expire) {
+                        (0, _) => 0,
+                        (ttl, Some(min_expire)) => ttl.saturating_add(min_expire) - 1,
+                        (ttl, None) => ttl,
+                    };
+
+                    let field = self.decode_string().await?;
+                    let value = self.decode_string().await?;
+
+                    if !self.is_field_expired(expire_at) {
+                        fields.insert(field, value);
+                    }
+                }
+
+                self.rdb_decoder.expired_value = length > 0 && fields.is_empty();
+
+                Ok(StoreData::Hash(fields))
+            }
+            // The listpack holds the field, the value and the expiration time (or 0) of every field. It is preceded
+            // by the minimum expiration time, except in the release candidates.
+            RdbValueType::HashListpackExPreGa | RdbValueType::HashListpackEx => {
+                if matches!(value_type, RdbValueType::HashListpackEx) {
+                    self.decode_milliseconds().await?;
+                }
+
+                let elements = self.decode_encoded(listpack::decode, "listpack").await?;
+
+                if elements.len() % 3 != 0 {
+                    return Err(RdbFileDecoderError::InvalidValue(String::from(
+                        "Invalid hash listpack with expirations",
+                    )));
+                }
+
+                let mut fields = vec![];
+
+                for triplet in elements.chunks_exact(3) {
+                    let expire_at = std::str::from_utf8(&triplet[2])
+                        .ok()
+                        .and_then(|expire_at| expire_at.parse().ok())
+                        .ok_or(RdbFileDecoderError::InvalidValue(String::from(
+                            "Invalid hash field expiration time",
+                        )))?;
+
+                    if !self.is_field_expired(expire_at) {
+                        fields.extend_from_slice(&triplet[0..2]);
+                    }
+                }
+
+                self.rdb_decoder.expired_value = !elements.is_empty() && fields.is_empty();
+
+                Ok(StoreData::Hash(self.pairs(fields)?.into_iter().collect()))
+            }
+            RdbValueType::ListQuicklist => {
+                let number_of_nodes = self.decode_length().await?;
+                let mut values = VecDeque::new();
+
+                for _ in 0..number_of_nodes {
+                    let elements = self.decode_encoded(ziplist::decode, "ziplist").await?;
+
+                    values.extend(self.strings(elements));
+                }
+
+                Ok(StoreData::List(values))
+            }
+            RdbValueType::ListQuicklist2 => {
+                let number_of_nodes = self.decode_length().await?;
+                let mut values = VecDeque::new();
+
+                for _ in 0..number_of_nodes {
+                    // Big elements are saved in their own node as a plain string, the rest of nodes are listpacks.
+                    match self.decode_length().await? {
+                        QUICKLIST_NODE_CONTAINER_PLAIN => {
+                            values.push_back(self.decode_string().await?);
+                        }
+                        QUICKLIST_NODE_CONTAINER_PACKED => {
+                            let elements =
+                                self.decode_encoded(listpack::decode, "listpack").await?;
+
+                            values.extend(self.strings(elements));
+                        }
+                        container => {
+                            return Err(RdbFileDecoderError::InvalidValue(format!(
+                                "Invalid quicklist node container {}",
+                                container
+                            )))
+                        }
+                    }
+                }
+
+                Ok(StoreData::List(values))
+            }
+            RdbValueType::StreamListpacks => self.decode_stream(1).await,
+            RdbValueType::StreamListpacks2 => self.decode_stream(2).await,
+            RdbValueType::StreamListpacks3 => self.decode_stream(3).await,
+        }
+    }
+
+    // Streams are saved as a radix tree of listpacks, followed by the stream metadata and its consumer groups. The
+    // version tells which fields are present: the second one added the first ID, the maximum deleted ID, the number
+    // of added entries and the entries read by every group, and the third one the consumers active time.
+    async fn decode_stream(&mut self, version: u8) -> Result<StoreData, RdbFileDecoderError> {
+        let mut stream = StoreStream::default();
+        let number_of_nodes = self.decode_length().await?;
+
+        for _ in 0..number_of_nodes {
+            let master_key = StringDecoder::new(self.rdb_decoder).decode_bytes().await?;
+            let master_id = parse_stream_id(&master_key)?;
+            let elements = self.decode_encoded(listpack::decode, "listpack").await?;
+            let elements = self.strings(elements);
+
+            decode_stream_node(master_id, &elements, &mut stream).ok_or(
+                RdbFileDecoderError::InvalidValue(String::from("Invalid stream listpack")),
+            )?;
+        }
+
+        // The number of entries, which is already known from the listpacks.
+        self.decode_length().await?;
+
+        stream.last_id = self.decode_stream_id().await?;
+
+        if version >= 2 {
+            stream.first_id = self.decode_stream_id().await?;
+            stream.max_deleted_id = self.decode_stream_id().await?;
+            stream.entries_added = self.decode_length().await? as u64;
+        } else {
+            stream.first_id = stream.entries.keys().next().copied().unwrap_or_default();
+            stream.entries_added = stream.entries.len() as u64;
+        }
+
+        let number_of_groups = self.decode_length().await?;
+
+        for _ in 0..number_of_groups {
+            let mut group = StoreStreamGroup {
+                name: self.decode_string().await?,
+                last_id: self.decode_stream_id().await?,
+                entries_read: -1,
+                ..StoreStreamGroup::default()
+            };
+
+            if version >= 2 {
+                // It is saved as an unsigned length, so -1 (unknown) is read back from its two's complement.
+                group.entries_read = self.decode_length().await? as i64;
+            }
+
+            let number_of_pending_entries = self.decode_length().await?;
+
+            for _ in 0..number_of_pending_entries {
+                let id = self.decode_raw_stream_id().await?;
+                let delivery_time = self.decode_milliseconds().await?;
+                let delivery_count = self.decode_length().await? as u64;
+
+                group.pending.push(StoreStreamPendingEntry {
+                    id,
+                    delivery_time,
+                    delivery_count,
+                });
+            }
+
+            let number_of_consumers = self.decode_length().await?;
+
+            for _ in 0..number_of_consumers {
+                let name = self.decode_string().await?;
+                let seen_time = self.decode_milliseconds().await?;
+                // Before the third version, the last interaction is the best estimate of the last successful one.
+                let active_time = if version >= 3 {
+                    self.decode_milliseconds().await?
+                } else {
+                    seen_time
+                };
+                let number_of_pending_entries = self.decode_length().await?;
+                let mut pending =
+                    Vec::with_capacity(number_of_pending_entries.min(MAX_PREALLOCATION));
+
+                for _ in 0..number_of_pending_entries {
+                    pending.push(self.decode_raw_stream_id().await?);
+                }
+
+                group.consumers.push(StoreStreamConsumer {
+                    name,
+                    seen_time,
+                    active_time,
+                    pending,
+                });
+            }
+
+            stream.groups.push(group);
+        }
+
+        Ok(StoreData::Stream(stream))
+    }
+
+    // Module values start with the module type ID, followed by the fields saved by the module. Every field starts
+    // with an opcode that tells its type, so they can be read without loading the module.
+    async fn decode_module(&mut self) -> Result<StoreData, RdbFileDecoderError> {
+        let id = self.decode_length().await? as u64;
+        let fields = self.decode_module_fields().await?;
+
+        Ok(StoreData::Module(StoreModuleValue {
+            id,
+            name: module_type_name(id),
+            fields,
+        }))
+    }
+
+    async fn decode_module_fields(&mut self) -> Result<Vec<StoreModuleField>, RdbFileDecoderError> {
+        let mut fields = Vec::new();
+
+        loop {
+            let field = match self.decode_length().await? {
+                MODULE_OPCODE_EOF => break,
+                MODULE_OPCODE_SINT => {
+                    StoreModuleField::SignedInt(self.decode_length().await? as i64)
+                }
+                MODULE_OPCODE_UINT => {
+                    StoreModuleField::UnsignedInt(self.decode_length().await? as u64)
+                }
+                MODULE_OPCODE_FLOAT => {
+                    let buf = self.read_array::<4>().await?;
+
+                    StoreModuleField::Float(f32::from_le_bytes(buf))
+                }
+                MODULE_OPCODE_DOUBLE => {
+                    StoreModuleField::Double(self.decode_binary_double().await?)
+                }
+                MODULE_OPCODE_STRING => StoreModuleField::String(
+                    StringDecoder::new(self.rdb_decoder).decode_bytes().await?,
+                ),
+                opcode => {
+                    return Err(RdbFileDecoderError::InvalidValue(format!(
+                        "Invalid module opcode {}",
+                        opcode
+                    )))
+                }
+            };
+
+            fields.push(field);
+        }
+
+        Ok(fields)
+    }
+
+    // Reads a string that contains a compact encoding and returns its elements.
+    async fn decode_encoded(
+        &mut self,
+        decode: fn(&[u8]) -> Option<Vec<Vec<u8>>>,
+        encoding: &str,
+    ) -> Result<Vec<Vec<u8>>, RdbFileDecoderError> {
+        let buf = StringDecoder::new(self.rdb_decoder).decode_bytes().await?;
+
+        decode(&buf).ok_or(RdbFileDecoderError::InvalidValue(format!(
+            "Invalid {}",
+            encoding
+        )))
+    }
+
+    async fn decode_string(&mut self) -> Result<String, RdbFileDecoderError> {
+        let buf = StringDecoder::new(self.rdb_decoder).decode_bytes().await?;
+
+        Ok(self.rdb_decoder.string(buf))
+    }
+
+    // Whether a hash field expiration time (in Unix milliseconds, or 0 when it does not expire) has already passed.
+    fn is_field_expired(&self, expire_at: i64) -> bool {
+        expire_at != 0
+            && self
+                .rdb_decoder
+                .now
+                .is_some_and(|now| expire_at < now.timestamp_millis())
+    }
+
+    fn strings(&mut self, elements: Vec<Vec<u8>>) -> Vec<String> {
+        elements
+            .into_iter()
+            .map(|element| self.rdb_decoder.string(element))
+            .collect()
+    }
+
+    fn pairs(
+        &mut self,
+        elements: Vec<Vec<u8>>,
+    ) -> Result<Vec<(String, String)>, RdbFileDecoderError> {
+        let elements = self.strings(elements);
+
+        if !elements.chunks_exact(2).remainder().is_empty() {
+            return Err(RdbFileDecoderError::InvalidValue(String::from(
+                "Expected an even number of elements",
+            )));
+        }
+
+        Ok(elements
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect())
+    }
+
+    async fn decode_length(&mut self) -> Result<usize, RdbFileDecoderError> {
+        SizeDecoder::new(self.rdb_decoder).decode_length().await
+    }
+
+    // Old sorted sets save scores as strings prefixed by their length. Lengths 253, 254 and 255 are used for NaN,
+    // positive and negative infinity.
+    async fn decode_string_double(&mut self) -> Result<f64, RdbFileDecoderError> {
+        match self.rdb_decoder.reader.read_u8().await? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            length => {
+                let buf = self
+                    .rdb_decoder
+                    .reader
+                    .read_exact(usize::from(length))
+                    .await?;
+
+                parse_double(&buf)
+            }
+        }
+    }
+
+    async fn decode_binary_double(&mut self) -> Result<f64, RdbFileDecoderError> {
+        let buf = self.read_array::<8>().await?;
+
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    async fn decode_milliseconds(&mut self) -> Result<i64, RdbFileDecoderError> {
+        let buf = self.read_array::<8>().await?;
+
+        Ok(i64::from_le_bytes(buf))
+    }
+
+    async fn decode_stream_id(&mut self) -> Result<StreamId, RdbFileDecoderError> {
+        let ms = self.decode_length().await? as u64;
+        let seq = self.decode_length().await? as u64;
+
+        Ok(StreamId { ms, seq })
+    }
+
+    async fn decode_raw_stream_id(&mut self) -> Result<StreamId, RdbFileDecoderError> {
+        let buf = self.read_array::<16>().await?;
+
+        parse_stream_id(&buf)
+    }
+
+    async fn read_array<const N: usize>(&mut self) -> Result<[u8; N], RdbFileDecoderError> {
+        let buf = self.rdb_decoder.reader.read_exact(N).await?;
+
+        buf.try_into().map_err(|_| {
+            RdbFileDecoderError::InvalidArrayConversion(format!(
+                "Array expected to have a length of {}",
+                N
+            ))
+        })
+    }
+}
+
+// Every stream listpack starts with a master entry: the number of valid and deleted entries, and the fields of the
+// first entry. Entries store their ID as the difference with the master ID (the listpack key), and their fields
+// only when they are not the same as the master fields. Every entry ends with its number of elements, which is
+// only needed for reading the listpack backwards.
+fn decode_stream_node(
+    master_id: StreamId,
+    elements: &[String],
+    stream: &mut StoreStream,
+) -> Option<()> {
+    let mut elements = elements.iter();
+    let mut next_string = || elements.next().cloned();
+    let count: u64 = next_string()?.parse().ok()?;
+    let deleted: u64 = next_string()?.parse().ok()?;
+    let number_of_master_fields: usize = next_string()?.parse().ok()?;
+    let master_fields = (0..number_of_master_fields)
+        .map(|_| next_string())
+        .collect::<Option<Vec<String>>>()?;
+
+    // The master entry terminator.
+    next_string()?;
+
+    for _ in 0..count + deleted {
+        let flags: u8 = next_string()?.parse().ok()?;
+        let ms_diff: i64 = next_string()?.parse().ok()?;
+        let seq_diff: i64 = next_string()?.parse().ok()?;
+
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), next_string()?)))
+                .collect::<Option<Vec<(String, String)>>>()?
+        } else {
+            let number_of_fields: usize = next_string()?.parse().ok()?;
+
+            (0..number_of_fields)
+                .map(|_| Some((next_string()?, next_string()?)))
+                .collect::<Option<Vec<(String, String)>>>()?
+        };
+
+        // The number of elements of the entry.
+        next_string()?;
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            let id = StreamId {
+                ms: master_id.ms.wrapping_add(ms_diff as u64),
+                seq: master_id.seq.wrapping_add(seq_diff as u64),
+            };
+
+            stream.entries.insert(id, fields);
+        }
+    }
+
+    Some(())
+}
+
+// Raw stream IDs are saved as two big-endian 64-bit numbers, so they are sorted the same as their bytes.
+fn parse_stream_id(buf: &[u8]) -> Result<StreamId, RdbFileDecoderError> {
+    let buf: [u8; 16] = buf.try_into().map_err(|_| {
+        RdbFileDecoderError::InvalidArrayConversion(String::from(
+            "Array expected to have a length of 16",
+        ))
+    })?;
+
+    Ok(StreamId {
+        ms: u64::from_be_bytes([
+            buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7],
+        ]),
+        seq: u64::from_be_bytes([
+            buf[8], buf[9], buf[10], buf[11], buf[12], buf[13], buf[14], buf[15],
+        ]),
+    })
+}
+
+fn parse_double(buf: &[u8]) -> Result<f64, RdbFileDecoderError> {
+    std::str::from_utf8(buf)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or(RdbFileDecoderError::InvalidValue(String::from(
+            "Invalid sorted set score",
+        )))
+}
+
+// The module type name is encoded in the 54 most significant bits of the ID, 6 bits per character.
+fn module_type_name(id: u64) -> String {
+    let mut bits = id >> 10;
+    let mut name = [0u8; 9];
+
+    for character in name.iter_mut().rev() {
+        *character = MODULE_TYPE_NAME_CHARSET[(bits & 63) as usize];
+        bits >>= 6;
+    }
+
+    String::from_utf8_lossy(&name).to_string()
+}
+
+fn sorted_set(mut members: Vec<(String, f64)>) -> StoreData {
+    members.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+
+    StoreData::SortedSet(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::super::crc64::crc64;
+    use super::*;
+
+    // The minimum expiration time of the hashes of the tests, in Unix milliseconds.
+    const MIN_EXPIRE: i64 = 1_700_000_000_000;
+
+    fn push_length(buf: &mut Vec<u8>, length: usize) {
+        match length {
+            0..=63 => buf.push(length as u8),
+            _ => buf.extend_from_slice(&[0x40 | (length >> 8) as u8, length as u8]),
+        }
+    }
+
+    fn push_string(buf: &mut Vec<u8>, string: &[u8]) {
+        push_length(buf, string.len());
+        buf.extend_from_slice(string);
+    }
+
+    // A hash with field expirations in the metadata encoding, with the expiration times relative to `MIN_EXPIRE`.
+    fn hash_metadata(fields: &[(&str, &str, i64)]) -> Vec<u8> {
+        let mut buf = vec![24];
+
+        buf.extend_from_slice(&MIN_EXPIRE.to_le_bytes());
+        push_length(&mut buf, fields.len());
+
+        for (field, value, expire_at) in fields {
+            match expire_at {
+                0 => push_length(&mut buf, 0),
+                expire_at => push_length(&mut buf, (expire_at - MIN_EXPIRE + 1) as usize),
+            }
+
+            push_string(&mut buf, field.as_bytes());
+            push_string(&mut buf, value.as_bytes());
+        }
+
+        buf
+    }
+
+    // A hash with field expirations in the listpack encoding.
+    fn hash_listpack_ex(fields: &[(&str, &str, i64)]) -> Vec<u8> {
+        let mut buf = vec![25];
+        let elements: Vec<Vec<u8>> = fields
+            .iter()
+            .flat_map(|(field, value, expire_at)| {
+                [
+                    field.as_bytes().to_vec(),
+                    value.as_bytes().to_vec(),
+                    expire_at.to_string().into_bytes(),
+                ]
+            })
+            .collect();
+
+        buf.extend_from_slice(&MIN_EXPIRE.to_le_bytes());
+        push_string(&mut buf, &listpack::encode(&elements));
+
+        buf
+    }
+
+    async fn decode_value(payload: &[u8], now: Option<i64>) -> StoreData {
+        let mut decoder = RdbFileDecoder::new(payload);
+
+        if let Some(now) = now {
+            decoder.with_now(Utc.timestamp_millis_opt(now).unwrap());
+        }
+
+        decoder.decode_value().await.unwrap()
+    }
+
+    fn hash(fields: &[(&str, &str)]) -> StoreData {
+        StoreData::Hash(
+            fields
+                .iter()
+                .map(|(field, value)| (field.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn expired_hash_fields_are_dropped() {
+        let fields = [
+            ("a", "1", MIN_EXPIRE),
+            ("b", "2", 0),
+            ("c", "3", MIN_EXPIRE + 10),
+        ];
+
+        for payload in [hash_metadata(&fields), hash_listpack_ex(&fields)] {
+            assert_eq!(
+                decode_value(&payload, None).await,
+                hash(&[("a", "1"), ("b", "2"), ("c", "3")])
+            );
+            assert_eq!(
+                decode_value(&payload, Some(MIN_EXPIRE)).await,
+                hash(&[("a", "1"), ("b", "2"), ("c", "3")])
+            );
+            assert_eq!(
+                decode_value(&payload, Some(MIN_EXPIRE + 1)).await,
+                hash(&[("b", "2"), ("c", "3")])
+            );
+            assert_eq!(
+                decode_value(&payload, Some(MIN_EXPIRE + 11)).await,
+                hash(&[("b", "2")])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn hashes_without_fields_left_are_not_loaded() {
+        let expired = [("a", "1", MIN_EXPIRE), ("b", "2", MIN_EXPIRE + 10)];
+        let mut file = b"REDIS0012".to_vec();
+
+        file.extend_from_slice(&[0xFE, 0x00]);
+
+        for (key, value) in [
+            ("metadata", hash_metadata(&expired)),
+            ("listpack", hash_listpack_ex(&expired)),
+        ] {
+            file.push(value[0]);
+            push_string(&mut file, key.as_bytes());
+            file.extend_from_slice(&value[1..]);
+        }
+
+        file.push(0x00);
+        push_string(&mut file, b"string");
+        push_string(&mut file, b"value");
+        // A checksum of 0 is not checked.
+        file.push(0xFF);
+        file.extend_from_slice(&[0; 8]);
+
+        let mut decoder = RdbFileDecoder::new(file.as_slice());
+
+        decoder.with_now(Utc.timestamp_millis_opt(MIN_EXPIRE + 11).unwrap());
+
+        let data = decoder.decode().await.unwrap();
+        let database = &data.databases.unwrap().databases[&0];
+
+        assert_eq!(database.keys_expired, 2);
+        assert_eq!(database.keys_skipped, 0);
+        assert_eq!(
+            database.data.keys().collect::<Vec<&String>>(),
+            vec!["string"]
+        );
+    }
+
+    // An RDB file with the given sections after the header, ending with its checksum.
+    fn rdb_file(sections: &[u8]) -> Vec<u8> {
+        let mut file = b"REDIS0012".to_vec();
+
+        file.extend_from_slice(sections);
+        file.push(0xFF);
+
+        let checksum = crc64(&file);
+
+        file.extend_from_slice(&checksum.to_le_bytes());
+
+        file
+    }
+
+    async fn decode_length(input: &[u8]) -> Result<usize, RdbFileDecoderError> {
+        SizeDecoder::new(&mut RdbFileDecoder::new(input))
+            .decode_length()
+            .await
+    }
+
+    async fn decode_string(input: &[u8]) -> String {
+        StringDecoder::new(&mut RdbFileDecoder::new(input))
+            .decode()
+            .await
+            .unwrap()
+    }
+
+    fn key_value(database: &RdbDatabase, key: &str) -> StoreValue {
+        database.data.get(key).cloned().unwrap()
+    }
+
+    #[tokio::test]
+    async fn lengths_are_decoded_in_every_encoding() {
+        assert_eq!(decode_length(&[0x3F]).await.unwrap(), 63);
+        assert_eq!(decode_length(&[0x7F, 0xFF]).await.unwrap(), 16383);
+        assert_eq!(
+            decode_length(&[0x80, 0x00, 0x01, 0x00, 0x00])
+                .await
+                .unwrap(),
+            65536
+        );
+        assert_eq!(
+            decode_length(&[0x81, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00])
+                .await
+                .unwrap(),
+            1 << 32
+        );
+        assert!(matches!(
+            decode_length(&[0x82]).await,
+            Err(RdbFileDecoderError::InvalidSize)
+        ));
+        // String encodings are not lengths.
+        assert!(matches!(
+            decode_length(&[0xC0]).await,
+            Err(RdbFileDecoderError::InvalidSize)
+        ));
+    }
+
+    #[tokio::test]
+    async fn integer_and_compressed_strings_are_decoded() {
+        assert_eq!(decode_string(&[0xC0, 0xFF]).await, "-1");
+        assert_eq!(decode_string(&[0xC1, 0x00, 0x80]).await, "-32768");
+
+        let mut int32 = vec![0xC2];
+
+        int32.extend_from_slice(&(-100_000i32).to_le_bytes());
+        assert_eq!(decode_string(&int32).await, "-100000");
+
+        let value = "abc".repeat(50);
+        let compressed = lzf::compress(value.as_bytes());
+        let mut lzf_string = vec![0xC3];
+
+        push_length(&mut lzf_string, compressed.len());
+        push_length(&mut lzf_string, value.len());
+        lzf_string.extend_from_slice(&compressed);
+        assert_eq!(decode_string(&lzf_string).await, value);
+    }
+
+    #[tokio::test]
+    async fn every_opcode_of_a_file_is_decoded() {
+        let mut sections = Vec::new();
+
+        // Auxiliary fields, including the ones that the server does not use.
+        for (key, value) in [("redis-ver", "7.2.0"), ("repl-id", "abc")] {
+            sections.push(0xFA);
+            push_string(&mut sections, key.as_bytes());
+            push_string(&mut sections, value.as_bytes());
+        }
+
+        sections.push(0xFA);
+        push_string(&mut sections, b"aof-base");
+        sections.extend_from_slice(&[0xC0, 0x00]);
+
+        // A function library.
+        sections.push(0xF5);
+        push_string(&mut sections, b"#!lua name=lib");
+
+        // A database with its size, and keys with an expiration in milliseconds, an idle time and a frequency.
+        sections.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x03, 0x01]);
+        sections.push(0xFC);
+        sections.extend_from_slice(&1_700_000_000_123u64.to_le_bytes());
+        sections.push(0x00);
+        push_string(&mut sections, b"a");
+        push_string(&mut sections, b"1");
+        sections.extend_from_slice(&[0xF8, 0x05, 0x00]);
+        push_string(&mut sections, b"b");
+        push_string(&mut sections, b"2");
+        sections.extend_from_slice(&[0xF9, 0x07, 0x00]);
+        push_string(&mut sections, b"c");
+        push_string(&mut sections, b"3");
+
+        // Another database, with an expiration in seconds and the slot information of cluster mode.
+        sections.extend_from_slice(&[0xFE, 0x01]);
+        sections.push(0xFD);
+        sections.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+        sections.push(0x00);
+        push_string(&mut sections, b"d");
+        push_string(&mut sections, b"4");
+        sections.extend_from_slice(&[0xF4, 0x01, 0x01, 0x00, 0x00]);
+        push_string(&mut sections, b"e");
+        push_string(&mut sections, b"5");
+
+        let file = rdb_file(&sections);
+        let data = RdbFileDecoder::new(file.as_slice()).decode().await.unwrap();
+        let metadata = data.metadata.unwrap();
+        let databases = data.databases.unwrap().databases;
+
+        assert_eq!(data.header.version, "0012");
+        assert_eq!(metadata.redis_ver(), Some("7.2.0"));
+        assert_eq!(metadata.get("repl-id"), Some("abc"));
+        assert_eq!(metadata.get("aof-base"), Some("0"));
+        assert_eq!(data.functions, vec![String::from("#!lua name=lib")]);
+        assert_eq!(
+            data.checksum,
+            u64::from_le_bytes(file[file.len() - 8..].try_into().unwrap())
+        );
+
+        assert_eq!(databases[&0].data.len(), 3);
+        assert_eq!(
+            key_value(&databases[&0], "a").exp,
+            Utc.timestamp_millis_opt(1_700_000_000_123).single()
+        );
+        assert_eq!(databases[&0].metadata["b"].idle, Some(5));
+        assert_eq!(databases[&0].metadata["c"].freq, Some(7));
+        assert!(!databases[&0].metadata.contains_key("a"));
+
+        assert_eq!(
+            key_value(&databases[&1], "d").exp,
+            Utc.timestamp_opt(1_700_000_000, 0).single()
+        );
+        assert_eq!(
+            key_value(&databases[&1], "e").value,
+            StoreData::String(String::from("5"))
+        );
+    }
+
+    #[tokio::test]
+    async fn files_are_checked_against_their_checksum() {
+        let mut file = rdb_file(&[0xFE, 0x00, 0x00, 0x01, b'a', 0x01, b'1']);
+        let last = file.len() - 1;
+
+        file[last] ^= 0xFF;
+
+        assert!(matches!(
+            RdbFileDecoder::new(file.as_slice()).decode().await,
+            Err(RdbFileDecoderError::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn only_known_versions_are_decoded() {
+        let mut file = rdb_file(&[]);
+
+        file[5..9].copy_from_slice(b"0013");
+
+        assert!(matches!(
+            RdbFileDecoder::new(file.as_slice()).decode().await,
+            Err(RdbFileDecoderError::UnsupportedVersion(13))
+        ));
+
+        // Files older than version 5 have no checksum.
+        let data = RdbFileDecoder::new(&b"REDIS0003\xFE\x00\x00\x01a\x011\xFF"[..])
+            .decode()
+            .await
+            .unwrap();
+
+        assert_eq!(data.databases.unwrap().databases[&0].data.len(), 1);
+    }
+
+    // A reader that returns a single byte on every read, the same as a socket that receives the payload slowly.
+    struct ByteByByteReader<'a>(&'a [u8]);
+
+    impl AsyncRead for ByteByByteReader<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if let Some((byte, rest)) = self.0.split_first() {
+                buf.put_slice(&[*byte]);
+                self.0 = rest;
+            }
+
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    fn string_file() -> Vec<u8> {
+        let mut sections = vec![0xFA];
+
+        push_string(&mut sections, b"redis-ver");
+        push_string(&mut sections, b"7.2.0");
+        sections.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x01, 0x00, 0x00]);
+        push_string(&mut sections, b"key");
+        push_string(&mut sections, "value".repeat(20).as_bytes());
+
+        rdb_file(&sections)
+    }
+
+    #[tokio::test]
+    async fn short_reads_are_completed() {
+        let file = string_file();
+        let data = RdbFileDecoder::new(ByteByByteReader(&file))
+            .decode()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            key_value(&data.databases.unwrap().databases[&0], "key").value,
+            StoreData::String("value".repeat(20))
+        );
+    }
+
+    #[tokio::test]
+    async fn pushed_back_bytes_are_read_first() {
+        let mut reader = RdbFileReader::new(&b"cd"[..]);
+
+        reader.push_back(b'b');
+        reader.push_back(b'a');
+
+        assert_eq!(reader.read_exact(1).await.unwrap(), b"a");
+        assert_eq!(reader.peek_u8().await.unwrap(), b'b');
+        assert_eq!(reader.read_exact(3).await.unwrap(), b"bcd");
+        assert!(matches!(
+            reader.read_exact(1).await,
+            Err(RdbFileDecoderError::ReadFile(_))
+        ));
+        // Only the bytes of the input are part of the checksum.
+        assert_eq!(reader.checksum(), crc64(b"cd"));
+    }
+
+    #[tokio::test]
+    async fn truncated_payloads_fail() {
+        let file = string_file();
+
+        for length in [4, 20, file.len() - 1] {
+            assert!(matches!(
+                RdbFileDecoder::new(ByteByByteReader(&file[..length]))
+                    .decode()
+                    .await,
+                Err(RdbFileDecoderError::ReadFile(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn the_reader_can_be_used_after_the_payload() {
+        let (mut client, server) = tokio::io::duplex(16);
+        let mut payload = string_file();
+
+        payload.extend_from_slice(b"+OK\r\n");
+
+        let writer = tokio::spawn(async move {
+            tokio::io::AsyncWriteExt::write_all(&mut client, &payload)
+                .await
+                .unwrap();
+        });
+        let mut decoder = RdbFileDecoder::new(server);
+
+        decoder.decode().await.unwrap();
+        writer.await.unwrap();
+
+        let mut rest = Vec::new();
+
+        decoder.into_inner().read_to_end(&mut rest).await.unwrap();
+
+        assert_eq!(rest, b"+OK\r\n");
+    }
 }
@@ -0,0 +1,243 @@
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::store::StoreData;
+
+use super::crc64::crc64;
+use super::decoder::{RdbFileDecoder, RdbFileDecoderError, MAX_VERSION};
+use super::encoder::{RdbFileEncoder, RdbFileEncoderError};
+
+/// The RDB version (2 bytes) and the checksum (8 bytes) that end every payload.
+const FOOTER_LENGTH: usize = 10;
+
+#[derive(Debug)]
+pub enum DumpError {
+    /// The payload was created by a newer RDB version, or its checksum does not match its content.
+    InvalidFooter,
+    InvalidValue(RdbFileDecoderError),
+    Encode(RdbFileEncoderError),
+}
+
+impl std::fmt::Display for DumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DumpError::InvalidFooter => {
+                write!(f, "DUMP payload version or checksum are wrong")
+            }
+            DumpError::InvalidValue(err) => {
+                write!(f, "Bad data format: {}", err)
+            }
+            DumpError::Encode(err) => {
+                write!(f, "DUMP payload could not be created: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DumpError {}
+
+/// Serializes a value the same as DUMP does in Redis: its type and its data in the RDB format, followed by the RDB
+/// version and the CRC64 of everything before it, both in little-endian. The key and the expiration are not part of
+/// the payload.
+pub fn dump_value(data: &StoreData) -> Result<Vec<u8>, DumpError> {
+    let mut encoder = RdbFileEncoder::new(Vec::new());
+
+    encoder.encode_value(data).map_err(DumpError::Encode)?;
+    encoder.finish_dump().map_err(DumpError::Encode)
+}
+
+/// Deserializes a payload created by `dump_value` or by Redis. The footer is verified before decoding the value, and
+/// the value has to take the rest of the payload.
+pub fn restore_value(payload: &[u8]) -> Result<StoreData, DumpError> {
+    if payload.len() < FOOTER_LENGTH {
+        return Err(DumpError::InvalidFooter);
+    }
+
+    let (content, checksum) = payload.split_at(payload.len() - 8);
+    let (mut value, version) = content.split_at(content.len() - 2);
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    let checksum = u64::from_le_bytes(checksum.try_into().map_err(|_| DumpError::InvalidFooter)?);
+
+    if u32::from(version) > MAX_VERSION || crc64(content) != checksum {
+        return Err(DumpError::InvalidFooter);
+    }
+
+    let data = poll_once(RdbFileDecoder::new(&mut value).decode_value())
+        .ok_or(DumpError::InvalidValue(RdbFileDecoderError::EmptyBuffer))?
+        .map_err(DumpError::InvalidValue)?;
+
+    if !value.is_empty() {
+        return Err(DumpError::InvalidValue(RdbFileDecoderError::InvalidValue(
+            format!("{} bytes found after the value", value.len()),
+        )));
+    }
+
+    Ok(data)
+}
+
+// Reading from memory never waits, so the decoder is done the first time it is polled. This way payloads can be
+// decoded by commands, which are not async.
+fn poll_once<T>(future: impl Future<Output = T>) -> Option<T> {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(value) => Some(value),
+        Poll::Pending => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+    use crate::store::{
+        StoreModuleField, StoreModuleValue, StoreStream, StoreStreamConsumer, StoreStreamGroup,
+        StoreStreamPendingEntry, StreamId,
+    };
+
+    use super::*;
+
+    fn assert_round_trip(data: StoreData) {
+        let payload = dump_value(&data).expect("The value should be dumped");
+
+        assert_eq!(
+            restore_value(&payload).expect("The payload should be restored"),
+            data
+        );
+    }
+
+    fn strings(values: impl IntoIterator<Item = impl ToString>) -> Vec<String> {
+        values.into_iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn dump_is_the_same_as_redis() {
+        let payload = dump_value(&StoreData::String(String::from("bar"))).unwrap();
+
+        assert_eq!(
+            payload,
+            [
+                0x00, 0x03, b'b', b'a', b'r', 0x0b, 0x00, 0x8f, 0x61, 0xf4, 0x13, 0x13, 0xf9, 0x14,
+                0x9e
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_of_strings() {
+        assert_round_trip(StoreData::String(String::new()));
+        assert_round_trip(StoreData::String(String::from("value")));
+        assert_round_trip(StoreData::String(String::from("-12345")));
+        assert_round_trip(StoreData::String("long value ".repeat(1000)));
+    }
+
+    #[test]
+    fn round_trip_of_lists() {
+        assert_round_trip(StoreData::List(VecDeque::new()));
+        // More elements than a quicklist node, with integers and long strings.
+        assert_round_trip(StoreData::List(
+            strings(0..300)
+                .into_iter()
+                .chain([String::from("element"), "x".repeat(5000)])
+                .collect(),
+        ));
+    }
+
+    #[test]
+    fn round_trip_of_sets() {
+        assert_round_trip(StoreData::Set(HashSet::new()));
+        assert_round_trip(StoreData::Set(
+            strings(["a", "b", "1", "-2"]).into_iter().collect(),
+        ));
+    }
+
+    #[test]
+    fn round_trip_of_hashes() {
+        assert_round_trip(StoreData::Hash(HashMap::from([
+            (String::from("field"), String::from("value")),
+            (String::from("number"), String::from("42")),
+            (String::from("empty"), String::new()),
+        ])));
+    }
+
+    #[test]
+    fn round_trip_of_sorted_sets() {
+        assert_round_trip(StoreData::SortedSet(vec![
+            (String::from("lowest"), f64::NEG_INFINITY),
+            (String::from("a"), -1.5),
+            (String::from("b"), 0.0),
+            (String::from("c"), 0.0),
+            (String::from("d"), 3.25),
+            (String::from("highest"), f64::INFINITY),
+        ]));
+    }
+
+    #[test]
+    fn round_trip_of_streams() {
+        let id = |ms, seq| StreamId { ms, seq };
+        let fields = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(field, value)| (field.to_string(), value.to_string()))
+                .collect::<Vec<(String, String)>>()
+        };
+        let stream = StoreStream {
+            entries: BTreeMap::from([
+                (id(1, 0), fields(&[("name", "a"), ("count", "1")])),
+                (id(1, 1), fields(&[("name", "b"), ("count", "2")])),
+                (id(5, 0), fields(&[("other", "fields")])),
+            ]),
+            last_id: id(6, 0),
+            first_id: id(1, 0),
+            max_deleted_id: id(6, 0),
+            entries_added: 4,
+            groups: vec![StoreStreamGroup {
+                name: String::from("group"),
+                last_id: id(1, 1),
+                entries_read: 2,
+                pending: vec![StoreStreamPendingEntry {
+                    id: id(1, 1),
+                    delivery_time: 1_700_000_000_000,
+                    delivery_count: 2,
+                }],
+                consumers: vec![StoreStreamConsumer {
+                    name: String::from("consumer"),
+                    seen_time: 1_700_000_000_000,
+                    active_time: 1_700_000_000_000,
+                    pending: vec![id(1, 1)],
+                }],
+            }],
+        };
+
+        assert_round_trip(StoreData::Stream(StoreStream::default()));
+        assert_round_trip(StoreData::Stream(stream));
+    }
+
+    #[test]
+    fn round_trip_of_module_values() {
+        // The "ReJSON-RL" type name with encoding version 3.
+        assert_round_trip(StoreData::Module(StoreModuleValue {
+            id: 0x45e25238df912c03,
+            name: String::from("ReJSON-RL"),
+            fields: vec![
+                StoreModuleField::SignedInt(-3),
+                StoreModuleField::UnsignedInt(7),
+                StoreModuleField::Float(1.5),
+                StoreModuleField::Double(-2.25),
+                StoreModuleField::String(b"{\"a\":1}".to_vec()),
+            ],
+        }));
+    }
+
+    #[test]
+    fn restore_rejects_a_wrong_checksum() {
+        let mut payload = dump_value(&StoreData::String(String::from("bar"))).unwrap();
+        let last = payload.len() - 1;
+
+        payload[last] ^= 1;
+
+        assert!(matches!(
+            restore_value(&payload),
+            Err(DumpError::InvalidFooter)
+        ));
+    }
+}
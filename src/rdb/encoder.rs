@@ -0,0 +1,392 @@
+use std::io::Write;
+
+use crate::store::{StoreData, StoreModuleField, StoreStream, StoreValue, StreamId};
+
+use super::crc64::Crc64;
+use super::{listpack, lzf};
+
+/// The RDB version written by the encoder (the one used by Redis 7.2).
+pub const RDB_VERSION: &str = "0011";
+
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZE_DB: u8 = 0xFB;
+const OPCODE_EXPIRE_TIME_MS: u8 = 0xFC;
+const OPCODE_SELECT_DB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_SET: u8 = 2;
+const TYPE_HASH: u8 = 4;
+const TYPE_SORTED_SET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const QUICKLIST_NODE_CONTAINER_PACKED: usize = 2;
+/// Maximum number of elements of every list node and stream node, the same as the Redis defaults.
+const MAX_LIST_NODE_ENTRIES: usize = 128;
+const MAX_STREAM_NODE_ENTRIES: usize = 100;
+
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+const MODULE_OPCODE_EOF: usize = 0;
+const MODULE_OPCODE_SINT: usize = 1;
+const MODULE_OPCODE_UINT: usize = 2;
+const MODULE_OPCODE_FLOAT: usize = 3;
+const MODULE_OPCODE_DOUBLE: usize = 4;
+const MODULE_OPCODE_STRING: usize = 5;
+
+const ENCODING_LZF: u8 = 0xC3;
+
+/// Strings up to this length are never compressed, the same as Redis does.
+const MIN_COMPRESSED_STRING_LENGTH: usize = 20;
+
+#[derive(Debug)]
+pub enum RdbFileEncoderError {
+    WriteFile(String),
+}
+
+impl std::fmt::Display for RdbFileEncoderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RdbFileEncoderError::WriteFile(err) => {
+                write!(f, "WriteFile Error: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RdbFileEncoderError {}
+
+/// Writes an RDB file section by section. The sections must be written in the same order as they appear in the
+/// file: header, metadata, databases and end of file.
+///
+/// The checksum of everything written is calculated on the fly and appended by `finish`.
+pub struct RdbFileEncoder<W: Write> {
+    writer: W,
+    crc: Crc64,
+}
+
+impl<W: Write> RdbFileEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            crc: Crc64::new(),
+        }
+    }
+
+    pub fn encode_header(&mut self) -> Result<(), RdbFileEncoderError> {
+        self.write(b"REDIS")?;
+        self.write(RDB_VERSION.as_bytes())
+    }
+
+    pub fn encode_metadata(&mut self, key: &str, value: &str) -> Result<(), RdbFileEncoderError> {
+        self.write(&[OPCODE_AUX])?;
+        self.encode_string(key.as_bytes())?;
+        self.encode_string(value.as_bytes())
+    }
+
+    /// Starts a database section. The number of keys (and of keys with an expiration) are only hints that help
+    /// the reader to size its hash tables.
+    pub fn encode_database(
+        &mut self,
+        index: usize,
+        number_of_keys: usize,
+        number_of_expired_keys: usize,
+    ) -> Result<(), RdbFileEncoderError> {
+        self.write(&[OPCODE_SELECT_DB])?;
+        self.encode_size(index)?;
+        self.write(&[OPCODE_RESIZE_DB])?;
+        self.encode_size(number_of_keys)?;
+        self.encode_size(number_of_expired_keys)
+    }
+
+    pub fn encode_key_value(
+        &mut self,
+        key: &str,
+        value: &StoreValue,
+    ) -> Result<(), RdbFileEncoderError> {
+        if let Some(exp) = value.exp {
+            self.write(&[OPCODE_EXPIRE_TIME_MS])?;
+            self.write(&exp.timestamp_millis().to_le_bytes())?;
+        }
+
+        self.write(&[value_type(&value.value)])?;
+        self.encode_string(key.as_bytes())?;
+        self.encode_value_data(&value.value)
+    }
+
+    /// Writes a value without its key and expiration: its type followed by its data, the same as the beginning of a
+    /// DUMP payload.
+    pub fn encode_value(&mut self, data: &StoreData) -> Result<(), RdbFileEncoderError> {
+        self.write(&[value_type(data)])?;
+        self.encode_value_data(data)
+    }
+
+    /// Ends a DUMP payload with the RDB version and the checksum of the payload, returning the inner writer.
+    pub fn finish_dump(mut self) -> Result<W, RdbFileEncoderError> {
+        let version: u16 = RDB_VERSION.parse().unwrap_or_default();
+
+        self.write(&version.to_le_bytes())?;
+        self.write_checksum()
+    }
+
+    fn encode_value_data(&mut self, data: &StoreData) -> Result<(), RdbFileEncoderError> {
+        match data {
+            StoreData::String(value) => self.encode_string(value.as_bytes()),
+            // Lists are split in nodes of up to 128 elements, every one saved as a listpack.
+            StoreData::List(values) => {
+                let values: Vec<&String> = values.iter().collect();
+
+                self.encode_size(values.len().div_ceil(MAX_LIST_NODE_ENTRIES))?;
+
+                for node in values.chunks(MAX_LIST_NODE_ENTRIES) {
+                    self.encode_size(QUICKLIST_NODE_CONTAINER_PACKED)?;
+                    self.encode_string(&listpack::encode(node))?;
+                }
+
+                Ok(())
+            }
+            StoreData::Set(members) => {
+                self.encode_size(members.len())?;
+
+                for member in members {
+                    self.encode_string(member.as_bytes())?;
+                }
+
+                Ok(())
+            }
+            StoreData::Hash(fields) => {
+                self.encode_size(fields.len())?;
+
+                for (field, value) in fields {
+                    self.encode_string(field.as_bytes())?;
+                    self.encode_string(value.as_bytes())?;
+                }
+
+                Ok(())
+            }
+            StoreData::SortedSet(members) => {
+                self.encode_size(members.len())?;
+
+                for (member, score) in members {
+                    self.encode_string(member.as_bytes())?;
+                    self.write(&score.to_le_bytes())?;
+                }
+
+                Ok(())
+            }
+            StoreData::Stream(stream) => self.encode_stream(stream),
+            StoreData::Module(module) => {
+                self.encode_size(module.id as usize)?;
+
+                for field in module.fields.iter() {
+                    match field {
+                        StoreModuleField::SignedInt(value) => {
+                            self.encode_size(MODULE_OPCODE_SINT)?;
+                            self.encode_size(*value as usize)?;
+                        }
+                        StoreModuleField::UnsignedInt(value) => {
+                            self.encode_size(MODULE_OPCODE_UINT)?;
+                            self.encode_size(*value as usize)?;
+                        }
+                        StoreModuleField::Float(value) => {
+                            self.encode_size(MODULE_OPCODE_FLOAT)?;
+                            self.write(&value.to_le_bytes())?;
+                        }
+                        StoreModuleField::Double(value) => {
+                            self.encode_size(MODULE_OPCODE_DOUBLE)?;
+                            self.write(&value.to_le_bytes())?;
+                        }
+                        StoreModuleField::String(value) => {
+                            self.encode_size(MODULE_OPCODE_STRING)?;
+                            self.encode_string(value)?;
+                        }
+                    }
+                }
+
+                self.encode_size(MODULE_OPCODE_EOF)
+            }
+        }
+    }
+
+    /// Writes the end of file section followed by the checksum, returning the inner writer.
+    pub fn finish(mut self) -> Result<W, RdbFileEncoderError> {
+        self.write(&[OPCODE_EOF])?;
+        self.write_checksum()
+    }
+
+    fn write_checksum(mut self) -> Result<W, RdbFileEncoderError> {
+        let checksum = self.crc.finish();
+
+        self.writer
+            .write_all(&checksum.to_le_bytes())
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| RdbFileEncoderError::WriteFile(err.to_string()))?;
+
+        Ok(self.writer)
+    }
+
+    /// Length encoding, the inverse of `SizeDecoder`. It uses as few bytes as possible:
+    ///
+    ///  - 0b00 prefix: the size fits in the remaining 6 bits.
+    ///  - 0b01 prefix: the size fits in the remaining 6 bits plus the next byte (14 bits, big-endian).
+    ///  - 0x80: the size is the next 4 bytes (big-endian).
+    ///  - 0x81: the size is the next 8 bytes (big-endian).
+    fn encode_size(&mut self, size: usize) -> Result<(), RdbFileEncoderError> {
+        if size < (1 << 6) {
+            self.write(&[size as u8])
+        } else if size < (1 << 14) {
+            self.write(&[0b0100_0000 | (size >> 8) as u8, size as u8])
+        } else if let Ok(size) = u32::try_from(size) {
+            self.write(&[0x80])?;
+            self.write(&size.to_be_bytes())
+        } else {
+            self.write(&[0x81])?;
+            self.write(&(size as u64).to_be_bytes())
+        }
+    }
+
+    // Streams are saved as listpacks of up to 100 entries. The first entry of every listpack is its master entry: its
+    // ID is the listpack key and its fields are saved once, so the following entries with the same fields only save
+    // their values. IDs are saved as the difference with the master ID.
+    fn encode_stream(&mut self, stream: &StoreStream) -> Result<(), RdbFileEncoderError> {
+        let entries: Vec<(&StreamId, &Vec<(String, String)>)> = stream.entries.iter().collect();
+
+        self.encode_size(entries.len().div_ceil(MAX_STREAM_NODE_ENTRIES))?;
+
+        for node in entries.chunks(MAX_STREAM_NODE_ENTRIES) {
+            let (master_id, master_fields) = node[0];
+            let mut elements: Vec<String> = vec![
+                node.len().to_string(),
+                String::from("0"),
+                master_fields.len().to_string(),
+            ];
+
+            elements.extend(master_fields.iter().map(|(field, _)| field.clone()));
+            elements.push(String::from("0"));
+
+            for (id, fields) in node {
+                let same_fields = fields.len() == master_fields.len()
+                    && fields
+                        .iter()
+                        .zip(master_fields.iter())
+                        .all(|((field, _), (master_field, _))| field == master_field);
+                let ms_diff = id.ms.wrapping_sub(master_id.ms) as i64;
+                let seq_diff = id.seq.wrapping_sub(master_id.seq) as i64;
+
+                if same_fields {
+                    elements.push(STREAM_ITEM_FLAG_SAMEFIELDS.to_string());
+                    elements.push(ms_diff.to_string());
+                    elements.push(seq_diff.to_string());
+                    elements.extend(fields.iter().map(|(_, value)| value.clone()));
+                    elements.push((fields.len() + 3).to_string());
+                } else {
+                    elements.push(String::from("0"));
+                    elements.push(ms_diff.to_string());
+                    elements.push(seq_diff.to_string());
+                    elements.push(fields.len().to_string());
+
+                    for (field, value) in fields.iter() {
+                        elements.push(field.clone());
+                        elements.push(value.clone());
+                    }
+
+                    elements.push((fields.len() * 2 + 4).to_string());
+                }
+            }
+
+            self.encode_string(&raw_stream_id(master_id))?;
+            self.encode_string(&listpack::encode(&elements))?;
+        }
+
+        self.encode_size(entries.len())?;
+        self.encode_stream_id(&stream.last_id)?;
+        self.encode_stream_id(&stream.first_id)?;
+        self.encode_stream_id(&stream.max_deleted_id)?;
+        self.encode_size(stream.entries_added as usize)?;
+        self.encode_size(stream.groups.len())?;
+
+        for group in stream.groups.iter() {
+            self.encode_string(group.name.as_bytes())?;
+            self.encode_stream_id(&group.last_id)?;
+            // -1 (unknown) is saved as its two's complement.
+            self.encode_size(group.entries_read as usize)?;
+            self.encode_size(group.pending.len())?;
+
+            for pending_entry in group.pending.iter() {
+                self.write(&raw_stream_id(&pending_entry.id))?;
+                self.write(&pending_entry.delivery_time.to_le_bytes())?;
+                self.encode_size(pending_entry.delivery_count as usize)?;
+            }
+
+            self.encode_size(group.consumers.len())?;
+
+            for consumer in group.consumers.iter() {
+                self.encode_string(consumer.name.as_bytes())?;
+                self.write(&consumer.seen_time.to_le_bytes())?;
+                self.write(&consumer.active_time.to_le_bytes())?;
+                self.encode_size(consumer.pending.len())?;
+
+                for id in consumer.pending.iter() {
+                    self.write(&raw_stream_id(id))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encode_stream_id(&mut self, id: &StreamId) -> Result<(), RdbFileEncoderError> {
+        self.encode_size(id.ms as usize)?;
+        self.encode_size(id.seq as usize)
+    }
+
+    // Long strings are compressed with LZF, as long as compressing them saves at least 4 bytes.
+    fn encode_string(&mut self, value: &[u8]) -> Result<(), RdbFileEncoderError> {
+        if value.len() > MIN_COMPRESSED_STRING_LENGTH {
+            let compressed = lzf::compress(value);
+
+            if compressed.len() + 4 <= value.len() {
+                self.write(&[ENCODING_LZF])?;
+                self.encode_size(compressed.len())?;
+                self.encode_size(value.len())?;
+
+                return self.write(&compressed);
+            }
+        }
+
+        self.encode_size(value.len())?;
+        self.write(value)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), RdbFileEncoderError> {
+        self.crc.update(bytes);
+
+        self.writer
+            .write_all(bytes)
+            .map_err(|err| RdbFileEncoderError::WriteFile(err.to_string()))
+    }
+}
+
+fn value_type(data: &StoreData) -> u8 {
+    match data {
+        StoreData::String(_) => TYPE_STRING,
+        StoreData::List(_) => TYPE_LIST_QUICKLIST_2,
+        StoreData::Set(_) => TYPE_SET,
+        StoreData::Hash(_) => TYPE_HASH,
+        StoreData::SortedSet(_) => TYPE_SORTED_SET_2,
+        StoreData::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+        StoreData::Module(_) => TYPE_MODULE_2,
+    }
+}
+
+// Raw stream IDs are two big-endian 64-bit numbers.
+fn raw_stream_id(id: &StreamId) -> [u8; 16] {
+    let mut buf = [0; 16];
+
+    buf[0..8].copy_from_slice(&id.ms.to_be_bytes());
+    buf[8..16].copy_from_slice(&id.seq.to_be_bytes());
+
+    buf
+}
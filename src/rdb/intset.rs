@@ -0,0 +1,33 @@
+//! The intset format, used for small sets where every member is an integer.
+//!
+//! An intset is a 4 bytes size of every integer (2, 4 or 8), a 4 bytes number of integers and the sorted integers,
+//! everything in little-endian.
+
+const HEADER_SIZE: usize = 8;
+
+/// Reads the members of an intset as their decimal representation. It returns None when the intset is corrupted.
+pub fn decode(input: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let integer_size = u32::from_le_bytes(input.get(0..4)?.try_into().ok()?) as usize;
+    let length = u32::from_le_bytes(input.get(4..8)?.try_into().ok()?) as usize;
+
+    if !matches!(integer_size, 2 | 4 | 8) || input.len() != HEADER_SIZE + integer_size * length {
+        return None;
+    }
+
+    let members = input[HEADER_SIZE..]
+        .chunks_exact(integer_size)
+        .map(|bytes| {
+            let value = match integer_size {
+                2 => i64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+                4 => i64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+                _ => i64::from_le_bytes([
+                    bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+                ]),
+            };
+
+            value.to_string().into_bytes()
+        })
+        .collect();
+
+    Some(members)
+}
@@ -0,0 +1,328 @@
+//! The listpack format, used by Redis 7 for small lists, sets, hashes, sorted sets and for stream nodes.
+//!
+//! A listpack is a 4 bytes (little-endian) total length, a 2 bytes number of elements, the elements and a 0xFF
+//! terminator. Every element is an encoding byte, the content and the length of both (backlen), which allows
+//! reading the listpack backwards.
+
+const HEADER_SIZE: usize = 6;
+const END: u8 = 0xFF;
+
+/// Reads the elements of a listpack. Integers are returned as their decimal representation. It returns None when
+/// the listpack is corrupted.
+pub fn decode(input: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let total_length = u32::from_le_bytes(input.get(0..4)?.try_into().ok()?) as usize;
+
+    if total_length != input.len() {
+        return None;
+    }
+
+    let mut elements = Vec::new();
+    let mut position = HEADER_SIZE;
+
+    loop {
+        let encoding = *input.get(position)?;
+
+        if encoding == END {
+            break;
+        }
+
+        let (element, length) = decode_element(&input[position..])?;
+
+        elements.push(element);
+        position += length + backlen_size(length);
+    }
+
+    Some(elements)
+}
+
+// Returns the element and the number of bytes of its encoding and content.
+fn decode_element(input: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let encoding = input[0];
+
+    let string = |start: usize, length: usize| -> Option<(Vec<u8>, usize)> {
+        Some((input.get(start..start + length)?.to_vec(), start + length))
+    };
+    let integer = |value: i64, length: usize| Some((value.to_string().into_bytes(), length));
+
+    match encoding {
+        // 0xxxxxxx: 7 bits unsigned integer.
+        0x00..=0x7F => integer(i64::from(encoding), 1),
+        // 10xxxxxx: string of up to 63 bytes.
+        0x80..=0xBF => string(1, usize::from(encoding & 0x3F)),
+        // 110xxxxx yyyyyyyy: 13 bits signed integer.
+        0xC0..=0xDF => {
+            let value = (u16::from(encoding & 0x1F) << 8) | u16::from(*input.get(1)?);
+            // Sign extension from 13 to 16 bits.
+            let value = ((value << 3) as i16) >> 3;
+
+            integer(i64::from(value), 2)
+        }
+        // 1110xxxx yyyyyyyy: string of up to 4095 bytes.
+        0xE0..=0xEF => {
+            let length = (usize::from(encoding & 0x0F) << 8) | usize::from(*input.get(1)?);
+
+            string(2, length)
+        }
+        0xF0 => {
+            let length = u32::from_le_bytes(input.get(1..5)?.try_into().ok()?) as usize;
+
+            string(5, length)
+        }
+        0xF1 => integer(
+            i64::from(i16::from_le_bytes(input.get(1..3)?.try_into().ok()?)),
+            3,
+        ),
+        0xF2 => {
+            let bytes = input.get(1..4)?;
+            // The 24 bits are shifted to the top of an i32, so the sign is extended when shifting them back.
+            let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+
+            integer(i64::from(value), 4)
+        }
+        0xF3 => integer(
+            i64::from(i32::from_le_bytes(input.get(1..5)?.try_into().ok()?)),
+            5,
+        ),
+        0xF4 => integer(i64::from_le_bytes(input.get(1..9)?.try_into().ok()?), 9),
+        _ => None,
+    }
+}
+
+fn backlen_size(length: usize) -> usize {
+    match length {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Writes a listpack with the given elements. Elements that are the canonical representation of an integer are
+/// encoded as integers, the same as Redis does.
+pub fn encode<T: AsRef<[u8]>>(elements: &[T]) -> Vec<u8> {
+    let mut output = vec![0; HEADER_SIZE];
+
+    for element in elements {
+        let start = output.len();
+
+        encode_element(&mut output, element.as_ref());
+
+        let length = output.len() - start;
+
+        encode_backlen(&mut output, length);
+    }
+
+    output.push(END);
+
+    let total_length = output.len() as u32;
+    // Listpacks with more elements than a u16 can hold store the maximum, and readers count them instead.
+    let number_of_elements = u16::try_from(elements.len()).unwrap_or(u16::MAX);
+
+    output[0..4].copy_from_slice(&total_length.to_le_bytes());
+    output[4..6].copy_from_slice(&number_of_elements.to_le_bytes());
+
+    output
+}
+
+fn encode_element(output: &mut Vec<u8>, element: &[u8]) {
+    if let Some(value) = parse_integer(element) {
+        match value {
+            0..=127 => output.push(value as u8),
+            -4096..=4095 => {
+                let value = (value as u16) & 0x1FFF;
+
+                output.push(0xC0 | (value >> 8) as u8);
+                output.push(value as u8);
+            }
+            -32768..=32767 => {
+                output.push(0xF1);
+                output.extend_from_slice(&(value as i16).to_le_bytes());
+            }
+            -8388608..=8388607 => {
+                output.push(0xF2);
+                output.extend_from_slice(&(value as i32).to_le_bytes()[0..3]);
+            }
+            -2147483648..=2147483647 => {
+                output.push(0xF3);
+                output.extend_from_slice(&(value as i32).to_le_bytes());
+            }
+            _ => {
+                output.push(0xF4);
+                output.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        return;
+    }
+
+    let length = element.len();
+
+    if length < 64 {
+        output.push(0x80 | length as u8);
+    } else if length < 4096 {
+        output.push(0xE0 | (length >> 8) as u8);
+        output.push(length as u8);
+    } else {
+        output.push(0xF0);
+        output.extend_from_slice(&(length as u32).to_le_bytes());
+    }
+
+    output.extend_from_slice(element);
+}
+
+// The backlen is written from the most significant 7 bits to the least significant ones, with the high bit set in
+// all the bytes except the first one, so it can be read from right to left.
+fn encode_backlen(output: &mut Vec<u8>, length: usize) {
+    let size = backlen_size(length);
+
+    for index in (0..size).rev() {
+        let byte = ((length >> (7 * index)) & 127) as u8;
+
+        if index == size - 1 {
+            output.push(byte);
+        } else {
+            output.push(byte | 128);
+        }
+    }
+}
+
+// Only the canonical representation of an integer is parsed ("01" or "+1" are kept as strings), so the element is
+// decoded back exactly the same.
+fn parse_integer(element: &[u8]) -> Option<i64> {
+    let value: i64 = std::str::from_utf8(element).ok()?.parse().ok()?;
+
+    (value.to_string().as_bytes() == element).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element_length(element: &[u8]) -> usize {
+        let mut output = vec![];
+
+        encode_element(&mut output, element);
+
+        output.len()
+    }
+
+    fn assert_round_trip(elements: &[Vec<u8>]) {
+        assert_eq!(decode(&encode(elements)).as_deref(), Some(elements));
+    }
+
+    #[test]
+    fn backlen_size_limits() {
+        assert_eq!(backlen_size(0), 1);
+        assert_eq!(backlen_size(127), 1);
+        assert_eq!(backlen_size(128), 2);
+        assert_eq!(backlen_size(16382), 2);
+        assert_eq!(backlen_size(16383), 3);
+        assert_eq!(backlen_size(2097150), 3);
+        assert_eq!(backlen_size(2097151), 4);
+        assert_eq!(backlen_size(268435454), 4);
+        assert_eq!(backlen_size(268435455), 5);
+    }
+
+    #[test]
+    fn backlen_is_written_from_the_most_significant_bits() {
+        let backlen = |length| {
+            let mut output = vec![];
+
+            encode_backlen(&mut output, length);
+
+            output
+        };
+
+        assert_eq!(backlen(1), [0x01]);
+        assert_eq!(backlen(127), [0x7F]);
+        assert_eq!(backlen(128), [0x01, 0x80]);
+        assert_eq!(backlen(16382), [0x7F, 0xFE]);
+        assert_eq!(backlen(16383), [0x00, 0xFF, 0xFF]);
+        assert_eq!(backlen(16384), [0x01, 0x80, 0x80]);
+        assert_eq!(backlen(2097151), [0x00, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn round_trip_of_elements_around_the_backlen_limits() {
+        // Strings of 64 to 4095 bytes take 2 bytes of encoding, and longer ones take 5.
+        for length in [127, 128, 16382, 16383] {
+            let encoding_size = if length < 4096 { 2 } else { 5 };
+            let element = vec![b'a'; length - encoding_size];
+
+            assert_eq!(element_length(&element), length);
+            assert_round_trip(&[element.clone(), b"next".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn round_trip_of_strings_around_the_encoding_limits() {
+        let elements: Vec<Vec<u8>> = [0, 1, 63, 64, 4095, 4096]
+            .into_iter()
+            .map(|length| vec![b'x'; length])
+            .collect();
+
+        assert_round_trip(&elements);
+    }
+
+    #[test]
+    fn round_trip_of_integers_around_the_encoding_limits() {
+        let elements: Vec<Vec<u8>> = [
+            0,
+            127,
+            128,
+            -1,
+            -4096,
+            4095,
+            -4097,
+            4096,
+            -32768,
+            32767,
+            -32769,
+            32768,
+            -8388608,
+            8388607,
+            -8388609,
+            8388608,
+            i64::from(i32::MIN),
+            i64::from(i32::MAX),
+            i64::from(i32::MIN) - 1,
+            i64::from(i32::MAX) + 1,
+            i64::MIN,
+            i64::MAX,
+        ]
+        .iter()
+        .map(|value| value.to_string().into_bytes())
+        .collect();
+
+        assert_round_trip(&elements);
+    }
+
+    #[test]
+    fn integers_that_are_not_canonical_are_kept_as_strings() {
+        for element in [&b"01"[..], b"+1", b"-0", b" 1", b"1 "] {
+            assert_eq!(element_length(element), element.len() + 1);
+            assert_round_trip(&[element.to_vec()]);
+        }
+    }
+
+    #[test]
+    fn decode_rejects_a_wrong_total_length() {
+        let mut listpack = encode(&[b"a"]);
+
+        listpack.push(0);
+
+        assert_eq!(decode(&listpack), None);
+    }
+
+    #[test]
+    fn decode_rejects_a_missing_terminator() {
+        let mut listpack = encode(&[b"a"]);
+        let length = listpack.len() as u32 - 1;
+
+        listpack.pop();
+        listpack[0..4].copy_from_slice(&length.to_le_bytes());
+
+        assert_eq!(decode(&listpack), None);
+    }
+}
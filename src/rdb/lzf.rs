@@ -0,0 +1,223 @@
+//! The LZF compression format used by Redis for long strings inside RDB files.
+//!
+//! A compressed buffer is a sequence of chunks that start with a control byte:
+//!
+//!  - 0b000LLLLL: a literal run, the next `L + 1` bytes are copied as they are.
+//!  - 0bLLLOOOOO: a back reference of `L + 2` bytes, starting `O * 256 + next byte + 1` bytes before the end of the
+//!    output. When `L` is 7, the length continues in the next byte (before the offset byte).
+
+use super::decoder::MAX_PREALLOCATION;
+
+/// Maximum number of bytes of a literal run.
+const MAX_LITERAL: usize = 1 << 5;
+/// Maximum distance between a back reference and the current position.
+const MAX_OFFSET: usize = 1 << 13;
+/// Maximum length of a back reference.
+const MAX_REFERENCE: usize = (1 << 8) + (1 << 3);
+/// Number of bits of the hash table used to find repeated sequences.
+const HASH_LOG: u32 = 14;
+
+/// Compresses `input`. The result can be longer than the input when there is nothing to compress.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    // Positions (plus one, so zero means empty) of the last sequence of three bytes with the same hash.
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut literal_start = 0;
+    let mut position = 0;
+
+    while position + 2 < input.len() {
+        let hash = hash(&input[position..position + 3]);
+        let candidate = table[hash];
+
+        table[hash] = position + 1;
+
+        if candidate > 0 {
+            let reference = candidate - 1;
+            let offset = position - reference - 1;
+
+            if offset < MAX_OFFSET
+                && input[reference..reference + 3] == input[position..position + 3]
+            {
+                let max_length = MAX_REFERENCE.min(input.len() - position);
+                let mut length = 3;
+
+                while length < max_length && input[reference + length] == input[position + length] {
+                    length += 1;
+                }
+
+                write_literals(&mut output, &input[literal_start..position]);
+
+                let encoded_length = length - 2;
+
+                if encoded_length < 7 {
+                    output.push(((encoded_length << 5) | (offset >> 8)) as u8);
+                } else {
+                    output.push(((7 << 5) | (offset >> 8)) as u8);
+                    output.push((encoded_length - 7) as u8);
+                }
+
+                output.push(offset as u8);
+
+                position += length;
+                literal_start = position;
+
+                continue;
+            }
+        }
+
+        position += 1;
+    }
+
+    write_literals(&mut output, &input[literal_start..]);
+
+    output
+}
+
+/// Decompresses `input`, which must expand to exactly `length` bytes. It returns None when the input is corrupted.
+pub fn decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    // The length comes from the input, so it is not allocated up front.
+    let mut output = Vec::with_capacity(length.min(MAX_PREALLOCATION));
+    let mut position = 0;
+
+    while position < input.len() {
+        let control = usize::from(input[position]);
+
+        position += 1;
+
+        if control < MAX_LITERAL {
+            let literal = input.get(position..position + control + 1)?;
+
+            output.extend_from_slice(literal);
+            position += control + 1;
+
+            continue;
+        }
+
+        let mut reference_length = control >> 5;
+
+        if reference_length == 7 {
+            reference_length += usize::from(*input.get(position)?);
+            position += 1;
+        }
+
+        let offset = ((control & 0x1f) << 8) + usize::from(*input.get(position)?) + 1;
+
+        position += 1;
+
+        let start = output.len().checked_sub(offset)?;
+
+        // The reference can overlap with the bytes that it produces, so they are copied one by one.
+        for index in start..start + reference_length + 2 {
+            output.push(output[index]);
+        }
+
+        if output.len() > length {
+            return None;
+        }
+    }
+
+    (output.len() == length).then_some(output)
+}
+
+fn write_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERAL) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+
+    (value.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(input: &[u8]) {
+        let compressed = compress(input);
+
+        assert_eq!(decompress(&compressed, input.len()).as_deref(), Some(input));
+    }
+
+    #[test]
+    fn round_trip_of_an_empty_input() {
+        assert_round_trip(b"");
+    }
+
+    #[test]
+    fn round_trip_of_inputs_shorter_than_a_reference() {
+        assert_round_trip(b"a");
+        assert_round_trip(b"ab");
+        assert_round_trip(b"abc");
+    }
+
+    #[test]
+    fn round_trip_of_repeated_bytes() {
+        let input = vec![b'a'; 10_000];
+        let compressed = compress(&input);
+
+        assert!(compressed.len() < input.len() / 10);
+        assert_eq!(decompress(&compressed, input.len()), Some(input));
+    }
+
+    #[test]
+    fn round_trip_of_repeated_sequences() {
+        let input = "Redis LZF compression test. ".repeat(100);
+
+        assert_round_trip(input.as_bytes());
+    }
+
+    #[test]
+    fn round_trip_of_references_longer_than_the_maximum() {
+        let mut input = b"0123456789".to_vec();
+
+        input.extend(std::iter::repeat_n(b'x', MAX_REFERENCE * 3 + 1));
+        input.extend(b"0123456789");
+
+        assert_round_trip(&input);
+    }
+
+    #[test]
+    fn round_trip_of_references_beyond_the_maximum_offset() {
+        let sequence = b"a sequence that is repeated far away";
+        let mut input = sequence.to_vec();
+
+        // Bytes that do not repeat any sequence of three, so the second copy is too far from the first one.
+        input.extend((0..MAX_OFFSET as u32 + 100).flat_map(|value| value.to_le_bytes()));
+        input.extend(sequence);
+
+        assert_round_trip(&input);
+    }
+
+    #[test]
+    fn round_trip_of_incompressible_bytes() {
+        let input: Vec<u8> = (0..=255).collect();
+        let compressed = compress(&input);
+
+        // Every literal run of 32 bytes takes an extra control byte.
+        assert_eq!(compressed.len(), input.len() + input.len() / MAX_LITERAL);
+        assert_eq!(decompress(&compressed, input.len()), Some(input));
+    }
+
+    #[test]
+    fn decompress_rejects_a_wrong_length() {
+        let input = "abcabcabcabc".repeat(10);
+        let compressed = compress(input.as_bytes());
+
+        assert_eq!(decompress(&compressed, input.len() - 1), None);
+        assert_eq!(decompress(&compressed, input.len() + 1), None);
+    }
+
+    #[test]
+    fn decompress_rejects_corrupted_input() {
+        // A literal run of 4 bytes with only 2 of them.
+        assert_eq!(decompress(&[3, b'a', b'b'], 4), None);
+        // A back reference before the start of the output.
+        assert_eq!(decompress(&[0, b'a', 0x20, 5], 4), None);
+        // A back reference without its offset byte.
+        assert_eq!(decompress(&[0, b'a', 0x20], 4), None);
+    }
+}
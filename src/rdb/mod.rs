@@ -1,2 +1,12 @@
+pub mod crc64;
 pub mod decoder;
+pub mod dump;
+pub mod encoder;
+pub mod intset;
+pub mod listpack;
+pub mod lzf;
+pub mod replay;
+pub mod save;
 pub mod sync;
+pub mod ziplist;
+pub mod zipmap;
@@ -0,0 +1,444 @@
+use super::decoder::RdbDatabase;
+use crate::store::{StoreData, StoreStream, StoreValue};
+
+/// Maximum number of elements added by a single command, the same as Redis uses when rewriting the AOF, so big
+/// collections do not become huge commands.
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// The commands that create the keys of a database, in the same form as the AOF: every key is followed by its
+/// expiration. The keys are in the order of their names. The database has to be selected before running them.
+///
+/// Module values are not included, since only the module knows the commands that create them. Their keys are
+/// returned apart.
+pub fn database_commands(database: &RdbDatabase) -> (Vec<Vec<String>>, Vec<String>) {
+    let mut keys: Vec<(&String, &StoreValue)> = database.data.iter().collect();
+    let mut commands = vec![];
+    let mut skipped_keys = vec![];
+
+    keys.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (key, value) in keys {
+        match key_commands(key, value) {
+            Some(key_commands) => commands.extend(key_commands),
+            None => skipped_keys.push(key.to_string()),
+        }
+    }
+
+    (commands, skipped_keys)
+}
+
+/// The commands that create a key with its value and expiration. It returns None for module values.
+pub fn key_commands(key: &str, value: &StoreValue) -> Option<Vec<Vec<String>>> {
+    let mut commands = match &value.value {
+        StoreData::String(value) => vec![command(&["SET", key, value])],
+        StoreData::List(values) => {
+            chunked_commands("RPUSH", key, values.iter().map(|value| vec![value.clone()]))
+        }
+        StoreData::Set(members) => {
+            let mut members: Vec<&String> = members.iter().collect();
+
+            members.sort();
+
+            chunked_commands(
+                "SADD",
+                key,
+                members.into_iter().map(|member| vec![member.clone()]),
+            )
+        }
+        StoreData::Hash(fields) => {
+            let mut fields: Vec<(&String, &String)> = fields.iter().collect();
+
+            fields.sort();
+
+            chunked_commands(
+                "HSET",
+                key,
+                fields
+                    .into_iter()
+                    .map(|(field, value)| vec![field.clone(), value.clone()]),
+            )
+        }
+        StoreData::SortedSet(members) => chunked_commands(
+            "ZADD",
+            key,
+            members
+                .iter()
+                .map(|(member, score)| vec![score.to_string(), member.clone()]),
+        ),
+        StoreData::Stream(stream) => stream_commands(key, stream),
+        StoreData::Module(_) => return None,
+    };
+
+    if let Some(exp) = value.exp {
+        commands.push(command(&[
+            "PEXPIREAT",
+            key,
+            &exp.timestamp_millis().to_string(),
+        ]));
+    }
+
+    Some(commands)
+}
+
+// The entries are added with their IDs, and then the stream state that can not be deduced from them (the last ID,
+// the consumer groups and their pending entries) is restored. It is the same that Redis does when rewriting the AOF.
+fn stream_commands(key: &str, stream: &StoreStream) -> Vec<Vec<String>> {
+    let last_id = stream.last_id.to_string();
+    let mut commands: Vec<Vec<String>> = stream
+        .entries
+        .iter()
+        .map(|(id, fields)| {
+            let mut command = command(&["XADD", key, &id.to_string()]);
+
+            for (field, value) in fields {
+                command.push(field.clone());
+                command.push(value.clone());
+            }
+
+            command
+        })
+        .collect();
+
+    // An entry is needed to create the stream, but it is removed right away.
+    if commands.is_empty() {
+        commands.push(command(&["XADD", key, "MAXLEN", "0", &last_id, "x", "y"]));
+    }
+
+    commands.push(command(&[
+        "XSETID",
+        key,
+        &last_id,
+        "ENTRIESADDED",
+        &stream.entries_added.to_string(),
+        "MAXDELETEDID",
+        &stream.max_deleted_id.to_string(),
+    ]));
+
+    for group in stream.groups.iter() {
+        commands.push(command(&[
+            "XGROUP",
+            "CREATE",
+            key,
+            &group.name,
+            &group.last_id.to_string(),
+            "ENTRIESREAD",
+            &group.entries_read.to_string(),
+        ]));
+
+        for consumer in group.consumers.iter() {
+            // Claiming the pending entries of a consumer creates it too.
+            if consumer.pending.is_empty() {
+                commands.push(command(&[
+                    "XGROUP",
+                    "CREATECONSUMER",
+                    key,
+                    &group.name,
+                    &consumer.name,
+                ]));
+            }
+
+            for id in consumer.pending.iter() {
+                let entry = group.pending.iter().find(|entry| entry.id == *id);
+                let (delivery_time, delivery_count) = entry
+                    .map(|entry| (entry.delivery_time, entry.delivery_count))
+                    .unwrap_or_default();
+
+                commands.push(command(&[
+                    "XCLAIM",
+                    key,
+                    &group.name,
+                    &consumer.name,
+                    "0",
+                    &id.to_string(),
+                    "TIME",
+                    &delivery_time.to_string(),
+                    "RETRYCOUNT",
+                    &delivery_count.to_string(),
+                    "JUSTID",
+                    "FORCE",
+                ]));
+            }
+        }
+    }
+
+    commands
+}
+
+// Splits the items of a collection into commands of up to `ITEMS_PER_COMMAND` items.
+fn chunked_commands(
+    name: &str,
+    key: &str,
+    items: impl Iterator<Item = Vec<String>>,
+) -> Vec<Vec<String>> {
+    let items: Vec<Vec<String>> = items.collect();
+
+    items
+        .chunks(ITEMS_PER_COMMAND)
+        .map(|chunk| {
+            let mut command = command(&[name, key]);
+
+            command.extend(chunk.iter().flatten().cloned());
+
+            command
+        })
+        .collect()
+}
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet, VecDeque};
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::store::{
+        StoreModuleValue, StoreStreamConsumer, StoreStreamGroup, StoreStreamPendingEntry, StreamId,
+    };
+
+    use super::*;
+
+    fn value(data: StoreData) -> StoreValue {
+        StoreValue {
+            value: data,
+            exp: None,
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    #[test]
+    fn string_with_expiration() {
+        let value = StoreValue {
+            value: StoreData::String(String::from("1")),
+            exp: Some(Utc.timestamp_millis_opt(1_700_000_000_123).unwrap()),
+        };
+
+        assert_eq!(
+            key_commands("a", &value),
+            Some(vec![
+                command(&["SET", "a", "1"]),
+                command(&["PEXPIREAT", "a", "1700000000123"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn collections() {
+        assert_eq!(
+            key_commands(
+                "list",
+                &value(StoreData::List(VecDeque::from(strings(&["b", "a"]))))
+            ),
+            Some(vec![command(&["RPUSH", "list", "b", "a"])])
+        );
+        assert_eq!(
+            key_commands(
+                "set",
+                &value(StoreData::Set(HashSet::from_iter(strings(&["b", "a"]))))
+            ),
+            Some(vec![command(&["SADD", "set", "a", "b"])])
+        );
+        assert_eq!(
+            key_commands(
+                "hash",
+                &value(StoreData::Hash(HashMap::from([
+                    (String::from("y"), String::from("2")),
+                    (String::from("x"), String::from("1")),
+                ])))
+            ),
+            Some(vec![command(&["HSET", "hash", "x", "1", "y", "2"])])
+        );
+        assert_eq!(
+            key_commands(
+                "zset",
+                &value(StoreData::SortedSet(vec![
+                    (String::from("a"), 1.5),
+                    (String::from("b"), f64::INFINITY),
+                ]))
+            ),
+            Some(vec![command(&["ZADD", "zset", "1.5", "a", "inf", "b"])])
+        );
+    }
+
+    #[test]
+    fn big_collections_are_split() {
+        let values: Vec<String> = (0..ITEMS_PER_COMMAND * 2 + 1)
+            .map(|index| index.to_string())
+            .collect();
+        let commands = key_commands(
+            "list",
+            &value(StoreData::List(VecDeque::from(values.clone()))),
+        )
+        .unwrap();
+
+        assert_eq!(
+            commands.iter().map(Vec::len).collect::<Vec<usize>>(),
+            vec![ITEMS_PER_COMMAND + 2, ITEMS_PER_COMMAND + 2, 3]
+        );
+        assert_eq!(
+            commands
+                .into_iter()
+                .flat_map(|command| command.into_iter().skip(2))
+                .collect::<Vec<String>>(),
+            values
+        );
+    }
+
+    #[test]
+    fn module_values_are_skipped() {
+        let module = value(StoreData::Module(StoreModuleValue {
+            id: 1,
+            name: String::from("mymodule1"),
+            fields: vec![],
+        }));
+
+        assert_eq!(key_commands("module", &module), None);
+    }
+
+    #[test]
+    fn stream_with_groups() {
+        let mut stream = StoreStream {
+            last_id: id(3, 0),
+            max_deleted_id: id(2, 0),
+            entries_added: 3,
+            ..Default::default()
+        };
+
+        stream
+            .entries
+            .insert(id(1, 0), vec![(String::from("f"), String::from("v"))]);
+        stream.entries.insert(id(3, 0), vec![]);
+        stream.groups.push(StoreStreamGroup {
+            name: String::from("group"),
+            last_id: id(3, 0),
+            entries_read: 2,
+            pending: vec![StoreStreamPendingEntry {
+                id: id(1, 0),
+                delivery_time: 1000,
+                delivery_count: 2,
+            }],
+            consumers: vec![
+                StoreStreamConsumer {
+                    name: String::from("alice"),
+                    pending: vec![id(1, 0)],
+                    ..Default::default()
+                },
+                StoreStreamConsumer {
+                    name: String::from("bob"),
+                    ..Default::default()
+                },
+            ],
+        });
+
+        assert_eq!(
+            key_commands("stream", &value(StoreData::Stream(stream))),
+            Some(vec![
+                command(&["XADD", "stream", "1-0", "f", "v"]),
+                command(&["XADD", "stream", "3-0"]),
+                command(&[
+                    "XSETID",
+                    "stream",
+                    "3-0",
+                    "ENTRIESADDED",
+                    "3",
+                    "MAXDELETEDID",
+                    "2-0",
+                ]),
+                command(&[
+                    "XGROUP",
+                    "CREATE",
+                    "stream",
+                    "group",
+                    "3-0",
+                    "ENTRIESREAD",
+                    "2",
+                ]),
+                command(&[
+                    "XCLAIM",
+                    "stream",
+                    "group",
+                    "alice",
+                    "0",
+                    "1-0",
+                    "TIME",
+                    "1000",
+                    "RETRYCOUNT",
+                    "2",
+                    "JUSTID",
+                    "FORCE",
+                ]),
+                command(&["XGROUP", "CREATECONSUMER", "stream", "group", "bob"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_stream() {
+        let stream = StoreStream {
+            last_id: id(5, 1),
+            entries_added: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            key_commands("stream", &value(StoreData::Stream(stream))),
+            Some(vec![
+                command(&["XADD", "stream", "MAXLEN", "0", "5-1", "x", "y"]),
+                command(&[
+                    "XSETID",
+                    "stream",
+                    "5-1",
+                    "ENTRIESADDED",
+                    "4",
+                    "MAXDELETEDID",
+                    "0-0",
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn database_commands_are_sorted_by_key() {
+        let database = RdbDatabase {
+            index: 0,
+            data: HashMap::from([
+                (
+                    String::from("b"),
+                    value(StoreData::String(String::from("2"))),
+                ),
+                (
+                    String::from("a"),
+                    value(StoreData::String(String::from("1"))),
+                ),
+                (
+                    String::from("module"),
+                    value(StoreData::Module(StoreModuleValue {
+                        id: 1,
+                        name: String::from("mymodule1"),
+                        fields: vec![],
+                    })),
+                ),
+            ]),
+            keys_skipped: 0,
+            keys_expired: 0,
+            metadata: HashMap::new(),
+        };
+
+        assert_eq!(
+            database_commands(&database),
+            (
+                vec![command(&["SET", "a", "1"]), command(&["SET", "b", "2"])],
+                vec![String::from("module")]
+            )
+        );
+    }
+}
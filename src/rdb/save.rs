@@ -0,0 +1,619 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::store::{Store, StoreSnapshot};
+
+use super::encoder::RdbFileEncoder;
+
+/// The Redis version written to the RDB metadata. Redis refuses to load files from versions newer than its own.
+const REDIS_VERSION: &str = "7.2.0";
+/// Seconds to wait before retrying an automatic save after a failed one.
+const BGSAVE_RETRY_DELAY: i64 = 5;
+/// The suffix of the copies kept by the retention, after the name of the RDB file (example: dump.rdb.20250102-030405).
+const RETAINED_FILE_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+const TEMP_PREFIX: &str = "temp-";
+
+#[derive(Debug)]
+pub enum RdbSaveError {
+    InProgress,
+    WriteFile(String),
+    Encode(String),
+}
+
+impl std::fmt::Display for RdbSaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RdbSaveError::InProgress => {
+                write!(f, "Background save already in progress")
+            }
+            RdbSaveError::WriteFile(err) => {
+                write!(f, "WriteFile: {}", err)
+            }
+            RdbSaveError::Encode(err) => {
+                write!(f, "Encode: {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RdbSaveError {}
+
+/// A save rule: the store is saved when at least `changes` changes were made and more than `seconds` seconds passed
+/// since the last save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavePoint {
+    pub seconds: i64,
+    pub changes: u64,
+}
+
+impl SavePoint {
+    /// Parses the save rules with the same format as the Redis `save` configuration: pairs of seconds and changes
+    /// separated by spaces (example: "3600 1 300 100 60 10000"). An empty string disables automatic saving.
+    pub fn parse_all(value: &str) -> Result<Vec<SavePoint>, String> {
+        let values: Vec<&str> = value.split_whitespace().collect();
+
+        if !values.chunks_exact(2).remainder().is_empty() {
+            return Err(format!("Invalid save rules {}", value));
+        }
+
+        values
+            .chunks_exact(2)
+            .map(|pair| {
+                let seconds = pair[0].parse().ok().filter(|seconds| *seconds > 0);
+                let changes = pair[1].parse().ok().filter(|changes| *changes > 0);
+
+                match (seconds, changes) {
+                    (Some(seconds), Some(changes)) => Ok(SavePoint { seconds, changes }),
+                    _ => Err(format!("Invalid save rule {} {}", pair[0], pair[1])),
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for SavePoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.seconds, self.changes)
+    }
+}
+
+/// Saves the store to an RDB file, either blocking the caller (SAVE) or in the background (BGSAVE).
+///
+/// Only one save can run at a time.
+#[derive(Debug)]
+pub struct RdbSaver {
+    // Unix time (in seconds) of the last successful save.
+    last_save: AtomicI64,
+    // Unix time (in seconds) when the last background save started.
+    last_bgsave_try: AtomicI64,
+    in_progress: AtomicBool,
+    last_bgsave_ok: AtomicBool,
+    // Number of copies of the RDB file kept after every save. 0 means that no copy is kept.
+    retention: usize,
+    last_retention_ok: AtomicBool,
+}
+
+impl RdbSaver {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            last_save: AtomicI64::new(now.timestamp()),
+            last_bgsave_try: AtomicI64::new(0),
+            in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+            retention: 0,
+            last_retention_ok: AtomicBool::new(true),
+        }
+    }
+
+    /// Keeps a copy of the last `retention` saves next to the RDB file, named after the time they were made, so the
+    /// dataset can be restored to a previous one.
+    pub fn with_retention(&mut self, retention: usize) {
+        self.retention = retention;
+    }
+
+    /// Saves the store blocking the caller (SAVE).
+    pub fn save(&self, store: &Store, rdb_path: &Path) -> Result<(), RdbSaveError> {
+        self.start()?;
+
+        let dirty = store.dirty();
+        let snapshot = store.snapshot();
+        let now = store.clock().now();
+        let result = write_rdb_file(&snapshot, store.used_memory(), now, rdb_path, false);
+
+        if result.is_ok() {
+            store.clear_dirty(dirty);
+            self.last_save.store(now.timestamp(), Ordering::Relaxed);
+            self.retain(rdb_path, now);
+        }
+
+        // Only background saves change the status of the last one. A failed SAVE is replied to its client instead.
+        self.in_progress.store(false, Ordering::Release);
+
+        result
+    }
+
+    /// Takes a snapshot of the store and writes it to `rdb_path` in a blocking task, so the caller does not need to
+    /// wait for the file to be written.
+    pub fn background_save(
+        self: &Arc<Self>,
+        store: Arc<Store>,
+        rdb_path: PathBuf,
+    ) -> Result<(), RdbSaveError> {
+        self.start()?;
+
+        let dirty = store.dirty();
+        let snapshot = store.snapshot();
+        let saver = self.clone();
+
+        self.last_bgsave_try
+            .store(store.clock().now().timestamp(), Ordering::Relaxed);
+
+        tokio::task::spawn_blocking(move || {
+            let now = store.clock().now();
+            let result = write_rdb_file(&snapshot, store.used_memory(), now, &rdb_path, false);
+
+            // The error is reported by INFO persistence (rdb_last_bgsave_status).
+            if result.is_ok() {
+                store.clear_dirty(dirty);
+                saver.last_save.store(now.timestamp(), Ordering::Relaxed);
+                saver.retain(&rdb_path, now);
+            }
+
+            saver
+                .last_bgsave_ok
+                .store(result.is_ok(), Ordering::Relaxed);
+
+            saver.in_progress.store(false, Ordering::Release);
+        });
+
+        Ok(())
+    }
+
+    /// Returns true when any of the save rules is met and there is no save running. After a failed save, it waits
+    /// a few seconds before trying again.
+    pub fn should_save(&self, save_points: &[SavePoint], dirty: u64, now: DateTime<Utc>) -> bool {
+        if self.is_in_progress() {
+            return false;
+        }
+
+        let now = now.timestamp();
+        let can_retry = self.last_bgsave_ok()
+            || now - self.last_bgsave_try.load(Ordering::Relaxed) > BGSAVE_RETRY_DELAY;
+        let elapsed = now - self.last_save();
+
+        can_retry
+            && save_points
+                .iter()
+                .any(|save_point| dirty >= save_point.changes && elapsed > save_point.seconds)
+    }
+
+    /// Unix time (in seconds) of the last successful save.
+    pub fn last_save(&self) -> i64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    pub fn is_in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::Acquire)
+    }
+
+    pub fn last_bgsave_ok(&self) -> bool {
+        self.last_bgsave_ok.load(Ordering::Relaxed)
+    }
+
+    /// Whether the copies of the last successful save were kept.
+    pub fn last_retention_ok(&self) -> bool {
+        self.last_retention_ok.load(Ordering::Relaxed)
+    }
+
+    // The saved file is already in place, so a copy that could not be kept does not make the save fail. It is
+    // reported by INFO persistence (rdb_last_retention_status).
+    fn retain(&self, rdb_path: &Path, now: DateTime<Utc>) {
+        if self.retention == 0 {
+            return;
+        }
+
+        let result = retain_rdb_file(rdb_path, now, self.retention);
+
+        self.last_retention_ok
+            .store(result.is_ok(), Ordering::Relaxed);
+    }
+
+    fn start(&self) -> Result<(), RdbSaveError> {
+        self.in_progress
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .map(|_| ())
+            .map_err(|_| RdbSaveError::InProgress)
+    }
+}
+
+/// Writes a snapshot to an RDB file. `aof_base` marks the file as the base of an AOF in its metadata.
+///
+/// The snapshot is written to a temporary file in the same directory, which replaces `rdb_path` once it is on disk.
+/// A crash in the middle of a save leaves the previous file untouched.
+pub fn write_rdb_file(
+    snapshot: &StoreSnapshot,
+    used_memory: usize,
+    now: DateTime<Utc>,
+    rdb_path: &Path,
+    aof_base: bool,
+) -> Result<(), RdbSaveError> {
+    let file_name = rdb_path
+        .file_name()
+        .ok_or(RdbSaveError::WriteFile(String::from(
+            "Invalid RDB file path",
+        )))?;
+    let temp_path =
+        rdb_path.with_file_name(format!("{}{}", TEMP_PREFIX, file_name.to_string_lossy()));
+
+    let result = encode_rdb_file(snapshot, used_memory, now, &temp_path, aof_base).and_then(|_| {
+        fs::rename(&temp_path, rdb_path)
+            .and_then(|_| sync_dir(rdb_path))
+            .map_err(|err| RdbSaveError::WriteFile(err.to_string()))
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+fn encode_rdb_file(
+    snapshot: &StoreSnapshot,
+    used_memory: usize,
+    now: DateTime<Utc>,
+    rdb_path: &Path,
+    aof_base: bool,
+) -> Result<(), RdbSaveError> {
+    let file = File::create(rdb_path).map_err(|err| RdbSaveError::WriteFile(err.to_string()))?;
+    let mut encoder = RdbFileEncoder::new(BufWriter::new(file));
+
+    encoder
+        .encode_header()
+        .and_then(|_| encoder.encode_metadata("redis-ver", REDIS_VERSION))
+        .and_then(|_| encoder.encode_metadata("redis-bits", "64"))
+        .and_then(|_| encoder.encode_metadata("ctime", &now.timestamp().to_string()))
+        .and_then(|_| encoder.encode_metadata("used-mem", &used_memory.to_string()))
+        .and_then(|_| encoder.encode_metadata("aof-base", if aof_base { "1" } else { "0" }))
+        .map_err(|err| RdbSaveError::Encode(err.to_string()))?;
+
+    // Empty databases are not written, the same as Redis does.
+    if !snapshot.is_empty() {
+        encoder
+            .encode_database(0, snapshot.len(), snapshot.expires_len())
+            .map_err(|err| RdbSaveError::Encode(err.to_string()))?;
+
+        for (key, value) in snapshot.iter() {
+            encoder
+                .encode_key_value(key, value)
+                .map_err(|err| RdbSaveError::Encode(err.to_string()))?;
+        }
+    }
+
+    let writer = encoder
+        .finish()
+        .map_err(|err| RdbSaveError::Encode(err.to_string()))?;
+
+    writer
+        .into_inner()
+        .map_err(|err| RdbSaveError::WriteFile(err.to_string()))?
+        .sync_all()
+        .map_err(|err| RdbSaveError::WriteFile(err.to_string()))
+}
+
+// Links the saved file with the time of the save in its name, and deletes the oldest links so only the last
+// `retention` ones are kept. A link is as good as a copy, since every save replaces the RDB file with a new one.
+fn retain_rdb_file(
+    rdb_path: &Path,
+    now: DateTime<Utc>,
+    retention: usize,
+) -> Result<(), RdbSaveError> {
+    let file_name = rdb_path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .ok_or(RdbSaveError::WriteFile(String::from(
+            "Invalid RDB file path",
+        )))?;
+    let prefix = format!("{}.", file_name);
+    let retained_path = rdb_path.with_file_name(format!(
+        "{}{}",
+        prefix,
+        now.format(RETAINED_FILE_TIME_FORMAT)
+    ));
+
+    // Two saves in the same second keep the last one.
+    match fs::remove_file(&retained_path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            return Err(RdbSaveError::WriteFile(err.to_string()))
+        }
+        _ => {}
+    }
+
+    fs::hard_link(rdb_path, &retained_path)
+        .map_err(|err| RdbSaveError::WriteFile(err.to_string()))?;
+
+    let dir = rdb_path.parent().unwrap_or(Path::new("")).join(".");
+    let mut retained_names: Vec<String> = fs::read_dir(&dir)
+        .map_err(|err| RdbSaveError::WriteFile(err.to_string()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| {
+            name.strip_prefix(&prefix).is_some_and(|time| {
+                NaiveDateTime::parse_from_str(time, RETAINED_FILE_TIME_FORMAT).is_ok()
+            })
+        })
+        .collect();
+
+    // The time format sorts the names from the oldest to the newest.
+    retained_names.sort();
+
+    for name in retained_names
+        .iter()
+        .take(retained_names.len().saturating_sub(retention))
+    {
+        fs::remove_file(rdb_path.with_file_name(name))
+            .map_err(|err| RdbSaveError::WriteFile(err.to_string()))?;
+    }
+
+    sync_dir(rdb_path).map_err(|err| RdbSaveError::WriteFile(err.to_string()))
+}
+
+// Flushes the directory of a file, which makes the creation, rename or removal of the file durable.
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    File::open(path.parent().unwrap_or(Path::new("")).join(".")).and_then(|dir| dir.sync_all())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+    use tokio::io::BufReader;
+
+    use crate::clock::{Clock, ManualClock};
+    use crate::rdb::decoder::RdbFileDecoder;
+    use crate::store::{StoreData, StoreValueBuilder};
+
+    use super::*;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    // An empty directory for the files of a test, removed when the test starts.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rdb-save-{}-{}", std::process::id(), name));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn store_with_keys(keys: &[(&str, &str)]) -> Arc<Store> {
+        let store = Arc::new(Store::default());
+
+        for (key, value) in keys {
+            let mut builder = StoreValueBuilder::new();
+
+            builder.with_value(value);
+            store.set(key, builder.build()).unwrap();
+        }
+
+        store
+    }
+
+    async fn saved_keys(rdb_path: &Path) -> Vec<(String, StoreData)> {
+        let file = tokio::fs::File::open(rdb_path).await.unwrap();
+        let data = RdbFileDecoder::new(BufReader::new(file))
+            .decode()
+            .await
+            .unwrap();
+        let mut keys: Vec<(String, StoreData)> = data
+            .databases
+            .into_iter()
+            .flat_map(|databases| databases.databases.into_values())
+            .flat_map(|database| database.data)
+            .map(|(key, value)| (key, value.value))
+            .collect();
+
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+
+        keys
+    }
+
+    async fn wait_for_background_save(saver: &RdbSaver) {
+        while saver.is_in_progress() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn save_writes_the_dataset() {
+        let rdb_path = test_dir("save").join("dump.rdb");
+        let store = store_with_keys(&[("a", "1"), ("b", "2")]);
+        let saver = RdbSaver::new(time(0));
+
+        saver.save(&store, &rdb_path).unwrap();
+
+        assert_eq!(
+            saved_keys(&rdb_path).await,
+            vec![
+                (String::from("a"), StoreData::String(String::from("1"))),
+                (String::from("b"), StoreData::String(String::from("2"))),
+            ]
+        );
+        assert_eq!(store.dirty(), 0);
+        assert_eq!(saver.last_save(), store.clock().now().timestamp());
+        assert!(!saver.is_in_progress());
+    }
+
+    #[tokio::test]
+    async fn background_save_writes_the_dataset_at_the_time_it_started() {
+        let rdb_path = test_dir("bgsave").join("dump.rdb");
+        let store = store_with_keys(&[("a", "1")]);
+        let saver = Arc::new(RdbSaver::new(time(0)));
+
+        saver
+            .background_save(store.clone(), rdb_path.clone())
+            .unwrap();
+        // Written once the snapshot is taken, so it is not saved.
+        store.set("b", store.get("a").unwrap()).unwrap();
+        wait_for_background_save(&saver).await;
+
+        assert_eq!(
+            saved_keys(&rdb_path).await,
+            vec![(String::from("a"), StoreData::String(String::from("1")))]
+        );
+        assert!(saver.last_bgsave_ok());
+        assert_eq!(store.dirty(), 1);
+    }
+
+    #[tokio::test]
+    async fn background_save_reports_failures() {
+        let rdb_path = test_dir("bgsave-failure")
+            .join("missing-dir")
+            .join("dump.rdb");
+        let store = store_with_keys(&[("a", "1")]);
+        let saver = Arc::new(RdbSaver::new(time(0)));
+
+        saver
+            .background_save(store.clone(), rdb_path.clone())
+            .unwrap();
+        wait_for_background_save(&saver).await;
+
+        assert!(!saver.last_bgsave_ok());
+        assert_eq!(store.dirty(), 1);
+        assert!(!rdb_path.with_file_name("temp-dump.rdb").exists());
+    }
+
+    #[test]
+    fn save_rules_are_parsed_in_pairs() {
+        assert_eq!(
+            SavePoint::parse_all("3600 1 300 100"),
+            Ok(vec![
+                SavePoint {
+                    seconds: 3600,
+                    changes: 1
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 100
+                },
+            ])
+        );
+        assert_eq!(SavePoint::parse_all(""), Ok(vec![]));
+        assert!(SavePoint::parse_all("3600").is_err());
+        assert!(SavePoint::parse_all("0 1").is_err());
+        assert!(SavePoint::parse_all("60 0").is_err());
+        assert!(SavePoint::parse_all("60 many").is_err());
+    }
+
+    #[test]
+    fn saves_once_a_rule_is_met() {
+        let saver = RdbSaver::new(time(1000));
+        let rules = SavePoint::parse_all("60 10 3600 1").unwrap();
+
+        assert!(!saver.should_save(&rules, 0, time(10000)));
+        assert!(!saver.should_save(&rules, 9, time(1061)));
+        assert!(!saver.should_save(&rules, 10, time(1060)));
+        assert!(saver.should_save(&rules, 10, time(1061)));
+        assert!(saver.should_save(&rules, 1, time(4601)));
+        assert!(!saver.should_save(&[], 1000, time(10000)));
+    }
+
+    // The names in the directory of a test, sorted.
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+
+        names.sort();
+
+        names
+    }
+
+    #[tokio::test]
+    async fn rdb_files_are_replaced_through_a_temporary_file() {
+        let dir = test_dir("atomic-write");
+        let rdb_path = dir.join("dump.rdb");
+
+        // Left by a save that crashed.
+        fs::write(dir.join("temp-dump.rdb"), "partial").unwrap();
+
+        for value in ["1", "2"] {
+            let store = store_with_keys(&[("a", value)]);
+
+            write_rdb_file(&store.snapshot(), 0, time(0), &rdb_path, false).unwrap();
+        }
+
+        assert_eq!(
+            saved_keys(&rdb_path).await,
+            vec![(String::from("a"), StoreData::String(String::from("2")))]
+        );
+        assert_eq!(file_names(&dir), vec![String::from("dump.rdb")]);
+    }
+
+    #[test]
+    fn retention_keeps_the_last_saves() {
+        let dir = test_dir("retention");
+        let rdb_path = dir.join("dump.rdb");
+        let clock = Arc::new(ManualClock::new(time(1_735_786_800)));
+        let store = Store::with_clock(clock.clone());
+        let mut saver = RdbSaver::new(clock.now());
+
+        saver.with_retention(2);
+
+        for seconds in [0, 1, 1, 2] {
+            clock.set(time(1_735_786_800 + seconds));
+            saver.save(&store, &rdb_path).unwrap();
+        }
+
+        assert_eq!(
+            file_names(&dir),
+            vec![
+                String::from("dump.rdb"),
+                String::from("dump.rdb.20250102-030001"),
+                String::from("dump.rdb.20250102-030002"),
+            ]
+        );
+        assert!(saver.last_retention_ok());
+    }
+
+    #[test]
+    fn failed_retention_does_not_fail_the_save() {
+        let dir = test_dir("retention-failure");
+        let rdb_path = dir.join("dump.rdb");
+        let clock = Arc::new(ManualClock::new(time(1_735_786_800)));
+        let store = Store::with_clock(clock.clone());
+        let mut saver = RdbSaver::new(clock.now());
+
+        saver.with_retention(2);
+        // The copy of the save can not replace a directory.
+        fs::create_dir(dir.join("dump.rdb.20250102-030000")).unwrap();
+
+        saver.save(&store, &rdb_path).unwrap();
+
+        assert!(rdb_path.exists());
+        assert!(!saver.last_retention_ok());
+    }
+
+    #[test]
+    fn failed_saves_do_not_change_the_background_save_status() {
+        let store = Store::default();
+        let saver = RdbSaver::new(store.clock().now());
+        let rdb_path = std::env::temp_dir()
+            .join("missing-dir-of-failed-save")
+            .join("dump.rdb");
+
+        assert!(saver.save(&store, &rdb_path).is_err());
+        assert!(saver.last_bgsave_ok());
+        assert!(!saver.is_in_progress());
+    }
+}
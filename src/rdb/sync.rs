@@ -1,17 +1,24 @@
-use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
 use tokio::fs::File;
+use tokio::io::{AsyncRead, BufReader, ReadBuf};
 
-use crate::store::Store;
+use chrono::{DateTime, Utc};
+
+use crate::aof::writer::{AofError, AofWriter};
+use crate::store::{Store, StoreValue};
 
 use super::decoder::RdbFileDecoder;
 
+#[derive(Debug)]
 pub enum RdbSyncError {
     ReadFile(String),
     DecodeData(String),
-    LockStore(String),
+    Store(String),
+    Aof(String),
 }
 
 impl std::fmt::Display for RdbSyncError {
@@ -23,53 +30,500 @@ impl std::fmt::Display for RdbSyncError {
             RdbSyncError::DecodeData(err) => {
                 write!(f, "DecodeData: {}", err)
             }
-            RdbSyncError::LockStore(err) => {
-                write!(f, "LockStore: {}", err)
+            RdbSyncError::Store(err) => {
+                write!(f, "Store: {}", err)
+            }
+            RdbSyncError::Aof(err) => {
+                write!(f, "Aof: {}", err)
             }
         }
     }
 }
 
+/// The number of keys of the last RDB file loaded.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RdbSyncStats {
+    pub keys_loaded: u64,
+    /// Keys that had already expired and were not loaded. Always 0 on replicas.
+    pub keys_expired: u64,
+    /// Keys that were not loaded because their name or value is not valid UTF-8.
+    pub keys_skipped: u64,
+}
+
+/// What happens to the keys already in the store when an RDB file is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdbLoadMode {
+    /// The keys of the file are added to the store in a single step once the file is decoded, overwriting the ones
+    /// with the same name.
+    Merge,
+    /// The keys of the file replace the whole keyspace, in a single step once the file is decoded.
+    Replace,
+}
+
+// The keys of an RDB file that was decoded, but not added to the store yet.
+struct RdbDecodedKeys {
+    mode: RdbLoadMode,
+    values: Vec<(String, StoreValue)>,
+    stats: RdbSyncStats,
+}
+
+/// The progress of the RDB file being loaded.
+#[derive(Debug, Clone, Copy)]
+pub struct RdbLoadingProgress {
+    /// Unix time (in seconds) when the loading started.
+    pub start_time: i64,
+    pub total_bytes: u64,
+    pub loaded_bytes: u64,
+}
+
+impl RdbLoadingProgress {
+    pub fn loaded_perc(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 0.0;
+        }
+
+        // One byte is added so that it does not reach 100% before the loading ends, the same as Redis.
+        self.loaded_bytes as f64 / (self.total_bytes + 1) as f64 * 100.0
+    }
+
+    /// Estimated seconds to finish, based on the speed of the loading so far.
+    pub fn eta_seconds(&self, now: DateTime<Utc>) -> u64 {
+        let elapsed = (now.timestamp() - self.start_time).max(0) as u64;
+        let remaining_bytes = self.total_bytes.saturating_sub(self.loaded_bytes);
+
+        if elapsed == 0 {
+            return 1;
+        }
+
+        elapsed * remaining_bytes / (self.loaded_bytes + 1)
+    }
+}
+
+/// Loads RDB files into the store. The loading state is shared, so clients can be told that the dataset is not
+/// ready yet and follow the progress.
+#[derive(Debug)]
 pub struct RdbSync {
-    store: Arc<Mutex<Store>>,
+    store: Arc<Store>,
+    // Replicas keep expired keys until the master deletes them, so the dataset of both is the same.
+    keep_expired: bool,
+    // The AOF of the server, once it is created. Loading a file while it is enabled changes the dataset behind its
+    // back, so it is locked while the keys are added and rewritten afterwards.
+    aof_writer: OnceLock<Arc<AofWriter>>,
+    last_load_keys_loaded: AtomicU64,
+    last_load_keys_expired: AtomicU64,
+    last_load_keys_skipped: AtomicU64,
+    loading: AtomicBool,
+    loading_start_time: AtomicI64,
+    loading_total_bytes: AtomicU64,
+    loading_loaded_bytes: AtomicU64,
 }
 
 impl RdbSync {
-    pub fn new(store: Arc<Mutex<Store>>) -> Self {
-        Self { store }
+    pub fn new(store: Arc<Store>) -> Self {
+        Self {
+            store,
+            keep_expired: false,
+            aof_writer: OnceLock::new(),
+            last_load_keys_loaded: AtomicU64::new(0),
+            last_load_keys_expired: AtomicU64::new(0),
+            last_load_keys_skipped: AtomicU64::new(0),
+            loading: AtomicBool::new(false),
+            loading_start_time: AtomicI64::new(0),
+            loading_total_bytes: AtomicU64::new(0),
+            loading_loaded_bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_keep_expired(&mut self, keep_expired: bool) {
+        self.keep_expired = keep_expired;
+    }
+
+    /// Sets the AOF that is kept up to date when a file is loaded. It can only be set once.
+    pub fn set_aof_writer(&self, aof_writer: Arc<AofWriter>) {
+        let _ = self.aof_writer.set(aof_writer);
+    }
+
+    pub fn last_load_stats(&self) -> RdbSyncStats {
+        RdbSyncStats {
+            keys_loaded: self.last_load_keys_loaded.load(Ordering::Relaxed),
+            keys_expired: self.last_load_keys_expired.load(Ordering::Relaxed),
+            keys_skipped: self.last_load_keys_skipped.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Acquire)
+    }
+
+    /// Marks the dataset as being loaded. `sync` does it too, but a loading that happens in the background has to
+    /// be marked before accepting clients.
+    pub fn start_loading(&self) {
+        self.loading_start_time
+            .store(self.store.clock().now().timestamp(), Ordering::Relaxed);
+        self.loading_total_bytes.store(0, Ordering::Relaxed);
+        self.loading_loaded_bytes.store(0, Ordering::Relaxed);
+        self.loading.store(true, Ordering::Release);
+    }
+
+    pub fn finish_loading(&self) {
+        self.loading.store(false, Ordering::Release);
+    }
+
+    /// Updates the progress of a loading made by someone else (the AOF loading, for instance).
+    pub fn set_loading_progress(&self, total_bytes: u64, loaded_bytes: u64) {
+        self.loading_total_bytes
+            .store(total_bytes, Ordering::Relaxed);
+        self.loading_loaded_bytes
+            .store(loaded_bytes, Ordering::Relaxed);
     }
+
+    pub fn loading_progress(&self) -> Option<RdbLoadingProgress> {
+        if !self.is_loading() {
+            return None;
+        }
+
+        Some(RdbLoadingProgress {
+            start_time: self.loading_start_time.load(Ordering::Relaxed),
+            total_bytes: self.loading_total_bytes.load(Ordering::Relaxed),
+            loaded_bytes: self.loading_loaded_bytes.load(Ordering::Relaxed),
+        })
+    }
+
     // Sync the values from .rdb file to the redis store
-    pub async fn sync(&mut self, rdb_path: PathBuf) -> Result<(), RdbSyncError> {
+    pub async fn sync(&self, rdb_path: PathBuf) -> Result<RdbSyncStats, RdbSyncError> {
+        self.start_loading();
+
+        let result = self.load(rdb_path, RdbLoadMode::Merge).await;
+
+        self.finish_loading();
+
+        result
+    }
+
+    /// Loads an RDB file without marking the dataset as being loaded, for files that are only part of the loading
+    /// (the base of the AOF, for instance) and for replacing the dataset of a running server. Clients keep using the
+    /// previous keys while the file is decoded.
+    ///
+    /// When the AOF is enabled, it is locked while the keys are added to the store, so no write is appended in the
+    /// middle of the change of the dataset, and it is rewritten from the new dataset afterwards, since it does not
+    /// know about the keys loaded. It fails while the AOF is being rewritten, since the rewrite would miss them.
+    pub async fn load(
+        &self,
+        rdb_path: PathBuf,
+        mode: RdbLoadMode,
+    ) -> Result<RdbSyncStats, RdbSyncError> {
+        let aof_writer = self.aof_writer.get().filter(|aof| aof.is_enabled());
+        let rewrite_in_progress = || aof_writer.is_some_and(|aof| aof.is_rewrite_in_progress());
+
+        // Checked before decoding too, so the file is not decoded for nothing.
+        if rewrite_in_progress() {
+            return Err(RdbSyncError::Aof(AofError::RewriteInProgress.to_string()));
+        }
+
+        let keys = self.decode(rdb_path, mode).await?;
+
+        let stats = {
+            let _aof = aof_writer.map(|aof| aof.lock());
+
+            // A rewrite that starts once the AOF is unlocked takes its snapshot after the keys are added.
+            if rewrite_in_progress() {
+                return Err(RdbSyncError::Aof(AofError::RewriteInProgress.to_string()));
+            }
+
+            self.apply(keys)?
+        };
+
+        // A rewrite started in the meantime has the keys loaded already.
+        if let Some(aof) = aof_writer {
+            match aof.background_rewrite(self.store.clone()) {
+                Ok(()) | Err(AofError::RewriteInProgress) => {}
+                Err(err) => return Err(RdbSyncError::Aof(err.to_string())),
+            }
+        }
+
+        Ok(stats)
+    }
+
+    // Decodes an RDB file without touching the store. The keys are added to the store with `apply`.
+    async fn decode(
+        &self,
+        rdb_path: PathBuf,
+        mode: RdbLoadMode,
+    ) -> Result<RdbDecodedKeys, RdbSyncError> {
+        let mut keys = RdbDecodedKeys {
+            mode,
+            values: vec![],
+            stats: RdbSyncStats::default(),
+        };
+
+        // The progress of a previous file is not carried over.
+        self.loading_total_bytes.store(0, Ordering::Relaxed);
+        self.loading_loaded_bytes.store(0, Ordering::Relaxed);
+
         let file = File::open(rdb_path).await;
         // When the file does not exists, we do not need to modify the Redis database, neither to throw an error.
+        // Replacing the dataset with a missing file is an error though, since it would remove every key.
         let file = match file {
             Ok(f) => f,
             Err(err) => match err.kind() {
-                tokio::io::ErrorKind::NotFound => return Ok(()),
+                tokio::io::ErrorKind::NotFound if mode == RdbLoadMode::Merge => return Ok(keys),
                 _ => return Err(RdbSyncError::ReadFile(err.to_string())),
             },
         };
 
-        let mut decoder = RdbFileDecoder::new(file);
+        let total_bytes = file
+            .metadata()
+            .await
+            .map_err(|err| RdbSyncError::ReadFile(err.to_string()))?
+            .len();
+
+        self.loading_total_bytes
+            .store(total_bytes, Ordering::Relaxed);
+
+        let reader = ProgressReader {
+            reader: file,
+            loaded_bytes: &self.loading_loaded_bytes,
+        };
+        let mut decoder = RdbFileDecoder::new(BufReader::new(reader));
+        let now = self.store.clock().now();
+
+        if !self.keep_expired {
+            decoder.with_now(now);
+        }
 
         let rdb_data = decoder
             .decode()
             .await
             .map_err(|err| RdbSyncError::DecodeData(err.to_string()))?;
 
-        let mut store = self
-            .store
-            .lock()
-            .map_err(|err| RdbSyncError::LockStore(err.to_string()))?;
-
         if let Some(databases) = rdb_data.databases {
             for (_, database) in databases.databases {
+                keys.stats.keys_skipped += database.keys_skipped;
+                keys.stats.keys_expired += database.keys_expired;
+
                 for (key, value) in database.data {
-                    store.set(&key, value);
+                    if !self.keep_expired && value.exp.is_some_and(|exp| exp < now) {
+                        keys.stats.keys_expired += 1;
+
+                        continue;
+                    }
+
+                    keys.values.push((key, value));
+                    keys.stats.keys_loaded += 1;
                 }
             }
         }
 
-        Ok(())
+        Ok(keys)
+    }
+
+    // Adds the keys decoded by `decode` to the store, in a single step for both modes. When the keys do not fit in
+    // memory, none of them is added.
+    fn apply(&self, keys: RdbDecodedKeys) -> Result<RdbSyncStats, RdbSyncError> {
+        match keys.mode {
+            RdbLoadMode::Merge => self
+                .store
+                .mset(keys.values)
+                .map_err(|err| RdbSyncError::Store(err.to_string()))?,
+            RdbLoadMode::Replace => self.store.replace_all(keys.values),
+        }
+
+        self.last_load_keys_loaded
+            .store(keys.stats.keys_loaded, Ordering::Relaxed);
+        self.last_load_keys_expired
+            .store(keys.stats.keys_expired, Ordering::Relaxed);
+        self.last_load_keys_skipped
+            .store(keys.stats.keys_skipped, Ordering::Relaxed);
+
+        Ok(keys.stats)
+    }
+}
+
+// Counts the bytes read, in order to report the loading progress.
+struct ProgressReader<'a, R> {
+    reader: R,
+    loaded_bytes: &'a AtomicU64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.reader).poll_read(cx, buf);
+
+        self.loaded_bytes
+            .fetch_add((buf.filled().len() - filled) as u64, Ordering::Relaxed);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use chrono::{Duration, TimeZone};
+
+    use crate::clock::ManualClock;
+    use crate::rdb::save::write_rdb_file;
+    use crate::store::StoreValueBuilder;
+
+    use super::*;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(seconds, 0).unwrap()
+    }
+
+    // An empty directory for the files of a test, removed when the test starts.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rdb-sync-{}-{}", std::process::id(), name));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn new_store(now: DateTime<Utc>) -> Arc<Store> {
+        Arc::new(Store::with_clock(Arc::new(ManualClock::new(now))))
+    }
+
+    // Saves a persistent key and a key that expires at `exp` to an RDB file.
+    fn write_keys(rdb_path: &Path, exp: DateTime<Utc>) {
+        let store = new_store(exp - Duration::seconds(10));
+
+        for (key, exp) in [("persistent", None), ("volatile", Some(exp))] {
+            let mut builder = StoreValueBuilder::new();
+
+            builder.with_value("value");
+
+            if let Some(exp) = exp {
+                builder.with_exp(exp);
+            }
+
+            store.set(key, builder.build()).unwrap();
+        }
+
+        write_rdb_file(&store.snapshot(), 0, store.clock().now(), rdb_path, false).unwrap();
+    }
+
+    fn sorted_keys(store: &Store) -> Vec<String> {
+        let mut keys = store.get_all_keys();
+
+        keys.sort();
+
+        keys
+    }
+
+    #[tokio::test]
+    async fn masters_skip_the_keys_that_already_expired() {
+        let rdb_path = test_dir("master").join("dump.rdb");
+        let store = new_store(time(1_700_000_100));
+        let rdb_sync = RdbSync::new(store.clone());
+
+        write_keys(&rdb_path, time(1_700_000_050));
+
+        let stats = rdb_sync.sync(rdb_path).await.unwrap();
+
+        assert_eq!(stats.keys_loaded, 1);
+        assert_eq!(stats.keys_expired, 1);
+        assert_eq!(rdb_sync.last_load_stats().keys_expired, 1);
+        assert_eq!(sorted_keys(&store), vec![String::from("persistent")]);
+    }
+
+    #[tokio::test]
+    async fn replicas_keep_the_keys_that_already_expired() {
+        let rdb_path = test_dir("replica").join("dump.rdb");
+        let store = new_store(time(1_700_000_100));
+        let mut rdb_sync = RdbSync::new(store.clone());
+
+        rdb_sync.with_keep_expired(true);
+        write_keys(&rdb_path, time(1_700_000_050));
+
+        let stats = rdb_sync.sync(rdb_path).await.unwrap();
+
+        assert_eq!(stats.keys_loaded, 2);
+        assert_eq!(stats.keys_expired, 0);
+        // The store does not return them, but they are kept until the master deletes them.
+        assert_eq!(store.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn missing_files_only_fail_when_replacing_the_dataset() {
+        let rdb_path = test_dir("missing").join("dump.rdb");
+        let store = new_store(time(1_700_000_000));
+        let rdb_sync = RdbSync::new(store.clone());
+
+        assert_eq!(
+            rdb_sync
+                .load(rdb_path.clone(), RdbLoadMode::Merge)
+                .await
+                .unwrap()
+                .keys_loaded,
+            0
+        );
+        assert!(matches!(
+            rdb_sync.load(rdb_path, RdbLoadMode::Replace).await,
+            Err(RdbSyncError::ReadFile(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn the_loading_progress_is_reported_while_loading() {
+        let rdb_path = test_dir("progress").join("dump.rdb");
+        let store = new_store(time(1_700_000_100));
+        let rdb_sync = RdbSync::new(store.clone());
+
+        write_keys(&rdb_path, time(1_700_000_200));
+
+        assert!(rdb_sync.loading_progress().is_none());
+
+        rdb_sync.start_loading();
+
+        let progress = rdb_sync.loading_progress().unwrap();
+
+        assert_eq!(progress.start_time, 1_700_000_100);
+        assert_eq!(progress.total_bytes, 0);
+
+        rdb_sync
+            .load(rdb_path.clone(), RdbLoadMode::Merge)
+            .await
+            .unwrap();
+
+        let size = fs::metadata(&rdb_path).unwrap().len();
+        let progress = rdb_sync.loading_progress().unwrap();
+
+        assert_eq!(progress.total_bytes, size);
+        assert_eq!(progress.loaded_bytes, size);
+
+        rdb_sync.finish_loading();
+
+        assert!(!rdb_sync.is_loading());
+        assert!(rdb_sync.loading_progress().is_none());
+    }
+
+    #[test]
+    fn the_loading_eta_follows_the_speed_so_far() {
+        let progress = RdbLoadingProgress {
+            start_time: 1_700_000_000,
+            total_bytes: 1000,
+            loaded_bytes: 249,
+        };
+
+        assert!((progress.loaded_perc() - 24.875).abs() < 0.001);
+        assert_eq!(progress.eta_seconds(time(1_700_000_000)), 1);
+        assert_eq!(progress.eta_seconds(time(1_700_000_010)), 30);
+        assert_eq!(
+            RdbLoadingProgress {
+                total_bytes: 0,
+                ..progress
+            }
+            .loaded_perc(),
+            0.0
+        );
     }
 }
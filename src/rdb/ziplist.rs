@@ -0,0 +1,88 @@
+//! The ziplist format, used before Redis 7 for small lists, hashes and sorted sets (and for quicklist nodes).
+//!
+//! A ziplist is a 4 bytes total length, a 4 bytes offset of the last element, a 2 bytes number of elements (all
+//! little-endian), the elements and a 0xFF terminator. Every element is the length of the previous one, an encoding
+//! and the content.
+
+const HEADER_SIZE: usize = 10;
+const END: u8 = 0xFF;
+
+/// Reads the elements of a ziplist. Integers are returned as their decimal representation. It returns None when
+/// the ziplist is corrupted.
+pub fn decode(input: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let total_length = u32::from_le_bytes(input.get(0..4)?.try_into().ok()?) as usize;
+
+    if total_length != input.len() {
+        return None;
+    }
+
+    let mut elements = Vec::new();
+    let mut position = HEADER_SIZE;
+
+    loop {
+        let first_byte = *input.get(position)?;
+
+        if first_byte == END {
+            break;
+        }
+
+        // The previous element length takes one byte, or five when the first one is 0xFE.
+        position += if first_byte < 0xFE { 1 } else { 5 };
+
+        let (element, length) = decode_element(input.get(position..)?)?;
+
+        elements.push(element);
+        position += length;
+    }
+
+    Some(elements)
+}
+
+// Returns the element and the number of bytes of its encoding and content.
+fn decode_element(input: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let encoding = *input.first()?;
+
+    let string = |start: usize, length: usize| -> Option<(Vec<u8>, usize)> {
+        Some((input.get(start..start + length)?.to_vec(), start + length))
+    };
+    let integer = |value: i64, length: usize| Some((value.to_string().into_bytes(), length));
+
+    match encoding >> 6 {
+        // 00pppppp: string of up to 63 bytes.
+        0b00 => string(1, usize::from(encoding & 0x3F)),
+        // 01pppppp qqqqqqqq: string of up to 16383 bytes (big-endian).
+        0b01 => {
+            let length = (usize::from(encoding & 0x3F) << 8) | usize::from(*input.get(1)?);
+
+            string(2, length)
+        }
+        // 10000000 followed by 4 bytes (big-endian): longer strings.
+        0b10 => {
+            let length = u32::from_be_bytes(input.get(1..5)?.try_into().ok()?) as usize;
+
+            string(5, length)
+        }
+        _ => match encoding {
+            0xC0 => integer(
+                i64::from(i16::from_le_bytes(input.get(1..3)?.try_into().ok()?)),
+                3,
+            ),
+            0xD0 => integer(
+                i64::from(i32::from_le_bytes(input.get(1..5)?.try_into().ok()?)),
+                5,
+            ),
+            0xE0 => integer(i64::from_le_bytes(input.get(1..9)?.try_into().ok()?), 9),
+            0xF0 => {
+                let bytes = input.get(1..4)?;
+                // The 24 bits are shifted to the top of an i32, so the sign is extended when shifting them back.
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+
+                integer(i64::from(value), 4)
+            }
+            0xFE => integer(i64::from(*input.get(1)? as i8), 2),
+            // 1111xxxx: an integer between 0 and 12, stored as xxxx - 1.
+            0xF1..=0xFD => integer(i64::from(encoding & 0x0F) - 1, 1),
+            _ => None,
+        },
+    }
+}
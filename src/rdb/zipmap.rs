@@ -0,0 +1,48 @@
+//! The zipmap format, used before Redis 2.6 for small hashes.
+//!
+//! A zipmap is a 1 byte number of pairs (not reliable over 253), the pairs and a 0xFF terminator. Every pair is
+//! the key length, the key, the value length, a 1 byte number of free bytes, the value and the free bytes.
+
+const END: u8 = 0xFF;
+
+/// Reads the fields and values of a zipmap, one after the other. It returns None when the zipmap is corrupted.
+pub fn decode(input: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut elements = Vec::new();
+    let mut position = 1;
+
+    loop {
+        if *input.get(position)? == END {
+            break;
+        }
+
+        let (key_length, size) = decode_length(&input[position..])?;
+
+        position += size;
+        elements.push(input.get(position..position + key_length)?.to_vec());
+        position += key_length;
+
+        let (value_length, size) = decode_length(input.get(position..)?)?;
+
+        position += size;
+
+        let free = usize::from(*input.get(position)?);
+
+        position += 1;
+        elements.push(input.get(position..position + value_length)?.to_vec());
+        position += value_length + free;
+    }
+
+    Some(elements)
+}
+
+// Lengths take one byte, or five when the first one is 254.
+fn decode_length(input: &[u8]) -> Option<(usize, usize)> {
+    match *input.first()? {
+        254 => Some((
+            u32::from_le_bytes(input.get(1..5)?.try_into().ok()?) as usize,
+            5,
+        )),
+        END => None,
+        length => Some((usize::from(length), 1)),
+    }
+}
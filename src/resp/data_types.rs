@@ -5,8 +5,13 @@ use std::str::Lines;
 pub enum RespDataType {
     Array(Vec<RespDataType>),
     BulkString(String),
+    /// A bulk string of arbitrary bytes, such as a DUMP payload. Only bulk strings that are not valid UTF-8 are
+    /// decoded as `BinaryBulkString`, the rest are decoded as `BulkString`.
+    BinaryBulkString(Vec<u8>),
     NullBulkString,
     SimpleString(String),
+    SimpleError(String),
+    Integer(i64),
 }
 
 #[derive(Debug)]
@@ -54,34 +59,124 @@ impl RespDecoder {
                 Some(value) => Ok(RespDataType::SimpleString(value.to_string())),
                 None => Ok(RespDataType::SimpleString("".to_string())),
             },
+            Some(line) if line.starts_with("-") => match line.strip_prefix("-") {
+                Some(value) => Ok(RespDataType::SimpleError(value.to_string())),
+                None => Ok(RespDataType::SimpleError("".to_string())),
+            },
+            Some(line) if line.starts_with(":") => line
+                .strip_prefix(":")
+                .and_then(|value| value.parse::<i64>().ok())
+                .map(RespDataType::Integer)
+                .ok_or(RespDecoderError::InvalidRespDataType),
             Some(_) => Err(RespDecoderError::InvalidRespDataType),
             None => Err(RespDecoderError::InvalidRespDataType),
         }
     }
+
+    /// Decodes the first value of a buffer that can contain more values after it. Bulk strings are read by their
+    /// length, so they can contain line breaks.
+    ///
+    /// It returns the value and the number of bytes that it takes, or None when the buffer ends before the value.
+    pub fn decode_bytes(buf: &[u8]) -> Result<Option<(RespDataType, usize)>, RespDecoderError> {
+        // The type is checked first, so a value that is not valid is not taken for an incomplete one.
+        match buf.first() {
+            Some(b'*' | b'$' | b'+' | b'-' | b':') => {}
+            Some(_) => return Err(RespDecoderError::InvalidRespDataType),
+            None => return Ok(None),
+        }
+
+        let line_end = match buf.windows(2).position(|window| window == b"\r\n") {
+            Some(line_end) => line_end,
+            None => return Ok(None),
+        };
+        let line = String::from_utf8_lossy(&buf[1..line_end]).to_string();
+        let mut length = line_end + 2;
+
+        let value = match buf[0] {
+            b'*' => {
+                let size: usize = line
+                    .parse()
+                    .map_err(|_| RespDecoderError::InvalidRespDataType)?;
+                let mut values = Vec::with_capacity(size);
+
+                for _ in 0..size {
+                    match RespDecoder::decode_bytes(&buf[length..])? {
+                        Some((value, value_length)) => {
+                            values.push(value);
+                            length += value_length;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+
+                RespDataType::Array(values)
+            }
+            b'$' if line == "-1" => RespDataType::NullBulkString,
+            b'$' => {
+                let size: usize = line
+                    .parse()
+                    .map_err(|_| RespDecoderError::InvalidRespDataType)?;
+
+                if buf.len() < length + size + 2 {
+                    return Ok(None);
+                }
+
+                if &buf[length + size..length + size + 2] != b"\r\n" {
+                    return Err(RespDecoderError::InvalidRespDataType);
+                }
+
+                let value = buf[length..length + size].to_vec();
+
+                length += size + 2;
+
+                match String::from_utf8(value) {
+                    Ok(value) => RespDataType::BulkString(value),
+                    Err(err) => RespDataType::BinaryBulkString(err.into_bytes()),
+                }
+            }
+            b'+' => RespDataType::SimpleString(line),
+            b'-' => RespDataType::SimpleError(line),
+            b':' => line
+                .parse::<i64>()
+                .map(RespDataType::Integer)
+                .map_err(|_| RespDecoderError::InvalidRespDataType)?,
+            _ => return Err(RespDecoderError::InvalidRespDataType),
+        };
+
+        Ok(Some((value, length)))
+    }
 }
 
 pub struct RespEncoder;
 
 impl RespEncoder {
-    pub fn encode(data_type: RespDataType) -> String {
+    /// Encodes a value as bytes, since bulk strings can be binary.
+    pub fn encode(data_type: RespDataType) -> Vec<u8> {
         match data_type {
-            RespDataType::SimpleString(value) => {
-                format!("+{}\r\n", value)
-            }
-            RespDataType::BulkString(value) => {
-                format!("${}\r\n{}\r\n", value.len(), value)
-            }
-            RespDataType::NullBulkString => "$-1\r\n".to_string(),
+            RespDataType::SimpleString(value) => format!("+{}\r\n", value).into_bytes(),
+            RespDataType::SimpleError(value) => format!("-{}\r\n", value).into_bytes(),
+            RespDataType::Integer(value) => format!(":{}\r\n", value).into_bytes(),
+            RespDataType::BulkString(value) => RespEncoder::encode_bulk_string(value.as_bytes()),
+            RespDataType::BinaryBulkString(value) => RespEncoder::encode_bulk_string(&value),
+            RespDataType::NullBulkString => b"$-1\r\n".to_vec(),
             RespDataType::Array(data_types) => {
-                let size = data_types.len();
-                let mut encoded_value = format!("*{}\r\n", size);
+                let mut encoded_value = format!("*{}\r\n", data_types.len()).into_bytes();
 
                 for dt in data_types {
-                    encoded_value = format!("{}{}", encoded_value, RespEncoder::encode(dt));
+                    encoded_value.extend(RespEncoder::encode(dt));
                 }
 
                 encoded_value
             }
         }
     }
+
+    fn encode_bulk_string(value: &[u8]) -> Vec<u8> {
+        let mut encoded_value = format!("${}\r\n", value.len()).into_bytes();
+
+        encoded_value.extend_from_slice(value);
+        encoded_value.extend_from_slice(b"\r\n");
+
+        encoded_value
+    }
 }
@@ -12,22 +12,35 @@ impl<'a> RespReader<'a> {
         Self { stream }
     }
 
+    /// Reads the next value. Bulk strings are read by their length, so they can be binary and they can take more
+    /// than one read.
     pub async fn read(&mut self) -> Result<Option<RespDataType>, ServerError> {
-        let mut reader = TcpStreamReader::new(self.stream);
-        let message = reader
-            .read()
-            .await
-            .expect("Error when reading from TCPStream");
-
-        if message.is_empty() {
-            return Ok(None);
+        let mut message = vec![];
+
+        loop {
+            let mut reader = TcpStreamReader::new(self.stream);
+            let buf = reader
+                .read()
+                .await
+                .map_err(|err| ServerError::TcpReader(err.to_string()))?;
+
+            if buf.is_empty() {
+                return match message.is_empty() {
+                    true => Ok(None),
+                    false => Err(ServerError::TcpReader(
+                        "The connection was closed in the middle of a value".to_string(),
+                    )),
+                };
+            }
+
+            message.extend(buf);
+
+            let resp_data_type = RespDecoder::decode_bytes(&message)
+                .map_err(|err| ServerError::TcpReader(err.to_string()))?;
+
+            if let Some((resp_data_type, _)) = resp_data_type {
+                return Ok(Some(resp_data_type));
+            }
         }
-
-        let mut lines = message.lines();
-
-        let resp_data_type =
-            RespDecoder::decode(&mut lines).expect("Error when decoding string to RESP");
-
-        Ok(Some(resp_data_type))
     }
 }
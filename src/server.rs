@@ -1,24 +1,53 @@
 use std::net::SocketAddr;
+use std::time::Duration;
 use std::{
     error::Error,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::{Arc, Mutex},
+    sync::Arc,
 };
 use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
 
+use crate::aof::load::AofLoader;
+use crate::aof::writer::{AofWriter, AppendFsync};
+use crate::clock::Clock;
+use crate::connections::migrate::MigrateConnections;
 use crate::connections::replica::ReplicaConnection;
 use crate::resp::reader::RespReader;
-use crate::store::Store;
-use crate::{commands::CommandWriter, rdb::sync::RdbSync};
+use crate::store::{MaxMemoryPolicy, Store};
+use crate::{
+    commands::CommandWriter,
+    rdb::{
+        save::{RdbSaver, SavePoint},
+        sync::{RdbLoadMode, RdbSync, RdbSyncStats},
+    },
+};
+
+/// How often the keys that have already expired are removed from the store.
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+/// Maximum number of expired keys removed from every store shard on each active expire cycle.
+const ACTIVE_EXPIRE_KEYS_PER_SHARD: usize = 200;
+/// How often the save rules are checked.
+const SAVE_POINTS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the AOF is flushed to disk with the `everysec` policy.
+const AOF_FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the automatic AOF rewrite rules are checked.
+const AOF_REWRITE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_APPENDFILENAME: &str = "appendonly.aof";
+const DEFAULT_APPENDDIRNAME: &str = "appendonlydir";
+const DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE: u64 = 100;
+const DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE: usize = 64 * 1024 * 1024;
 
 #[derive(Debug)]
 pub enum ServerError {
     TcpListener(String),
     TcpReader(String),
     InvalidPath(String),
+    InvalidConfig(String),
     InvalidCommand(String),
     RdbSync(String),
+    Aof(String),
 }
 
 impl std::fmt::Display for ServerError {
@@ -26,9 +55,11 @@ impl std::fmt::Display for ServerError {
         match self {
             ServerError::TcpListener(err) => write!(f, "TcpListener Error: {}", err),
             ServerError::InvalidPath(err) => write!(f, "InvalidPath Error: {}", err),
+            ServerError::InvalidConfig(err) => write!(f, "InvalidConfig Error: {}", err),
             ServerError::TcpReader(err) => write!(f, "TcpReader Error: {}", err),
             ServerError::InvalidCommand(err) => write!(f, "InvalidCommand Error: {}", err),
             ServerError::RdbSync(err) => write!(f, "RdbSync Error: {}", err),
+            ServerError::Aof(err) => write!(f, "Aof Error: {}", err),
         }
     }
 }
@@ -56,10 +87,36 @@ pub struct ServerConfig {
     pub dir: Option<PathBuf>,
     /// The name of the RDB file (example: rdbfile)
     pub dbfilename: Option<PathBuf>,
+    /// The maximum amount of memory (in bytes) that the store can use. 0 means that there is no limit.
+    pub maxmemory: usize,
+    /// How the store frees memory when `maxmemory` is reached.
+    pub maxmemory_policy: MaxMemoryPolicy,
+    /// The rules that trigger an automatic background save. Empty means that the store is only saved on demand.
+    pub save: Vec<SavePoint>,
+    /// Number of previous RDB files kept next to `dbfilename`, named after the time they were saved. 0 keeps none.
+    pub rdb_retention: usize,
+    /// Whether write commands are appended to the AOF, which is loaded instead of the RDB file at startup.
+    pub appendonly: bool,
+    /// The name of the AOF, used as the prefix of its files (example: appendonly.aof)
+    pub appendfilename: PathBuf,
+    /// The directory, inside `dir`, where the files of the AOF are stored (example: appendonlydir)
+    pub appenddirname: PathBuf,
+    /// When the AOF is flushed to disk.
+    pub appendfsync: AppendFsync,
+    /// Whether an incomplete command at the end of the AOF is dropped when loading it, instead of failing.
+    pub aof_load_truncated: bool,
+    /// How much the AOF has to grow since the last rewrite to be rewritten again, as a percentage. 0 disables the
+    /// automatic rewrites.
+    pub auto_aof_rewrite_percentage: u64,
+    /// The minimum size (in bytes) of the AOF to be rewritten automatically.
+    pub auto_aof_rewrite_min_size: usize,
+    /// Whether DEBUG ADVANCE-TIME can move the clock of the store, which changes the time seen by every client. It
+    /// is only meant for tests.
+    pub debug_advance_time: bool,
 }
 
 impl ServerConfig {
-    fn get_rdb_path(&self) -> Option<PathBuf> {
+    pub fn get_rdb_path(&self) -> Option<PathBuf> {
         let mut rdb_path = PathBuf::new();
         let dir = self.dir.clone()?;
         let dbfilename = self.dbfilename.clone()?;
@@ -69,6 +126,24 @@ impl ServerConfig {
 
         Some(rdb_path)
     }
+
+    /// The AOF is stored in the working directory when `dir` is not set.
+    pub fn get_aof_dir(&self) -> PathBuf {
+        let mut aof_dir = self.dir.clone().unwrap_or_default();
+
+        aof_dir.push(&self.appenddirname);
+
+        aof_dir
+    }
+
+    /// The AOF written as a single file, before the multi-part layout. It is moved to the AOF directory when found.
+    pub fn get_legacy_aof_path(&self) -> PathBuf {
+        let mut aof_path = self.dir.clone().unwrap_or_default();
+
+        aof_path.push(&self.appendfilename);
+
+        aof_path
+    }
 }
 
 #[derive(Debug)]
@@ -77,29 +152,48 @@ pub struct ServerInfo {
     pub role: ServerRole,
     pub id: String,
     pub offset: u32,
+    /// The connections to other servers opened by MIGRATE.
+    pub migrate_connections: MigrateConnections,
 }
 
 #[derive(Debug)]
 pub struct Server {
-    store: Arc<Mutex<Store>>,
+    store: Arc<Store>,
+    rdb_sync: Arc<RdbSync>,
     config: ServerConfig,
     info: ServerInfo,
 }
 
 impl Server {
     pub fn new(address: SocketAddr, role: ServerRole) -> Self {
+        let store = Arc::new(Store::default());
+
         Self {
+            rdb_sync: new_rdb_sync(store.clone(), role),
             config: ServerConfig {
                 dir: None,
                 dbfilename: None,
+                maxmemory: 0,
+                maxmemory_policy: MaxMemoryPolicy::default(),
+                save: vec![],
+                rdb_retention: 0,
+                appendonly: false,
+                appendfilename: PathBuf::from(DEFAULT_APPENDFILENAME),
+                appenddirname: PathBuf::from(DEFAULT_APPENDDIRNAME),
+                appendfsync: AppendFsync::default(),
+                aof_load_truncated: true,
+                auto_aof_rewrite_percentage: DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE,
+                auto_aof_rewrite_min_size: DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE,
+                debug_advance_time: false,
             },
             info: ServerInfo {
                 address,
                 role,
                 id: String::from("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"),
                 offset: 0,
+                migrate_connections: MigrateConnections::new(),
             },
-            store: Arc::new(Mutex::new(Store::default())),
+            store,
         }
     }
 
@@ -121,20 +215,343 @@ impl Server {
         Ok(())
     }
 
+    /// Replaces the clock used for expirations and access times. It must be called before loading any data, since
+    /// it starts with an empty store.
+    pub fn with_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.store = Arc::new(Store::with_clock(clock));
+        self.rdb_sync = new_rdb_sync(self.store.clone(), self.info.role);
+    }
+
+    /// Sets the memory limit. It accepts the same units as Redis (example: 100mb, 1gb, 512k).
+    pub fn with_maxmemory(&mut self, maxmemory: &str) -> Result<(), ServerError> {
+        self.config.maxmemory = parse_memory(maxmemory).ok_or(ServerError::InvalidConfig(
+            format!("Invalid maxmemory value {}", maxmemory),
+        ))?;
+
+        Ok(())
+    }
+
+    pub fn with_maxmemory_policy(&mut self, maxmemory_policy: &str) -> Result<(), ServerError> {
+        self.config.maxmemory_policy =
+            MaxMemoryPolicy::from_str(maxmemory_policy).map_err(ServerError::InvalidConfig)?;
+
+        Ok(())
+    }
+
+    /// Sets the automatic save rules (example: "3600 1 300 100"). An empty string disables them.
+    pub fn with_save(&mut self, save: &str) -> Result<(), ServerError> {
+        self.config.save = SavePoint::parse_all(save).map_err(ServerError::InvalidConfig)?;
+
+        Ok(())
+    }
+
+    pub fn with_rdb_retention(&mut self, rdb_retention: &str) -> Result<(), ServerError> {
+        self.config.rdb_retention = rdb_retention.parse().map_err(|_| {
+            ServerError::InvalidConfig(format!("Invalid rdb-retention value {}", rdb_retention))
+        })?;
+
+        Ok(())
+    }
+
+    pub fn with_appendonly(&mut self, appendonly: &str) -> Result<(), ServerError> {
+        self.config.appendonly = match appendonly.to_lowercase().as_str() {
+            "yes" => true,
+            "no" => false,
+            _ => {
+                return Err(ServerError::InvalidConfig(format!(
+                    "Invalid appendonly value {}",
+                    appendonly
+                )))
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn with_appendfilename(&mut self, appendfilename: &str) -> Result<(), ServerError> {
+        let path = PathBuf::from_str(appendfilename)
+            .map_err(|_| ServerError::InvalidPath(String::from("Invalid AOF file name")))?;
+
+        self.config.appendfilename = path;
+
+        Ok(())
+    }
+
+    pub fn with_appenddirname(&mut self, appenddirname: &str) -> Result<(), ServerError> {
+        let path = PathBuf::from_str(appenddirname)
+            .map_err(|_| ServerError::InvalidPath(String::from("Invalid AOF directory name")))?;
+
+        self.config.appenddirname = path;
+
+        Ok(())
+    }
+
+    pub fn with_appendfsync(&mut self, appendfsync: &str) -> Result<(), ServerError> {
+        self.config.appendfsync =
+            AppendFsync::from_str(appendfsync).map_err(ServerError::InvalidConfig)?;
+
+        Ok(())
+    }
+
+    pub fn with_aof_load_truncated(&mut self, aof_load_truncated: &str) -> Result<(), ServerError> {
+        self.config.aof_load_truncated = match aof_load_truncated.to_lowercase().as_str() {
+            "yes" => true,
+            "no" => false,
+            _ => {
+                return Err(ServerError::InvalidConfig(format!(
+                    "Invalid aof-load-truncated value {}",
+                    aof_load_truncated
+                )))
+            }
+        };
+
+        Ok(())
+    }
+
+    pub fn with_auto_aof_rewrite_percentage(
+        &mut self,
+        percentage: &str,
+    ) -> Result<(), ServerError> {
+        self.config.auto_aof_rewrite_percentage = percentage.parse().map_err(|_| {
+            ServerError::InvalidConfig(format!(
+                "Invalid auto-aof-rewrite-percentage value {}",
+                percentage
+            ))
+        })?;
+
+        Ok(())
+    }
+
+    /// Sets the minimum size of the AOF to be rewritten automatically. It accepts the same units as `maxmemory`.
+    pub fn with_auto_aof_rewrite_min_size(&mut self, min_size: &str) -> Result<(), ServerError> {
+        self.config.auto_aof_rewrite_min_size =
+            parse_memory(min_size).ok_or(ServerError::InvalidConfig(format!(
+                "Invalid auto-aof-rewrite-min-size value {}",
+                min_size
+            )))?;
+
+        Ok(())
+    }
+
+    pub fn with_debug_advance_time(&mut self, debug_advance_time: &str) -> Result<(), ServerError> {
+        self.config.debug_advance_time = match debug_advance_time.to_lowercase().as_str() {
+            "yes" => true,
+            "no" => false,
+            _ => {
+                return Err(ServerError::InvalidConfig(format!(
+                    "Invalid debug-advance-time value {}",
+                    debug_advance_time
+                )))
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Replaces the whole dataset with the keys of an RDB file (or adds them to it, with `RdbLoadMode::Merge`). The
+    /// file is decoded before touching the store, so commands see either the previous keys or the new ones, and the
+    /// previous keys are kept when the file can not be loaded. When the AOF is enabled, it is rewritten with the new
+    /// dataset.
+    ///
+    /// It can be called before `listen`, or while the server is running through the handle returned by `rdb_sync`
+    /// (DEBUG RELOAD and DEBUG LOADRDB use the same path).
+    pub async fn load_rdb(
+        &self,
+        rdb_path: &Path,
+        mode: RdbLoadMode,
+    ) -> Result<RdbSyncStats, ServerError> {
+        self.rdb_sync
+            .load(rdb_path.to_path_buf(), mode)
+            .await
+            .map_err(|err| ServerError::RdbSync(err.to_string()))
+    }
+
+    /// The loader of RDB files into the store of this server, which can be kept to load files once `listen` takes the
+    /// server.
+    pub fn rdb_sync(&self) -> Arc<RdbSync> {
+        self.rdb_sync.clone()
+    }
+
     pub async fn listen(self) -> Result<(), ServerError> {
         let listener = TcpListener::bind(self.info.address).await.map_err(|_| {
             ServerError::TcpListener("Connection could not be established".to_string())
         })?;
         let config = Arc::new(self.config);
         let info = Arc::new(self.info);
+        let mut rdb_saver = RdbSaver::new(self.store.clock().now());
 
-        if let Some(rdb_path) = config.get_rdb_path() {
-            let mut rdb_sync = RdbSync::new(self.store.clone());
+        rdb_saver.with_retention(config.rdb_retention);
 
-            rdb_sync
-                .sync(rdb_path)
-                .await
-                .map_err(|err| ServerError::RdbSync(err.to_string()))?;
+        let rdb_saver = Arc::new(rdb_saver);
+        let rdb_sync = self.rdb_sync.clone();
+
+        let aof_writer = Arc::new(AofWriter::new(
+            config.get_aof_dir(),
+            &config.appendfilename.to_string_lossy(),
+            config.appendfsync,
+        ));
+
+        rdb_sync.set_aof_writer(aof_writer.clone());
+
+        // The dataset is loaded in the background, so clients can connect (and follow the progress) meanwhile.
+        // Commands that need the dataset are rejected until it is loaded.
+        //
+        // The AOF has the latest writes, so it is loaded instead of the RDB file when it is enabled.
+        //
+        // A dataset that can not be loaded stops the server, since starting with an empty dataset could overwrite the
+        // files with the next save.
+        let mut loading: Option<JoinHandle<Result<(), ServerError>>> = None;
+
+        if config.appendonly {
+            let store_cloned = self.store.clone();
+            let config_cloned = config.clone();
+            let info_cloned = info.clone();
+            let rdb_saver_cloned = rdb_saver.clone();
+            let rdb_sync_cloned = rdb_sync.clone();
+            let aof_writer_cloned = aof_writer.clone();
+
+            rdb_sync.start_loading();
+
+            loading = Some(tokio::spawn(async move {
+                load_aof_files(
+                    store_cloned.clone(),
+                    config_cloned.clone(),
+                    info_cloned,
+                    rdb_saver_cloned,
+                    rdb_sync_cloned.clone(),
+                    aof_writer_cloned.clone(),
+                )
+                .await?;
+
+                // The AOF is opened before accepting writes, so none of them is missing from it.
+                aof_writer_cloned
+                    .open()
+                    .map_err(|err| ServerError::Aof(err.to_string()))?;
+
+                rdb_sync_cloned.finish_loading();
+
+                store_cloned
+                    .set_max_memory(config_cloned.maxmemory, config_cloned.maxmemory_policy);
+
+                Ok(())
+            }));
+        } else {
+            match config.get_rdb_path() {
+                Some(rdb_path) => {
+                    let store_cloned = self.store.clone();
+                    let config_cloned = config.clone();
+                    let rdb_sync_cloned = rdb_sync.clone();
+
+                    rdb_sync.start_loading();
+
+                    loading = Some(tokio::spawn(async move {
+                        // The number of keys loaded, expired and skipped is reported by INFO persistence.
+                        rdb_sync_cloned
+                            .sync(rdb_path)
+                            .await
+                            .map_err(|err| ServerError::RdbSync(err.to_string()))?;
+
+                        // The memory limit is applied after loading the RDB file, so the whole dataset is loaded even
+                        // when it does not fit. Keys are evicted on the following writes.
+                        store_cloned.set_max_memory(
+                            config_cloned.maxmemory,
+                            config_cloned.maxmemory_policy,
+                        );
+
+                        Ok(())
+                    }));
+                }
+                None => self
+                    .store
+                    .set_max_memory(config.maxmemory, config.maxmemory_policy),
+            }
+        }
+
+        if config.appendonly && config.appendfsync == AppendFsync::Everysec {
+            let aof_writer_cloned = aof_writer.clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(AOF_FSYNC_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    // A failed fsync is reported by INFO persistence (aof_last_write_status).
+                    let _ = aof_writer_cloned.fsync();
+                }
+            });
+        }
+
+        if config.appendonly {
+            let store_cloned = self.store.clone();
+            let config_cloned = config.clone();
+            let aof_writer_cloned = aof_writer.clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(AOF_REWRITE_CHECK_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    let now = store_cloned.clock().now();
+
+                    // The AOF is not open until it is loaded, so it is never rewritten meanwhile.
+                    if aof_writer_cloned.should_rewrite(
+                        config_cloned.auto_aof_rewrite_percentage,
+                        config_cloned.auto_aof_rewrite_min_size as u64,
+                        now,
+                    ) {
+                        // A rewrite started by BGREWRITEAOF in the meantime is not an error.
+                        let _ = aof_writer_cloned.background_rewrite(store_cloned.clone());
+                    }
+                }
+            });
+        }
+
+        // Replicas do not expire keys by themselves. They wait for the master to delete them, so the dataset of
+        // both is always the same.
+        if let ServerRole::Master = info.role {
+            let store_cloned = self.store.clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    store_cloned.remove_expired(ACTIVE_EXPIRE_KEYS_PER_SHARD);
+                }
+            });
+        }
+
+        if let Some(rdb_path) = config.get_rdb_path().filter(|_| !config.save.is_empty()) {
+            let store_cloned = self.store.clone();
+            let config_cloned = config.clone();
+            let rdb_saver_cloned = rdb_saver.clone();
+            let rdb_sync_cloned = rdb_sync.clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(SAVE_POINTS_CHECK_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+
+                    // Saving a dataset that is partially loaded would overwrite the file being loaded.
+                    if rdb_sync_cloned.is_loading() {
+                        continue;
+                    }
+
+                    let now = store_cloned.clock().now();
+
+                    if rdb_saver_cloned.should_save(&config_cloned.save, store_cloned.dirty(), now)
+                    {
+                        // A save started by BGSAVE in the meantime is not an error, the rules are checked again
+                        // on the next tick.
+                        let _ = rdb_saver_cloned
+                            .background_save(store_cloned.clone(), rdb_path.clone());
+                    }
+                }
+            });
         }
 
         if let ServerRole::Slave(master_addr) = info.role {
@@ -148,13 +565,23 @@ impl Server {
         }
 
         loop {
-            let (mut socket, _) = listener.accept().await.map_err(|_| {
-                ServerError::TcpListener("Connection with could not be established".to_string())
-            })?;
+            let (mut socket, _) = tokio::select! {
+                accepted = listener.accept() => accepted.map_err(|_| {
+                    ServerError::TcpListener("Connection with could not be established".to_string())
+                })?,
+                result = wait_for_loading(&mut loading) => {
+                    result?;
+
+                    continue;
+                }
+            };
 
             let store_cloned = self.store.clone();
             let config_cloned = config.clone();
             let info_cloned = info.clone();
+            let rdb_saver_cloned = rdb_saver.clone();
+            let rdb_sync_cloned = rdb_sync.clone();
+            let aof_writer_cloned = aof_writer.clone();
 
             tokio::spawn(async move {
                 loop {
@@ -176,6 +603,9 @@ impl Server {
                             store_cloned.clone(),
                             config_cloned.clone(),
                             info_cloned.clone(),
+                            rdb_saver_cloned.clone(),
+                            rdb_sync_cloned.clone(),
+                            aof_writer_cloned.clone(),
                         )
                         .await
                         .expect("Invalid command")
@@ -184,3 +614,157 @@ impl Server {
         }
     }
 }
+
+// Waits for the dataset loaded in the background, returning its error. It never returns once the dataset is loaded,
+// or when there is nothing to load.
+async fn wait_for_loading(
+    loading: &mut Option<JoinHandle<Result<(), ServerError>>>,
+) -> Result<(), ServerError> {
+    match loading {
+        Some(task) => {
+            let result = task.await.expect("The dataset loading task panicked");
+
+            *loading = None;
+
+            result
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// Replicas keep expired keys until the master deletes them, so they are loaded too.
+fn new_rdb_sync(store: Arc<Store>, role: ServerRole) -> Arc<RdbSync> {
+    let mut rdb_sync = RdbSync::new(store);
+
+    rdb_sync.with_keep_expired(matches!(role, ServerRole::Slave(_)));
+
+    Arc::new(rdb_sync)
+}
+
+// Loads the files of the AOF in the order of its manifest. The base can be an RDB file or a list of commands, and the
+// incremental files are always commands.
+async fn load_aof_files(
+    store: Arc<Store>,
+    config: Arc<ServerConfig>,
+    info: Arc<ServerInfo>,
+    rdb_saver: Arc<RdbSaver>,
+    rdb_sync: Arc<RdbSync>,
+    aof_writer: Arc<AofWriter>,
+) -> Result<(), ServerError> {
+    let files = aof_writer
+        .load_manifest(&config.get_legacy_aof_path())
+        .map_err(|err| ServerError::Aof(err.to_string()))?;
+
+    let last_file = files.len().saturating_sub(1);
+
+    for (index, file) in files.into_iter().enumerate() {
+        let path = aof_writer.file_path(&file);
+
+        // Starting without the commands of a file would lose them on the next rewrite.
+        if !path.exists() {
+            return Err(ServerError::Aof(format!(
+                "File {} of the manifest does not exist",
+                file.name
+            )));
+        }
+
+        if file.is_rdb() {
+            rdb_sync
+                .load(path, RdbLoadMode::Merge)
+                .await
+                .map_err(|err| ServerError::RdbSync(err.to_string()))?;
+        } else {
+            let mut loader = AofLoader::open(&path)
+                .await
+                .map_err(|err| ServerError::Aof(err.to_string()))?
+                .ok_or(ServerError::Aof(format!(
+                    "File {} of the manifest does not exist",
+                    file.name
+                )))?;
+
+            // Only the last file can be cut by a crash, since the others are not appended anymore.
+            loader.with_load_truncated(config.aof_load_truncated && index == last_file);
+
+            load_aof(
+                &mut loader,
+                store.clone(),
+                config.clone(),
+                info.clone(),
+                rdb_saver.clone(),
+                rdb_sync.clone(),
+                aof_writer.clone(),
+            )
+            .await?;
+
+            // The incomplete command is removed, so the next commands are appended after a complete one. It is
+            // reported by INFO persistence (aof_last_load_truncated).
+            if loader.is_truncated() {
+                aof_writer.set_load_truncated(true);
+
+                loader
+                    .truncate()
+                    .map_err(|err| ServerError::Aof(err.to_string()))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Replays the commands of the AOF through the normal command path, reporting the progress as a dataset loading.
+async fn load_aof(
+    loader: &mut AofLoader,
+    store: Arc<Store>,
+    config: Arc<ServerConfig>,
+    info: Arc<ServerInfo>,
+    rdb_saver: Arc<RdbSaver>,
+    rdb_sync: Arc<RdbSync>,
+    aof_writer: Arc<AofWriter>,
+) -> Result<(), ServerError> {
+    let mut replies = tokio::io::sink();
+
+    while let Some(args) = loader
+        .next_command()
+        .map_err(|err| ServerError::Aof(err.to_string()))?
+    {
+        CommandWriter::from_aof(args, &mut replies)
+            .write(
+                store.clone(),
+                config.clone(),
+                info.clone(),
+                rdb_saver.clone(),
+                rdb_sync.clone(),
+                aof_writer.clone(),
+            )
+            .await
+            .map_err(|err| ServerError::Aof(err.to_string()))?;
+
+        rdb_sync.set_loading_progress(loader.len() as u64, loader.position() as u64);
+    }
+
+    Ok(())
+}
+
+// Parses a memory amount the same way Redis does: a number optionally followed by a unit (b, k, kb, m, mb, g, gb).
+// Units without the "b" suffix are multiples of 1000 and the ones with it are multiples of 1024.
+fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number: usize = number.parse().ok()?;
+
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    number.checked_mul(multiplier)
+}
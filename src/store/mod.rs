@@ -0,0 +1,1208 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::clock::{Clock, SystemClock};
+use shard::{entry_size, EvictionCandidate, ShardData, StoreShard};
+pub use value::{
+    StoreData, StoreModuleField, StoreModuleValue, StoreStream, StoreStreamConsumer,
+    StoreStreamGroup, StoreStreamPendingEntry, StreamId,
+};
+
+mod shard;
+mod value;
+
+/// Approximate amount of memory that the store needs for every key besides the key and the value themselves
+/// (hash table slot, entry metadata, allocation headers...).
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Number of keys that are sampled every time that the store needs to choose a key to evict.
+const MAX_MEMORY_SAMPLES: usize = 5;
+
+/// Number of independently locked shards of the keyspace. It is a lot higher than the number of cores of the hosts
+/// where the server runs, so two connections rarely wait for the same lock.
+const DEFAULT_SHARDS: usize = 256;
+
+#[derive(Debug)]
+pub enum StoreError {
+    OutOfMemory,
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::OutOfMemory => {
+                write!(f, "OOM command not allowed when used memory > 'maxmemory'.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// The policy used by the store to free memory when the `maxmemory` limit is reached.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MaxMemoryPolicy {
+    /// New values are not saved when the memory limit is reached.
+    #[default]
+    NoEviction,
+    /// Keeps most recently used keys; removes least recently used (LRU) keys.
+    AllKeysLru,
+    /// Keeps frequently used keys; removes least frequently used (LFU) keys.
+    AllKeysLfu,
+    /// Randomly removes keys.
+    AllKeysRandom,
+    /// Removes least recently used keys with an expiration.
+    VolatileLru,
+    /// Removes least frequently used keys with an expiration.
+    VolatileLfu,
+    /// Randomly removes keys with an expiration.
+    VolatileRandom,
+    /// Removes keys with an expiration, the ones with the shortest remaining time-to-live first.
+    VolatileTtl,
+}
+
+impl MaxMemoryPolicy {
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            MaxMemoryPolicy::VolatileLru
+                | MaxMemoryPolicy::VolatileLfu
+                | MaxMemoryPolicy::VolatileRandom
+                | MaxMemoryPolicy::VolatileTtl
+        )
+    }
+}
+
+impl FromStr for MaxMemoryPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "noeviction" => Ok(MaxMemoryPolicy::NoEviction),
+            "allkeys-lru" => Ok(MaxMemoryPolicy::AllKeysLru),
+            "allkeys-lfu" => Ok(MaxMemoryPolicy::AllKeysLfu),
+            "allkeys-random" => Ok(MaxMemoryPolicy::AllKeysRandom),
+            "volatile-lru" => Ok(MaxMemoryPolicy::VolatileLru),
+            "volatile-lfu" => Ok(MaxMemoryPolicy::VolatileLfu),
+            "volatile-random" => Ok(MaxMemoryPolicy::VolatileRandom),
+            "volatile-ttl" => Ok(MaxMemoryPolicy::VolatileTtl),
+            value => Err(format!("Max memory policy {} is not valid", value)),
+        }
+    }
+}
+
+impl std::fmt::Display for MaxMemoryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaxMemoryPolicy::NoEviction => write!(f, "noeviction"),
+            MaxMemoryPolicy::AllKeysLru => write!(f, "allkeys-lru"),
+            MaxMemoryPolicy::AllKeysLfu => write!(f, "allkeys-lfu"),
+            MaxMemoryPolicy::AllKeysRandom => write!(f, "allkeys-random"),
+            MaxMemoryPolicy::VolatileLru => write!(f, "volatile-lru"),
+            MaxMemoryPolicy::VolatileLfu => write!(f, "volatile-lfu"),
+            MaxMemoryPolicy::VolatileRandom => write!(f, "volatile-random"),
+            MaxMemoryPolicy::VolatileTtl => write!(f, "volatile-ttl"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct StoreValue {
+    pub value: StoreData,
+    pub exp: Option<DateTime<Utc>>,
+}
+
+impl StoreValue {
+    /// Approximate number of bytes used by the value.
+    pub fn memory_usage(&self) -> usize {
+        let exp_size = match self.exp {
+            Some(_) => std::mem::size_of::<DateTime<Utc>>(),
+            None => 0,
+        };
+
+        self.value.memory_usage() + exp_size
+    }
+
+    /// The internal representation that Redis would use for the value.
+    pub fn encoding(&self) -> &'static str {
+        self.value.encoding()
+    }
+}
+
+/// Access and memory information that the store keeps for every key.
+#[derive(Debug)]
+pub struct StoreKeyMetadata {
+    /// Time since the key was last read or written.
+    pub idle_time: Duration,
+    /// Logarithmic access frequency counter used by the LFU eviction policies.
+    pub lfu_counter: u8,
+    /// Approximate number of bytes used by the key, its value and the store bookkeeping.
+    pub memory_usage: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct StoreValueBuilder {
+    pub value: Option<StoreData>,
+    pub exp: Option<DateTime<Utc>>,
+}
+
+impl StoreValueBuilder {
+    pub fn new() -> Self {
+        StoreValueBuilder {
+            value: None,
+            exp: None,
+        }
+    }
+
+    pub fn with_exp(&mut self, exp: DateTime<Utc>) {
+        self.exp = Some(exp);
+    }
+
+    pub fn with_value(&mut self, value: &str) {
+        self.value = Some(StoreData::String(value.to_string()));
+    }
+
+    pub fn with_data(&mut self, data: StoreData) {
+        self.value = Some(data);
+    }
+
+    pub fn build(self) -> StoreValue {
+        StoreValue {
+            value: self.value.unwrap(),
+            exp: self.exp,
+        }
+    }
+}
+
+// Memory counters shared by all the shards of a store.
+#[derive(Debug, Default)]
+struct StoreCounters {
+    used_memory: AtomicUsize,
+    peak_memory: AtomicUsize,
+    evicted_keys: AtomicU64,
+    expired_keys: AtomicU64,
+    // Number of changes since the last time the dataset was saved.
+    dirty: AtomicU64,
+    // Memory used by entries that only snapshots are using, because they changed after the snapshot was taken.
+    snapshot_memory: AtomicUsize,
+}
+
+impl StoreCounters {
+    fn add_used_memory(&self, size: usize) {
+        let used_memory = self.used_memory.fetch_add(size, Ordering::Relaxed) + size;
+
+        self.peak_memory.fetch_max(used_memory, Ordering::Relaxed);
+    }
+
+    fn sub_used_memory(&self, size: usize) {
+        self.used_memory.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn add_dirty(&self) {
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The keyspace, split in shards that are locked independently. Every key always belongs to the same shard, so
+/// commands that work with a single key only lock one shard and connections that use different keys do not block
+/// each other.
+#[derive(Debug)]
+pub struct Store {
+    shards: Vec<Mutex<StoreShard>>,
+    hasher: RandomState,
+    counters: Arc<StoreCounters>,
+    max_memory: AtomicUsize,
+    max_memory_policy: Mutex<MaxMemoryPolicy>,
+    eviction_cursor: AtomicUsize,
+    active_expire: AtomicBool,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock::default()))
+    }
+}
+
+impl Store {
+    pub fn new(shards_count: usize, clock: Arc<dyn Clock>) -> Self {
+        let counters = Arc::new(StoreCounters::default());
+        let shards = (0..shards_count.max(1))
+            .map(|_| Mutex::new(StoreShard::new(counters.clone())))
+            .collect();
+
+        Self {
+            shards,
+            hasher: RandomState::new(),
+            counters,
+            max_memory: AtomicUsize::new(0),
+            max_memory_policy: Mutex::new(MaxMemoryPolicy::default()),
+            eviction_cursor: AtomicUsize::new(0),
+            active_expire: AtomicBool::new(true),
+            clock,
+        }
+    }
+
+    /// Creates a store with the default number of shards that uses `clock` for telling the time.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self::new(DEFAULT_SHARDS, clock)
+    }
+
+    /// Limits the memory used by the store. A `max_memory` of 0 means that there is no limit.
+    pub fn set_max_memory(&self, max_memory: usize, policy: MaxMemoryPolicy) {
+        *lock(&self.max_memory_policy) = policy;
+        self.max_memory.store(max_memory, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, key: &str, value: StoreValue) -> Result<(), StoreError> {
+        self.evict(&[key], entry_size(key, &value))?;
+
+        self.lock_shard(key).set(key, value, self.clock.now());
+
+        Ok(())
+    }
+
+    /// Sets several keys at once. No other command sees only part of the keys written, and no key is written when
+    /// they do not fit in memory.
+    pub fn mset(&self, values: Vec<(String, StoreValue)>) -> Result<(), StoreError> {
+        let keys: Vec<&str> = values.iter().map(|(key, _)| key.as_str()).collect();
+        let size = values
+            .iter()
+            .map(|(key, value)| entry_size(key, value))
+            .sum();
+
+        self.evict(&keys, size)?;
+
+        let mut guard = self.lock_keys(&keys);
+
+        for (key, value) in values {
+            guard.set(&key, value);
+        }
+
+        Ok(())
+    }
+
+    /// Sets a key moved from another server, along with its idle time and LFU counter. Unless `replace` is set,
+    /// nothing is written when the key already exists, and it returns false.
+    ///
+    /// The key is checked and written while its shard is locked, so no other command writes it in between.
+    pub fn restore(
+        &self,
+        key: &str,
+        value: StoreValue,
+        replace: bool,
+        idle_time: Option<Duration>,
+        lfu_counter: Option<u8>,
+    ) -> Result<bool, StoreError> {
+        self.evict(&[key], entry_size(key, &value))?;
+
+        let mut guard = self.lock_keys(&[key]);
+
+        if !replace && guard.peek(key).is_some() {
+            return Ok(false);
+        }
+
+        guard.set(key, value);
+        guard.set_access_metadata(key, idle_time, lfu_counter);
+
+        Ok(true)
+    }
+
+    /// Replaces the whole keyspace with `values`. All the shards are locked while they are replaced, so commands see
+    /// either the previous keys or the new ones, never a mix of both. Snapshots taken before keep the previous keys.
+    ///
+    /// No key is evicted, the same as when loading the dataset at startup. Keys are evicted on the following writes
+    /// when the new keys do not fit.
+    pub fn replace_all(&self, values: Vec<(String, StoreValue)>) {
+        let now = self.clock.now();
+        let mut guards: Vec<MutexGuard<'_, StoreShard>> = self.shards.iter().map(lock).collect();
+
+        for guard in guards.iter_mut() {
+            guard.clear();
+        }
+
+        for (key, value) in values {
+            guards[self.shard_index(&key)].set(&key, value, now);
+        }
+    }
+
+    /// Moves the value (and its expiration) of `key` to `new_key`, overwriting it. It returns false when `key`
+    /// does not exist.
+    pub fn rename(&self, key: &str, new_key: &str) -> bool {
+        let mut guard = self.lock_keys(&[key, new_key]);
+
+        match guard.remove(key) {
+            Some(value) => {
+                guard.set(new_key, value);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sets (or removes, when `exp` is None) the expiration of a key. It returns false when the key does not exist.
+    pub fn set_expiration(&self, key: &str, exp: Option<DateTime<Utc>>) -> bool {
+        self.lock_shard(key)
+            .set_expiration(key, exp, self.clock.now())
+            .is_some()
+    }
+
+    /// Removes the expiration of a key. It returns false when the key does not exist or it has no expiration.
+    pub fn persist(&self, key: &str) -> bool {
+        matches!(
+            self.lock_shard(key)
+                .set_expiration(key, None, self.clock.now()),
+            Some(Some(_))
+        )
+    }
+
+    /// Removes keys that have already expired, going through the keys that expire first. At most `limit_per_shard`
+    /// keys are removed from each shard, so a single call never blocks a shard for long.
+    ///
+    /// It returns the number of keys removed. Nothing is removed while active expiration is disabled.
+    pub fn remove_expired(&self, limit_per_shard: usize) -> usize {
+        if !self.is_active_expire_enabled() {
+            return 0;
+        }
+
+        let now = self.clock.now();
+
+        self.shards
+            .iter()
+            .map(|shard| lock(shard).remove_expired(now, limit_per_shard))
+            .sum()
+    }
+
+    pub fn get(&self, key: &str) -> Option<StoreValue> {
+        self.lock_shard(key).get(key, self.clock.now()).cloned()
+    }
+
+    /// Returns the value without updating its access information.
+    pub fn peek(&self, key: &str) -> Option<StoreValue> {
+        self.lock_shard(key).peek(key, self.clock.now()).cloned()
+    }
+
+    pub fn get_metadata(&self, key: &str) -> Option<StoreKeyMetadata> {
+        self.lock_shard(key).get_metadata(key, self.clock.now())
+    }
+
+    pub fn remove(&self, key: &str) -> Option<StoreValue> {
+        self.lock_shard(key).remove(key, self.clock.now())
+    }
+
+    pub fn get_all_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        let now = self.clock.now();
+
+        for shard in self.shards.iter() {
+            keys.extend(lock(shard).keys(now).cloned());
+        }
+
+        keys
+    }
+
+    /// Takes a point-in-time view of the whole keyspace (without the keys that have already expired).
+    ///
+    /// No key is copied: the snapshot shares the entries of every shard, and a shard only copies its entries when
+    /// it is written while a snapshot is using them. All the shards are locked while they are shared, which is
+    /// quick, so no write is applied to only part of the snapshot.
+    pub fn snapshot(&self) -> StoreSnapshot {
+        let now = self.clock.now();
+        let shards = {
+            let guards: Vec<MutexGuard<'_, StoreShard>> = self.shards.iter().map(lock).collect();
+
+            guards.iter().map(|guard| guard.snapshot()).collect()
+        };
+
+        StoreSnapshot { shards, now }
+    }
+
+    /// Locks the shards of all the given keys, so they can be read and written as a single operation.
+    ///
+    /// Shards are always locked in ascending order, which means that two commands locking several keys at the same
+    /// time can never deadlock.
+    pub fn lock_keys(&self, keys: &[&str]) -> StoreShardsGuard<'_> {
+        let mut indexes: Vec<usize> = keys.iter().map(|key| self.shard_index(key)).collect();
+
+        indexes.sort_unstable();
+        indexes.dedup();
+
+        let guards = indexes
+            .into_iter()
+            .map(|index| (index, lock(&self.shards[index])))
+            .collect();
+
+        StoreShardsGuard {
+            store: self,
+            guards,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.counters.used_memory.load(Ordering::Relaxed)
+    }
+
+    pub fn peak_memory(&self) -> usize {
+        self.counters.peak_memory.load(Ordering::Relaxed)
+    }
+
+    /// Memory used by the store bookkeeping instead of keys and values.
+    pub fn overhead_memory(&self) -> usize {
+        self.len() * ENTRY_OVERHEAD
+    }
+
+    pub fn max_memory(&self) -> usize {
+        self.max_memory.load(Ordering::Relaxed)
+    }
+
+    pub fn max_memory_policy(&self) -> MaxMemoryPolicy {
+        *lock(&self.max_memory_policy)
+    }
+
+    pub fn evicted_keys(&self) -> u64 {
+        self.counters.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    pub fn expired_keys(&self) -> u64 {
+        self.counters.expired_keys.load(Ordering::Relaxed)
+    }
+
+    /// Memory used by the keys that were copied because they changed while a snapshot was using them.
+    pub fn snapshot_memory(&self) -> usize {
+        self.counters.snapshot_memory.load(Ordering::Relaxed)
+    }
+
+    /// Number of changes (writes, deletions, expirations...) since the last save.
+    pub fn dirty(&self) -> u64 {
+        self.counters.dirty.load(Ordering::Relaxed)
+    }
+
+    /// Marks `changes` as saved. It receives the number of changes when the saved snapshot was taken, so the changes
+    /// made while the snapshot was written are still counted.
+    pub fn clear_dirty(&self, changes: u64) {
+        self.counters.dirty.fetch_sub(changes, Ordering::Relaxed);
+    }
+
+    /// The clock used for expirations and access times.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Enables or disables the removal of expired keys in the background. Expired keys are still never returned.
+    pub fn set_active_expire(&self, enabled: bool) {
+        self.active_expire.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_active_expire_enabled(&self) -> bool {
+        self.active_expire.load(Ordering::Relaxed)
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    fn lock_shard(&self, key: &str) -> MutexGuard<'_, StoreShard> {
+        lock(&self.shards[self.shard_index(key)])
+    }
+
+    // Evicts keys until there is enough memory for writing `size` bytes to `keys`.
+    //
+    // It runs before locking the shards of the keys that are going to be written, since evicting requires locking
+    // other shards. The keys that are going to be written are never evicted. When there are no keys left to evict
+    // (or the policy is noeviction), the write is rejected.
+    fn evict(&self, keys: &[&str], size: usize) -> Result<(), StoreError> {
+        let max_memory = self.max_memory();
+
+        if max_memory == 0 {
+            return Ok(());
+        }
+
+        let current_size: usize = keys
+            .iter()
+            .map(|key| self.lock_shard(key).entry_size(key).unwrap_or(0))
+            .sum();
+        let policy = self.max_memory_policy();
+
+        while self.used_memory().saturating_sub(current_size) + size > max_memory {
+            let (index, candidate) = self
+                .find_eviction_candidate(policy, keys)
+                .ok_or(StoreError::OutOfMemory)?;
+
+            if lock(&self.shards[index])
+                .remove(&candidate, self.clock.now())
+                .is_some()
+            {
+                self.counters.evicted_keys.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Samples one key from each of a few shards and returns the best one to evict according to the policy.
+    //
+    // The first shard changes on every call, so keys from the whole keyspace end up being considered.
+    fn find_eviction_candidate(
+        &self,
+        policy: MaxMemoryPolicy,
+        excluded_keys: &[&str],
+    ) -> Option<(usize, String)> {
+        if policy == MaxMemoryPolicy::NoEviction {
+            return None;
+        }
+
+        let start = self.eviction_cursor.fetch_add(1, Ordering::Relaxed);
+        let now = self.clock.now();
+        let mut best: Option<(usize, EvictionCandidate)> = None;
+        let mut samples = 0;
+
+        for offset in 0..self.shards.len() {
+            if samples == MAX_MEMORY_SAMPLES {
+                break;
+            }
+
+            let index = (start + offset) % self.shards.len();
+            let candidate =
+                lock(&self.shards[index]).sample_eviction_candidate(policy, excluded_keys, now);
+
+            if let Some(candidate) = candidate {
+                samples += 1;
+
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best)| candidate.score < best.score)
+                {
+                    best = Some((index, candidate));
+                }
+            }
+        }
+
+        best.map(|(index, candidate)| (index, candidate.key))
+    }
+}
+
+/// A point-in-time view of the keyspace, which can be read without blocking the store.
+#[derive(Debug)]
+pub struct StoreSnapshot {
+    shards: Vec<Arc<ShardData>>,
+    // Keys that expired before this time are not part of the snapshot.
+    now: DateTime<Utc>,
+}
+
+impl StoreSnapshot {
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &StoreValue)> {
+        self.shards.iter().flat_map(|shard| shard.entries(self.now))
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Number of keys with an expiration.
+    pub fn expires_len(&self) -> usize {
+        self.iter().filter(|(_, value)| value.exp.is_some()).count()
+    }
+}
+
+/// The locked shards of a set of keys. The shards are unlocked when the guard is dropped.
+///
+/// Only the keys passed to `Store::lock_keys` can be used with the guard. Writes through the guard do not evict
+/// keys, so callers must make room for them beforehand.
+pub struct StoreShardsGuard<'a> {
+    store: &'a Store,
+    guards: Vec<(usize, MutexGuard<'a, StoreShard>)>,
+}
+
+impl StoreShardsGuard<'_> {
+    pub fn get(&mut self, key: &str) -> Option<&StoreValue> {
+        let now = self.store.clock.now();
+
+        self.shard(key).get(key, now)
+    }
+
+    pub fn peek(&mut self, key: &str) -> Option<&StoreValue> {
+        let now = self.store.clock.now();
+
+        self.shard(key).peek(key, now)
+    }
+
+    pub fn set(&mut self, key: &str, value: StoreValue) {
+        let now = self.store.clock.now();
+
+        self.shard(key).set(key, value, now);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<StoreValue> {
+        let now = self.store.clock.now();
+
+        self.shard(key).remove(key, now)
+    }
+
+    /// Replaces the idle time and the LFU counter of a key, so a key moved from another server keeps its access
+    /// information. It returns false when the key does not exist.
+    pub fn set_access_metadata(
+        &mut self,
+        key: &str,
+        idle_time: Option<Duration>,
+        lfu_counter: Option<u8>,
+    ) -> bool {
+        let now = self.store.clock.now();
+
+        self.shard(key)
+            .set_access_metadata(key, idle_time, lfu_counter, now)
+    }
+
+    fn shard(&mut self, key: &str) -> &mut StoreShard {
+        let index = self.store.shard_index(key);
+
+        self.guards
+            .iter_mut()
+            .find(|(locked_index, _)| *locked_index == index)
+            .map(|(_, guard)| &mut **guard)
+            .expect("The key was not locked by the guard")
+    }
+}
+
+/// Approximate number of bytes that a key and its value would use in the store, including its bookkeeping. It is the
+/// size used for the memory limit.
+pub fn entry_memory_usage(key: &str, value: &StoreValue) -> usize {
+    entry_size(key, value)
+}
+
+// A poisoned shard is still consistent (shards never panic halfway through a change), so it can still be used.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::clock::ManualClock;
+
+    use super::*;
+
+    fn new_store() -> (Store, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        ));
+
+        (Store::with_clock(clock.clone()), clock)
+    }
+
+    fn string_value(value: &str) -> StoreValue {
+        let mut builder = StoreValueBuilder::new();
+
+        builder.with_value(value);
+
+        builder.build()
+    }
+
+    fn expiring_value(value: &str, exp: DateTime<Utc>) -> StoreValue {
+        let mut builder = StoreValueBuilder::new();
+
+        builder.with_value(value);
+        builder.with_exp(exp);
+
+        builder.build()
+    }
+
+    // Keys of the same length that belong to different shards, so every one of them is sampled when evicting.
+    fn keys_in_different_shards(store: &Store, count: usize) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+
+        for key in (0..).map(|index| format!("key:{:04}", index)) {
+            if keys.len() == count {
+                break;
+            }
+
+            if keys
+                .iter()
+                .all(|other| store.shard_index(other) != store.shard_index(&key))
+            {
+                keys.push(key);
+            }
+        }
+
+        keys
+    }
+
+    fn string(store: &Store, key: &str) -> Option<String> {
+        match store.peek(key)?.value {
+            StoreData::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn restore_does_not_replace_an_existing_key() {
+        let (store, _) = new_store();
+
+        store.set("key", string_value("old")).unwrap();
+
+        assert!(!store
+            .restore("key", string_value("new"), false, None, None)
+            .unwrap());
+        assert_eq!(string(&store, "key").as_deref(), Some("old"));
+
+        assert!(store
+            .restore("key", string_value("new"), true, None, None)
+            .unwrap());
+        assert_eq!(string(&store, "key").as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn restore_keeps_the_access_information() {
+        let (store, _) = new_store();
+
+        assert!(store
+            .restore(
+                "key",
+                string_value("value"),
+                false,
+                Some(Duration::seconds(100)),
+                Some(42)
+            )
+            .unwrap());
+
+        let metadata = store.get_metadata("key").unwrap();
+
+        assert_eq!(metadata.idle_time, Duration::seconds(100));
+        assert_eq!(metadata.lfu_counter, 42);
+    }
+
+    #[test]
+    fn used_memory_follows_the_writes() {
+        let (store, clock) = new_store();
+        let exp = clock.now() + Duration::seconds(10);
+
+        store.set("key", string_value("value")).unwrap();
+        assert_eq!(
+            store.used_memory(),
+            entry_memory_usage("key", &string_value("value"))
+        );
+
+        store.set("key", string_value("longer value")).unwrap();
+        assert_eq!(
+            store.used_memory(),
+            entry_memory_usage("key", &string_value("longer value"))
+        );
+
+        assert!(store.set_expiration("key", Some(exp)));
+        assert_eq!(
+            store.used_memory(),
+            entry_memory_usage("key", &expiring_value("longer value", exp))
+        );
+
+        store.remove("key");
+        assert_eq!(store.used_memory(), 0);
+        assert_eq!(
+            store.peak_memory(),
+            entry_memory_usage("key", &expiring_value("longer value", exp))
+        );
+    }
+
+    #[test]
+    fn noeviction_rejects_the_writes_that_do_not_fit() {
+        let (store, _) = new_store();
+
+        store.set("a", string_value("1")).unwrap();
+        store.set_max_memory(
+            store.used_memory() + entry_memory_usage("b", &string_value("1")) - 1,
+            MaxMemoryPolicy::NoEviction,
+        );
+
+        assert!(matches!(
+            store.set("b", string_value("1")),
+            Err(StoreError::OutOfMemory)
+        ));
+        assert!(matches!(
+            store.mset(vec![
+                (String::from("b"), string_value("1")),
+                (String::from("c"), string_value("1")),
+            ]),
+            Err(StoreError::OutOfMemory)
+        ));
+        assert_eq!(store.len(), 1);
+
+        // Replacing a key only needs the memory that it does not use yet.
+        store.set("a", string_value("2")).unwrap();
+        assert_eq!(string(&store, "a").as_deref(), Some("2"));
+        assert_eq!(store.evicted_keys(), 0);
+    }
+
+    #[test]
+    fn allkeys_lru_evicts_the_least_recently_used_key() {
+        let (store, _) = new_store();
+        let keys = keys_in_different_shards(&store, 4);
+
+        for (key, idle_time) in keys[..3].iter().zip([10, 100, 20]) {
+            store.set(key, string_value("value")).unwrap();
+            store.lock_keys(&[key]).set_access_metadata(
+                key,
+                Some(Duration::seconds(idle_time)),
+                None,
+            );
+        }
+
+        store.set_max_memory(store.used_memory(), MaxMemoryPolicy::AllKeysLru);
+        store.set(&keys[3], string_value("value")).unwrap();
+
+        assert!(store.peek(&keys[1]).is_none());
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.evicted_keys(), 1);
+    }
+
+    #[test]
+    fn allkeys_lfu_evicts_the_least_frequently_used_key() {
+        let (store, _) = new_store();
+        let keys = keys_in_different_shards(&store, 4);
+
+        for (key, lfu_counter) in keys[..3].iter().zip([20, 30, 1]) {
+            store.set(key, string_value("value")).unwrap();
+            store
+                .lock_keys(&[key])
+                .set_access_metadata(key, None, Some(lfu_counter));
+        }
+
+        store.set_max_memory(store.used_memory(), MaxMemoryPolicy::AllKeysLfu);
+        store.set(&keys[3], string_value("value")).unwrap();
+
+        assert!(store.peek(&keys[2]).is_none());
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn allkeys_random_never_evicts_the_written_key() {
+        let (store, _) = new_store();
+        let keys = keys_in_different_shards(&store, 3);
+
+        for key in &keys[..2] {
+            store.set(key, string_value("value")).unwrap();
+        }
+
+        store.set_max_memory(store.used_memory(), MaxMemoryPolicy::AllKeysRandom);
+        store.set(&keys[2], string_value("value")).unwrap();
+        // It does not fit next to any other key, but it is the one being written.
+        store.set(&keys[2], string_value("longer value")).unwrap();
+
+        assert_eq!(store.get_all_keys(), vec![keys[2].clone()]);
+        assert_eq!(store.evicted_keys(), 2);
+    }
+
+    #[test]
+    fn volatile_ttl_evicts_the_key_that_expires_first() {
+        let (store, clock) = new_store();
+        let keys = keys_in_different_shards(&store, 4);
+
+        store.set(&keys[0], string_value("value")).unwrap();
+        store
+            .set(
+                &keys[1],
+                expiring_value("value", clock.now() + Duration::seconds(100)),
+            )
+            .unwrap();
+        store
+            .set(
+                &keys[2],
+                expiring_value("value", clock.now() + Duration::seconds(10)),
+            )
+            .unwrap();
+
+        store.set_max_memory(store.used_memory(), MaxMemoryPolicy::VolatileTtl);
+        store.set(&keys[3], string_value("value")).unwrap();
+
+        assert!(store.peek(&keys[2]).is_none());
+        assert_eq!(store.len(), 3);
+    }
+
+    #[test]
+    fn volatile_policies_only_evict_keys_with_an_expiration() {
+        let (store, clock) = new_store();
+        let keys = keys_in_different_shards(&store, 4);
+
+        store.set(&keys[0], string_value("value")).unwrap();
+        store
+            .set(
+                &keys[1],
+                expiring_value("value", clock.now() + Duration::seconds(100)),
+            )
+            .unwrap();
+
+        store.set_max_memory(store.used_memory(), MaxMemoryPolicy::VolatileLru);
+        store.set(&keys[2], string_value("value")).unwrap();
+
+        assert!(store.peek(&keys[1]).is_none());
+        assert!(matches!(
+            store.set(&keys[3], string_value("value")),
+            Err(StoreError::OutOfMemory)
+        ));
+        assert!(store.peek(&keys[0]).is_some());
+        assert!(store.peek(&keys[2]).is_some());
+    }
+
+    #[test]
+    fn keys_are_spread_over_the_shards() {
+        let store = Store::new(4, Arc::new(SystemClock::default()));
+        let mut keys: Vec<String> = (0..100).map(|index| format!("key:{}", index)).collect();
+
+        store
+            .mset(
+                keys.iter()
+                    .map(|key| (key.clone(), string_value("value")))
+                    .collect(),
+            )
+            .unwrap();
+
+        assert!(store.shards.iter().all(|shard| lock(shard).len() > 0));
+        assert_eq!(store.len(), 100);
+
+        let mut all_keys = store.get_all_keys();
+
+        keys.sort();
+        all_keys.sort();
+        assert_eq!(all_keys, keys);
+    }
+
+    #[test]
+    fn lock_keys_locks_every_shard_once() {
+        let store = Store::new(2, Arc::new(SystemClock::default()));
+        let same_shard = (1..)
+            .map(|index| format!("key:{}", index))
+            .find(|key| store.shard_index(key) == store.shard_index("key:0"))
+            .unwrap();
+        let mut guard = store.lock_keys(&["key:0", &same_shard, "key:0"]);
+
+        guard.set("key:0", string_value("a"));
+        guard.set(&same_shard, string_value("b"));
+        drop(guard);
+
+        assert_eq!(string(&store, "key:0").as_deref(), Some("a"));
+        assert_eq!(string(&store, &same_shard).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn rename_moves_the_key_between_shards() {
+        let (store, clock) = new_store();
+        let keys = keys_in_different_shards(&store, 2);
+        let exp = clock.now() + Duration::seconds(10);
+
+        store.set(&keys[0], expiring_value("value", exp)).unwrap();
+
+        assert!(store.rename(&keys[0], &keys[1]));
+        assert!(store.peek(&keys[0]).is_none());
+        assert_eq!(store.peek(&keys[1]).unwrap().exp, Some(exp));
+        assert!(!store.rename(&keys[0], &keys[1]));
+    }
+
+    #[test]
+    fn keys_locked_in_any_order_do_not_deadlock() {
+        let store = Arc::new(Store::default());
+        let keys = keys_in_different_shards(&store, 2);
+
+        store.set(&keys[0], string_value("value")).unwrap();
+
+        let threads: Vec<_> = [(0, 1), (1, 0)]
+            .into_iter()
+            .map(|(from, to)| {
+                let store = store.clone();
+                let (from, to) = (keys[from].clone(), keys[to].clone());
+
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        store.rename(&from, &to);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // Every rename moves the only key, so it is never lost nor duplicated.
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn remove_expired_goes_through_the_keys_that_expire_first() {
+        let clock = Arc::new(ManualClock::new(
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        ));
+        let store = Store::new(1, clock.clone());
+
+        for seconds in [30, 10, 20, 40] {
+            store
+                .set(
+                    &format!("key:{}", seconds),
+                    expiring_value("value", clock.now() + Duration::seconds(seconds)),
+                )
+                .unwrap();
+        }
+        store.set("persistent", string_value("value")).unwrap();
+        clock.advance(Duration::seconds(35));
+
+        assert_eq!(store.remove_expired(2), 2);
+        assert!(store.peek("key:10").is_none());
+        assert!(store.peek("key:20").is_none());
+
+        // The next call continues where the previous one stopped, and keys that have not expired are kept.
+        assert_eq!(store.remove_expired(2), 1);
+        assert_eq!(store.remove_expired(2), 0);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.expired_keys(), 3);
+    }
+
+    #[test]
+    fn the_expiration_index_follows_expiration_changes() {
+        let (store, clock) = new_store();
+        let exp = clock.now() + Duration::seconds(10);
+
+        store
+            .set("persisted", expiring_value("value", exp))
+            .unwrap();
+        store.set("extended", expiring_value("value", exp)).unwrap();
+        store.set("expiring", string_value("value")).unwrap();
+        store
+            .set("overwritten", expiring_value("value", exp))
+            .unwrap();
+
+        assert!(store.persist("persisted"));
+        assert!(!store.persist("persisted"));
+        assert!(store.set_expiration("extended", Some(exp + Duration::seconds(10))));
+        assert!(store.set_expiration("expiring", Some(exp)));
+        store.set("overwritten", string_value("value")).unwrap();
+        clock.advance(Duration::seconds(15));
+
+        assert_eq!(store.remove_expired(10), 1);
+        assert!(store.peek("expiring").is_none());
+        assert_eq!(store.snapshot().expires_len(), 1);
+    }
+
+    #[test]
+    fn remove_expired_does_nothing_while_active_expire_is_disabled() {
+        let (store, clock) = new_store();
+
+        store
+            .set(
+                "key",
+                expiring_value("value", clock.now() + Duration::seconds(10)),
+            )
+            .unwrap();
+        clock.advance(Duration::seconds(20));
+        store.set_active_expire(false);
+
+        assert_eq!(store.remove_expired(10), 0);
+        assert_eq!(store.len(), 1);
+
+        store.set_active_expire(true);
+
+        assert_eq!(store.remove_expired(10), 1);
+        assert!(store.is_empty());
+    }
+
+    // The keys of a snapshot with their string values, sorted.
+    fn snapshot_strings(snapshot: &StoreSnapshot) -> Vec<(String, String)> {
+        let mut values: Vec<(String, String)> = snapshot
+            .iter()
+            .filter_map(|(key, value)| match &value.value {
+                StoreData::String(value) => Some((key.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+
+        values.sort();
+
+        values
+    }
+
+    #[test]
+    fn snapshots_do_not_see_later_writes() {
+        let (store, clock) = new_store();
+
+        store.set("a", string_value("1")).unwrap();
+        store.set("b", string_value("1")).unwrap();
+        store
+            .set(
+                "expired",
+                expiring_value("1", clock.now() - Duration::seconds(1)),
+            )
+            .unwrap();
+
+        let snapshot = store.snapshot();
+
+        store.set("a", string_value("2")).unwrap();
+        store.remove("b");
+        store.set("c", string_value("2")).unwrap();
+
+        assert_eq!(
+            snapshot_strings(&snapshot),
+            vec![
+                (String::from("a"), String::from("1")),
+                (String::from("b"), String::from("1")),
+            ]
+        );
+        assert_eq!(string(&store, "a").as_deref(), Some("2"));
+        assert_eq!(store.len(), 3);
+
+        store.replace_all(vec![(String::from("d"), string_value("3"))]);
+
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(store.get_all_keys(), vec![String::from("d")]);
+    }
+
+    #[test]
+    fn snapshot_memory_counts_the_copied_entries() {
+        let clock = Arc::new(ManualClock::new(
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        ));
+        let store = Store::new(1, clock);
+
+        store.set("a", string_value("1")).unwrap();
+
+        let size = store.used_memory();
+        let snapshot = store.snapshot();
+
+        // Reads do not copy the entries.
+        store.get("a");
+        assert_eq!(store.snapshot_memory(), 0);
+
+        store.set("b", string_value("1")).unwrap();
+        assert_eq!(store.snapshot_memory(), size);
+
+        // Once copied, the shard is not copied again.
+        store.set("c", string_value("1")).unwrap();
+        assert_eq!(store.snapshot_memory(), size);
+
+        drop(snapshot);
+        assert_eq!(store.snapshot_memory(), 0);
+
+        let snapshot = store.snapshot();
+
+        store.replace_all(vec![]);
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(store.snapshot_memory(), 3 * size);
+        assert_eq!(store.used_memory(), 0);
+
+        drop(snapshot);
+        assert_eq!(store.snapshot_memory(), 0);
+    }
+}
@@ -0,0 +1,557 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+
+use super::{
+    MaxMemoryPolicy, StoreCounters, StoreKeyMetadata, StoreValue, ENTRY_OVERHEAD,
+    MAX_MEMORY_SAMPLES,
+};
+
+/// The LFU counter of a new key. It is higher than zero so new keys are not evicted right away.
+const LFU_INIT_VAL: u8 = 5;
+/// The higher the factor, the more accesses are needed to increment the LFU counter.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Number of minutes that need to pass without accesses in order to decrement the LFU counter by one.
+const LFU_DECAY_TIME_IN_MINUTES: i64 = 1;
+
+// Everything the store needs to know about a key besides its value.
+//
+// The slots are the positions of the key inside the shard key vectors, which are used for sampling random keys
+// in constant time.
+#[derive(Debug, Clone)]
+struct StoreEntry {
+    value: StoreValue,
+    size: usize,
+    slot: usize,
+    volatile_slot: Option<usize>,
+    last_access: DateTime<Utc>,
+    lfu_counter: u8,
+    lfu_decremented_at: DateTime<Utc>,
+}
+
+impl StoreEntry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.value.exp.is_some_and(|exp| exp < now)
+    }
+
+    // The LFU counter is decremented lazily, based on the time that has passed since the last decrement.
+    fn lfu_decayed_counter(&self, now: DateTime<Utc>) -> u8 {
+        let periods = (now - self.lfu_decremented_at).num_minutes() / LFU_DECAY_TIME_IN_MINUTES;
+        let periods = u8::try_from(periods.max(0)).unwrap_or(u8::MAX);
+
+        self.lfu_counter.saturating_sub(periods)
+    }
+
+    // Updates the access information of the entry. The LFU counter is a logarithmic counter, so the more accesses
+    // the key has, the less likely it is to be incremented.
+    fn touch(&mut self, now: DateTime<Utc>, random: f64) {
+        let counter = self.lfu_decayed_counter(now);
+        let base = f64::from(counter.saturating_sub(LFU_INIT_VAL));
+        let probability = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+
+        self.lfu_counter = if counter < u8::MAX && random < probability {
+            counter + 1
+        } else {
+            counter
+        };
+        self.lfu_decremented_at = now;
+        self.last_access = now;
+    }
+}
+
+// A xorshift pseudo random number generator. The store only needs random numbers for sampling keys,
+// so it does not need to be cryptographically secure.
+#[derive(Debug)]
+struct StoreRng(u64);
+
+impl StoreRng {
+    fn new() -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+
+        Self(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        self.0
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_index(&mut self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
+
+/// The entries of a shard. They are shared with the snapshots taken while they did not change, and copied by the
+/// shard on the first write after a snapshot (copy-on-write), so snapshots never see later changes.
+#[derive(Debug)]
+pub(super) struct ShardData {
+    entries: HashMap<String, StoreEntry>,
+    counters: Arc<StoreCounters>,
+    // Memory used by the entries since the shard copied them, which means that only snapshots are using them.
+    // It is zero while the shard is still using them.
+    snapshot_size: AtomicUsize,
+}
+
+impl ShardData {
+    fn new(counters: Arc<StoreCounters>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            counters,
+            snapshot_size: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn entries(&self, now: DateTime<Utc>) -> impl Iterator<Item = (&String, &StoreValue)> {
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key, &entry.value))
+    }
+
+    // Returns the entries for writing them, copying them first when a snapshot is using them. From then on, the
+    // previous entries only belong to the snapshots, so their memory is counted as snapshot memory until the last
+    // snapshot is dropped.
+    fn make_mut(data: &mut Arc<ShardData>) -> &mut HashMap<String, StoreEntry> {
+        if Arc::get_mut(data).is_none() {
+            let size = data.entries.values().map(|entry| entry.size).sum();
+            let copy = ShardData {
+                entries: data.entries.clone(),
+                counters: data.counters.clone(),
+                snapshot_size: AtomicUsize::new(0),
+            };
+
+            data.snapshot_size.store(size, Ordering::Relaxed);
+            data.counters
+                .snapshot_memory
+                .fetch_add(size, Ordering::Relaxed);
+            *data = Arc::new(copy);
+        }
+
+        &mut Arc::get_mut(data)
+            .expect("The shard entries were just copied")
+            .entries
+    }
+}
+
+impl Drop for ShardData {
+    fn drop(&mut self) {
+        let size = *self.snapshot_size.get_mut();
+
+        self.counters
+            .snapshot_memory
+            .fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+/// A key that could be evicted. The lower the score, the better the candidate.
+#[derive(Debug)]
+pub(super) struct EvictionCandidate {
+    pub key: String,
+    pub score: i64,
+}
+
+/// A subset of the keyspace with its own lock. Memory counters are shared between all the shards of a store.
+///
+/// Keys with an expiration are also kept in an index ordered by expiration time, so the keys that expire first can
+/// be found without going through the whole shard.
+#[derive(Debug)]
+pub(super) struct StoreShard {
+    data: Arc<ShardData>,
+    keys: Vec<String>,
+    volatile_keys: Vec<String>,
+    expirations: BTreeSet<(DateTime<Utc>, String)>,
+    rng: StoreRng,
+    counters: Arc<StoreCounters>,
+}
+
+impl StoreShard {
+    pub fn new(counters: Arc<StoreCounters>) -> Self {
+        Self {
+            data: Arc::new(ShardData::new(counters.clone())),
+            keys: Vec::new(),
+            volatile_keys: Vec::new(),
+            expirations: BTreeSet::new(),
+            rng: StoreRng::new(),
+            counters,
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: StoreValue, now: DateTime<Utc>) {
+        let size = entry_size(key, &value);
+        let exp = value.exp;
+
+        let previous_exp = match ShardData::make_mut(&mut self.data).get_mut(key) {
+            Some(entry) => {
+                let previous_exp = entry.value.exp;
+
+                self.counters.sub_used_memory(entry.size);
+                self.counters.add_used_memory(size);
+
+                entry.value = value;
+                entry.size = size;
+                entry.touch(now, self.rng.next_f64());
+
+                previous_exp
+            }
+            None => {
+                self.keys.push(key.to_string());
+                self.counters.add_used_memory(size);
+                ShardData::make_mut(&mut self.data).insert(
+                    key.to_string(),
+                    StoreEntry {
+                        value,
+                        size,
+                        slot: self.keys.len() - 1,
+                        volatile_slot: None,
+                        last_access: now,
+                        lfu_counter: LFU_INIT_VAL,
+                        lfu_decremented_at: now,
+                    },
+                );
+
+                None
+            }
+        };
+
+        self.update_expiration_index(key, previous_exp, exp);
+        self.counters.add_dirty();
+    }
+
+    // Changes the expiration of a key, returning the previous one. It returns None when the key does not exist.
+    pub fn set_expiration(
+        &mut self,
+        key: &str,
+        exp: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<Option<DateTime<Utc>>> {
+        if self.expire_if_needed(key, now) {
+            return None;
+        }
+
+        let entry = ShardData::make_mut(&mut self.data).get_mut(key)?;
+        let previous_exp = entry.value.exp;
+
+        entry.value.exp = exp;
+
+        let size = entry_size(key, &entry.value);
+
+        self.counters.sub_used_memory(entry.size);
+        self.counters.add_used_memory(size);
+        entry.size = size;
+
+        self.update_expiration_index(key, previous_exp, exp);
+        self.counters.add_dirty();
+
+        Some(previous_exp)
+    }
+
+    pub fn get(&mut self, key: &str, now: DateTime<Utc>) -> Option<&StoreValue> {
+        if self.expire_if_needed(key, now) {
+            return None;
+        }
+
+        // Reads also update the access information, but that is not worth copying the whole shard while a snapshot
+        // is using it, so the reads are not tracked until the snapshot is dropped.
+        if Arc::get_mut(&mut self.data).is_none() {
+            return self.data.entries.get(key).map(|entry| &entry.value);
+        }
+
+        let random = self.rng.next_f64();
+        let entry = ShardData::make_mut(&mut self.data).get_mut(key)?;
+
+        entry.touch(now, random);
+
+        Some(&entry.value)
+    }
+
+    pub fn peek(&self, key: &str, now: DateTime<Utc>) -> Option<&StoreValue> {
+        self.data
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| &entry.value)
+    }
+
+    pub fn get_metadata(&self, key: &str, now: DateTime<Utc>) -> Option<StoreKeyMetadata> {
+        let entry = self
+            .data
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now))?;
+
+        Some(StoreKeyMetadata {
+            idle_time: now - entry.last_access,
+            lfu_counter: entry.lfu_decayed_counter(now),
+            memory_usage: entry.size,
+        })
+    }
+
+    // Replaces the access information of a key. It returns false when the key does not exist.
+    pub fn set_access_metadata(
+        &mut self,
+        key: &str,
+        idle_time: Option<Duration>,
+        lfu_counter: Option<u8>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if self.expire_if_needed(key, now) {
+            return false;
+        }
+
+        let entry = match ShardData::make_mut(&mut self.data).get_mut(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        if let Some(idle_time) = idle_time {
+            entry.last_access = now - idle_time;
+        }
+
+        if let Some(lfu_counter) = lfu_counter {
+            entry.lfu_counter = lfu_counter;
+            entry.lfu_decremented_at = now;
+        }
+
+        true
+    }
+
+    pub fn entry_size(&self, key: &str) -> Option<usize> {
+        self.data.entries.get(key).map(|entry| entry.size)
+    }
+
+    pub fn remove(&mut self, key: &str, now: DateTime<Utc>) -> Option<StoreValue> {
+        if self.expire_if_needed(key, now) {
+            return None;
+        }
+
+        self.remove_entry(key)
+    }
+
+    // Removes up to `limit` keys whose expiration time has already passed, returning how many were removed.
+    pub fn remove_expired(&mut self, now: DateTime<Utc>, limit: usize) -> usize {
+        let mut removed = 0;
+
+        while removed < limit {
+            let key = match self.expirations.first() {
+                Some((exp, key)) if *exp < now => key.clone(),
+                _ => break,
+            };
+
+            self.remove_entry(&key);
+            self.counters.expired_keys.fetch_add(1, Ordering::Relaxed);
+            removed += 1;
+        }
+
+        removed
+    }
+
+    pub fn keys(&self, now: DateTime<Utc>) -> impl Iterator<Item = &String> {
+        self.data
+            .entries
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key)
+    }
+
+    /// Removes all the keys. The entries are not copied when a snapshot is using them: the snapshot keeps them, and
+    /// their memory is counted as snapshot memory until it is dropped.
+    pub fn clear(&mut self) {
+        let size = self.data.entries.values().map(|entry| entry.size).sum();
+        let removed = self.data.entries.len() as u64;
+
+        if Arc::strong_count(&self.data) > 1 {
+            self.data.snapshot_size.store(size, Ordering::Relaxed);
+            self.counters
+                .snapshot_memory
+                .fetch_add(size, Ordering::Relaxed);
+        }
+
+        self.counters.sub_used_memory(size);
+        self.counters.dirty.fetch_add(removed, Ordering::Relaxed);
+        self.data = Arc::new(ShardData::new(self.counters.clone()));
+        self.keys.clear();
+        self.volatile_keys.clear();
+        self.expirations.clear();
+    }
+
+    /// Shares the current entries with a snapshot.
+    pub fn snapshot(&self) -> Arc<ShardData> {
+        self.data.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.entries.len()
+    }
+
+    // Picks a random key of the shard and scores it according to the max memory policy. Keys that can not be evicted
+    // are skipped, trying up to `MAX_MEMORY_SAMPLES` keys, so a shard with other keys is not left out.
+    //
+    // For the volatile-ttl policy, there is no need to pick a random key: the expiration index already knows which key
+    // expires first.
+    pub fn sample_eviction_candidate(
+        &mut self,
+        policy: MaxMemoryPolicy,
+        excluded_keys: &[&str],
+        now: DateTime<Utc>,
+    ) -> Option<EvictionCandidate> {
+        if policy == MaxMemoryPolicy::VolatileTtl {
+            return self
+                .expirations
+                .iter()
+                .find(|(_, key)| !excluded_keys.contains(&key.as_str()))
+                .map(|(exp, key)| EvictionCandidate {
+                    key: key.clone(),
+                    score: exp.timestamp_millis(),
+                });
+        }
+
+        let pool = if policy.is_volatile() {
+            &self.volatile_keys
+        } else {
+            &self.keys
+        };
+
+        if pool.is_empty() {
+            return None;
+        }
+
+        let (key, entry) = (0..MAX_MEMORY_SAMPLES).find_map(|_| {
+            let key = &pool[self.rng.next_index(pool.len())];
+
+            if excluded_keys.contains(&key.as_str()) {
+                return None;
+            }
+
+            self.data.entries.get(key).map(|entry| (key, entry))
+        })?;
+
+        let score = match policy {
+            MaxMemoryPolicy::AllKeysLru | MaxMemoryPolicy::VolatileLru => {
+                entry.last_access.timestamp_millis()
+            }
+            MaxMemoryPolicy::AllKeysLfu | MaxMemoryPolicy::VolatileLfu => {
+                i64::from(entry.lfu_decayed_counter(now))
+            }
+            MaxMemoryPolicy::AllKeysRandom | MaxMemoryPolicy::VolatileRandom => {
+                (self.rng.next_u64() >> 1) as i64
+            }
+            MaxMemoryPolicy::VolatileTtl | MaxMemoryPolicy::NoEviction => return None,
+        };
+
+        Some(EvictionCandidate {
+            key: key.clone(),
+            score,
+        })
+    }
+
+    // Keys are expired lazily when they are accessed, besides the active expiration that runs in the background.
+    // It returns true when the key was expired.
+    fn expire_if_needed(&mut self, key: &str, now: DateTime<Utc>) -> bool {
+        let is_expired = self
+            .data
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(now));
+
+        if is_expired {
+            self.remove_entry(key);
+            self.counters.expired_keys.fetch_add(1, Ordering::Relaxed);
+        }
+
+        is_expired
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<StoreValue> {
+        let entry = ShardData::make_mut(&mut self.data).remove(key)?;
+
+        self.counters.sub_used_memory(entry.size);
+        self.counters.add_dirty();
+        self.remove_slot(entry.slot);
+
+        if let Some(volatile_slot) = entry.volatile_slot {
+            self.remove_volatile_slot(volatile_slot);
+        }
+
+        if let Some(exp) = entry.value.exp {
+            self.expirations.remove(&(exp, key.to_string()));
+        }
+
+        Some(entry.value)
+    }
+
+    // Keeps the expiration index and the volatile key vector in sync with the expiration of a key.
+    fn update_expiration_index(
+        &mut self,
+        key: &str,
+        previous_exp: Option<DateTime<Utc>>,
+        exp: Option<DateTime<Utc>>,
+    ) {
+        if previous_exp == exp {
+            return;
+        }
+
+        if let Some(previous_exp) = previous_exp {
+            self.expirations.remove(&(previous_exp, key.to_string()));
+        }
+
+        if let Some(exp) = exp {
+            self.expirations.insert((exp, key.to_string()));
+        }
+
+        match (previous_exp, exp) {
+            (None, Some(_)) => {
+                if let Some(entry) = ShardData::make_mut(&mut self.data).get_mut(key) {
+                    entry.volatile_slot = Some(self.volatile_keys.len());
+                    self.volatile_keys.push(key.to_string());
+                }
+            }
+            (Some(_), None) => {
+                let volatile_slot = ShardData::make_mut(&mut self.data)
+                    .get_mut(key)
+                    .and_then(|entry| entry.volatile_slot.take());
+
+                if let Some(volatile_slot) = volatile_slot {
+                    self.remove_volatile_slot(volatile_slot);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Removes a key from the key vector, moving the last key into its slot.
+    fn remove_slot(&mut self, slot: usize) {
+        self.keys.swap_remove(slot);
+
+        if let Some(moved_key) = self.keys.get(slot) {
+            if let Some(entry) = ShardData::make_mut(&mut self.data).get_mut(moved_key) {
+                entry.slot = slot;
+            }
+        }
+    }
+
+    // Removes a key from the volatile key vector, moving the last key into its slot.
+    fn remove_volatile_slot(&mut self, slot: usize) {
+        self.volatile_keys.swap_remove(slot);
+
+        if let Some(moved_key) = self.volatile_keys.get(slot) {
+            if let Some(entry) = ShardData::make_mut(&mut self.data).get_mut(moved_key) {
+                entry.volatile_slot = Some(slot);
+            }
+        }
+    }
+}
+
+pub(super) fn entry_size(key: &str, value: &StoreValue) -> usize {
+    key.len() + value.memory_usage() + ENTRY_OVERHEAD
+}
@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// Strings up to this length are allocated together with their object header (embstr encoding).
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// Collections with up to this number of elements use a compact encoding (listpack or intset), as long as none of
+/// their elements is longer than `MAX_LISTPACK_VALUE`. These are the Redis default thresholds.
+const MAX_LISTPACK_ENTRIES: usize = 128;
+const MAX_LISTPACK_VALUE: usize = 64;
+const MAX_INTSET_ENTRIES: usize = 512;
+
+/// Approximate amount of memory needed by every element of a collection besides its content.
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// The data of a key. Commands only write strings at the moment, the rest of types are loaded from RDB files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreData {
+    String(String),
+    List(VecDeque<String>),
+    Set(HashSet<String>),
+    Hash(HashMap<String, String>),
+    /// Members with their scores, ordered by score and then by member.
+    SortedSet(Vec<(String, f64)>),
+    Stream(StoreStream),
+    Module(StoreModuleValue),
+}
+
+impl Default for StoreData {
+    fn default() -> Self {
+        StoreData::String(String::new())
+    }
+}
+
+impl StoreData {
+    /// The name of the type, as returned by the TYPE command.
+    pub fn type_name(&self) -> &str {
+        match self {
+            StoreData::String(_) => "string",
+            StoreData::List(_) => "list",
+            StoreData::Set(_) => "set",
+            StoreData::Hash(_) => "hash",
+            StoreData::SortedSet(_) => "zset",
+            StoreData::Stream(_) => "stream",
+            StoreData::Module(module) => &module.name,
+        }
+    }
+
+    /// Approximate number of bytes used by the data.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            StoreData::String(value) => value.len(),
+            StoreData::List(values) => values
+                .iter()
+                .map(|value| value.len() + ELEMENT_OVERHEAD)
+                .sum(),
+            StoreData::Set(members) => members
+                .iter()
+                .map(|member| member.len() + ELEMENT_OVERHEAD)
+                .sum(),
+            StoreData::Hash(fields) => fields
+                .iter()
+                .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD)
+                .sum(),
+            StoreData::SortedSet(members) => members
+                .iter()
+                .map(|(member, _)| member.len() + std::mem::size_of::<f64>() + ELEMENT_OVERHEAD)
+                .sum(),
+            StoreData::Stream(stream) => stream.memory_usage(),
+            StoreData::Module(module) => module.memory_usage(),
+        }
+    }
+
+    /// The internal representation that Redis would use for the data.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            StoreData::String(value) => {
+                if value.len() <= 20 && value.parse::<i64>().is_ok() {
+                    "int"
+                } else if value.len() <= EMBSTR_SIZE_LIMIT {
+                    "embstr"
+                } else {
+                    "raw"
+                }
+            }
+            StoreData::List(values) => {
+                if is_listpack(values.len(), values.iter()) {
+                    "listpack"
+                } else {
+                    "quicklist"
+                }
+            }
+            StoreData::Set(members) => {
+                if members.len() <= MAX_INTSET_ENTRIES
+                    && members.iter().all(|member| member.parse::<i64>().is_ok())
+                {
+                    "intset"
+                } else if is_listpack(members.len(), members.iter()) {
+                    "listpack"
+                } else {
+                    "hashtable"
+                }
+            }
+            StoreData::Hash(fields) => {
+                if is_listpack(fields.len(), fields.keys().chain(fields.values())) {
+                    "listpack"
+                } else {
+                    "hashtable"
+                }
+            }
+            StoreData::SortedSet(members) => {
+                if is_listpack(members.len(), members.iter().map(|(member, _)| member)) {
+                    "listpack"
+                } else {
+                    "skiplist"
+                }
+            }
+            StoreData::Stream(_) => "stream",
+            StoreData::Module(_) => "raw",
+        }
+    }
+}
+
+fn is_listpack<'a>(len: usize, mut values: impl Iterator<Item = &'a String>) -> bool {
+    len <= MAX_LISTPACK_ENTRIES && values.all(|value| value.len() <= MAX_LISTPACK_VALUE)
+}
+
+/// The ID of a stream entry: the creation time in milliseconds and a sequence number for entries created in the
+/// same millisecond.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl std::fmt::Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoreStream {
+    /// The entries, with their fields and values in insertion order.
+    pub entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    pub last_id: StreamId,
+    pub first_id: StreamId,
+    pub max_deleted_id: StreamId,
+    /// Number of entries ever added to the stream, including the deleted ones.
+    pub entries_added: u64,
+    pub groups: Vec<StoreStreamGroup>,
+}
+
+impl StoreStream {
+    fn memory_usage(&self) -> usize {
+        let entries: usize = self
+            .entries
+            .values()
+            .flat_map(|fields| fields.iter())
+            .map(|(field, value)| field.len() + value.len() + ELEMENT_OVERHEAD)
+            .sum();
+        let pending: usize = self
+            .groups
+            .iter()
+            .map(|group| {
+                group.name.len()
+                    + group.pending.len() * std::mem::size_of::<StoreStreamPendingEntry>()
+            })
+            .sum();
+
+        entries + pending
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoreStreamGroup {
+    pub name: String,
+    pub last_id: StreamId,
+    /// Number of entries read by the group. -1 means that it is unknown.
+    pub entries_read: i64,
+    /// Entries delivered to a consumer that were not acknowledged yet.
+    pub pending: Vec<StoreStreamPendingEntry>,
+    pub consumers: Vec<StoreStreamConsumer>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoreStreamPendingEntry {
+    pub id: StreamId,
+    /// Unix time (in milliseconds) of the last delivery.
+    pub delivery_time: i64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StoreStreamConsumer {
+    pub name: String,
+    /// Unix time (in milliseconds) of the last interaction of the consumer.
+    pub seen_time: i64,
+    /// Unix time (in milliseconds) of the last successful interaction of the consumer.
+    pub active_time: i64,
+    /// IDs of the pending entries of the group delivered to this consumer.
+    pub pending: Vec<StreamId>,
+}
+
+/// A value of a module type. The server does not load modules, so the value is kept as the sequence of fields
+/// saved by the module, which is enough for writing it back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreModuleValue {
+    /// The 64-bit module type ID: the 9 characters of the type name and a 10-bit encoding version.
+    pub id: u64,
+    pub name: String,
+    pub fields: Vec<StoreModuleField>,
+}
+
+impl StoreModuleValue {
+    fn memory_usage(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| match field {
+                StoreModuleField::String(value) => value.len(),
+                _ => std::mem::size_of::<u64>(),
+            })
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreModuleField {
+    SignedInt(i64),
+    UnsignedInt(u64),
+    Float(f32),
+    Double(f64),
+    String(Vec<u8>),
+}
@@ -12,14 +12,12 @@ impl<'a> TcpStreamReader<'a> {
         TcpStreamReader { stream }
     }
 
-    pub async fn read(&mut self) -> anyhow::Result<String> {
+    /// Reads the bytes that are available, waiting until there is at least one. It returns no bytes when the
+    /// connection is closed.
+    pub async fn read(&mut self) -> anyhow::Result<Vec<u8>> {
         let mut reader = BufReader::new(&mut self.stream);
-        let buf = reader
-            .fill_buf()
-            .await
-            .expect("An error ocurred while reading from stream")
-            .to_vec();
+        let buf = reader.fill_buf().await?.to_vec();
 
-        Ok(String::from_utf8_lossy(&buf).to_string())
+        Ok(buf)
     }
 }